@@ -0,0 +1,579 @@
+use crate::arb::{find_optimal_reference_trade, find_two_pool_arb, Route};
+use crate::error::PoolError;
+use crate::events::{PoolEvent, PoolId};
+use crate::journal::{Journal, JournalRecord};
+use crate::ledger::Account;
+use crate::market::{Action, Market, MarketPool};
+use crate::price::{PriceProcess, SECONDS_PER_YEAR};
+use crate::sim::{Clock, Simulation};
+use crate::token::TokenId;
+use rand::{Rng, RngCore};
+use serde::Serialize;
+use std::sync::mpsc::Receiver;
+
+/// What an agent can see when it is asked to act.
+pub struct AgentContext<'a> {
+    pub clock: Clock,
+    pub market: &'a Market,
+    pub account: &'a Account,
+    pub rng: &'a mut dyn RngCore,
+}
+
+/// A simulated market participant. Agents never touch pools directly: they
+/// return actions, which the [`MarketSim`] carries out for their account in
+/// the order given, stopping at the first one that fails.
+pub trait Agent {
+    fn name(&self) -> &str;
+
+    /// Called once per block, after `on_event` has seen the pool changes of the previous block.
+    fn on_block(&mut self, ctx: &mut AgentContext) -> Vec<Action>;
+
+    fn on_event(&mut self, _event: &PoolEvent, _ctx: &mut AgentContext) -> Vec<Action> {
+        Vec::new()
+    }
+
+    /// Reports how each returned action went.
+    fn on_result(&mut self, _action: &Action, _result: &Result<(), PoolError>) {}
+}
+
+/// Swaps a random share of its balance in a random direction, with
+/// probability `probability` each block.
+pub struct NoiseTrader {
+    pool: PoolId,
+    tokens: (TokenId, TokenId),
+    probability: f64,
+    max_fraction: f64,
+}
+
+impl NoiseTrader {
+    pub fn new(pool: PoolId, tokens: (TokenId, TokenId), probability: f64, max_fraction: f64) -> NoiseTrader {
+        NoiseTrader { pool, tokens, probability, max_fraction }
+    }
+}
+
+impl Agent for NoiseTrader {
+    fn name(&self) -> &str {
+        "noise trader"
+    }
+
+    fn on_block(&mut self, ctx: &mut AgentContext) -> Vec<Action> {
+        if !ctx.rng.gen_bool(self.probability) {
+            return Vec::new();
+        }
+        let (token_in, token_out) = if ctx.rng.gen_bool(0.5) {
+            self.tokens
+        } else {
+            (self.tokens.1, self.tokens.0)
+        };
+        let amount_in = ctx.account.balance(token_in) * ctx.rng.gen_range(0.0..self.max_fraction);
+        if amount_in <= 0.0 {
+            return Vec::new();
+        }
+        vec![Action::Swap { pool: self.pool, token_in, token_out, amount_in, min_amount_out: 0.0 }]
+    }
+}
+
+/// Trades a pool towards the market's reference price whenever the pool's
+/// mid price strays from it by more than `threshold`, sized for the largest
+/// profit at the reference price.
+pub struct InformedTrader {
+    pool: PoolId,
+    base: TokenId,
+    quote: TokenId,
+    threshold: f64,
+}
+
+impl InformedTrader {
+    pub fn new(pool: PoolId, base: TokenId, quote: TokenId, threshold: f64) -> InformedTrader {
+        InformedTrader { pool, base, quote, threshold }
+    }
+}
+
+impl Agent for InformedTrader {
+    fn name(&self) -> &str {
+        "informed trader"
+    }
+
+    fn on_block(&mut self, ctx: &mut AgentContext) -> Vec<Action> {
+        let Some(reference) = ctx.market.reference_price(self.base, self.quote) else {
+            return Vec::new();
+        };
+        let Ok(pool) = ctx.market.amm(self.pool) else {
+            return Vec::new();
+        };
+        let Ok(mid) = pool.spot_price(self.base, self.quote) else {
+            return Vec::new();
+        };
+        // buy base where it is cheap, sell it where it is dear; [price] values the output in the input token
+        let (token_in, token_out, price) = if mid < reference * (1.0 - self.threshold) {
+            (self.quote, self.base, reference)
+        } else if mid > reference * (1.0 + self.threshold) {
+            (self.base, self.quote, 1.0 / reference)
+        } else {
+            return Vec::new();
+        };
+
+        let max_in = ctx.account.balance(token_in);
+        let amount_in = match find_optimal_reference_trade(pool, token_in, token_out, price, max_in) {
+            Ok(amount) if amount > 0.0 => amount,
+            _ => {
+                return Vec::new();
+            }
+        };
+        let min_amount_out = pool.quote_exact_in(token_in, token_out, amount_in).unwrap_or(0.0);
+        vec![Action::Swap { pool: self.pool, token_in, token_out, amount_in, min_amount_out }]
+    }
+}
+
+/// Provides liquidity once, on its first block, and then holds it.
+pub struct PassiveLp {
+    deposit: Option<Action>,
+}
+
+impl PassiveLp {
+    /// Takes the [`Action::Deposit`] or [`Action::Mint`] to make.
+    pub fn new(deposit: Action) -> PassiveLp {
+        PassiveLp { deposit: Some(deposit) }
+    }
+}
+
+impl Agent for PassiveLp {
+    fn name(&self) -> &str {
+        "passive LP"
+    }
+
+    fn on_block(&mut self, _ctx: &mut AgentContext) -> Vec<Action> {
+        self.deposit.take().into_iter().collect()
+    }
+}
+
+/// Keeps `liquidity` in a V3 range `half_width` ticks either side of the
+/// current tick, moving the whole position once the price leaves the range.
+pub struct RangeLp {
+    pool: PoolId,
+    half_width: i32,
+    liquidity: f64,
+    range: Option<(i32, i32)>,
+}
+
+impl RangeLp {
+    pub fn new(pool: PoolId, half_width: i32, liquidity: f64) -> RangeLp {
+        RangeLp { pool, half_width, liquidity, range: None }
+    }
+
+    pub fn range(&self) -> Option<(i32, i32)> {
+        self.range
+    }
+}
+
+impl Agent for RangeLp {
+    fn name(&self) -> &str {
+        "range LP"
+    }
+
+    fn on_block(&mut self, ctx: &mut AgentContext) -> Vec<Action> {
+        let Ok(MarketPool::V3(pool)) = ctx.market.pool(self.pool) else {
+            return Vec::new();
+        };
+        let tick = pool.tick();
+        let mut actions = Vec::new();
+        match self.range {
+            Some((lower, upper)) if lower <= tick && tick < upper => {
+                return actions;
+            }
+            Some((lower, upper)) => {
+                actions.push(Action::Mint {
+                    pool: self.pool,
+                    lower_tick: lower,
+                    upper_tick: upper,
+                    liquidity_delta: -self.liquidity,
+                });
+            }
+            None => {}
+        }
+        actions.push(Action::Mint {
+            pool: self.pool,
+            lower_tick: tick - self.half_width,
+            upper_tick: tick + self.half_width,
+            liquidity_delta: self.liquidity,
+        });
+        actions
+    }
+
+    fn on_result(&mut self, action: &Action, result: &Result<(), PoolError>) {
+        if let (Action::Mint { lower_tick, upper_tick, liquidity_delta, .. }, Ok(())) = (action, result) {
+            self.range = if *liquidity_delta > 0.0 { Some((*lower_tick, *upper_tick)) } else { None };
+        }
+    }
+}
+
+/// Takes the best round trip between two pools through [`find_two_pool_arb`]
+/// whenever either of them has changed.
+pub struct Arbitrageur {
+    first: PoolId,
+    second: PoolId,
+    token_in: TokenId,
+    token_mid: TokenId,
+    max_amount_in: f64,
+    stale: bool,
+}
+
+impl Arbitrageur {
+    pub fn new(first: PoolId, second: PoolId, token_in: TokenId, token_mid: TokenId, max_amount_in: f64) -> Arbitrageur {
+        Arbitrageur { first, second, token_in, token_mid, max_amount_in, stale: true }
+    }
+}
+
+impl Agent for Arbitrageur {
+    fn name(&self) -> &str {
+        "arbitrageur"
+    }
+
+    fn on_event(&mut self, event: &PoolEvent, _ctx: &mut AgentContext) -> Vec<Action> {
+        if event.pool == self.first || event.pool == self.second {
+            self.stale = true;
+        }
+        Vec::new()
+    }
+
+    fn on_block(&mut self, ctx: &mut AgentContext) -> Vec<Action> {
+        if !self.stale {
+            return Vec::new();
+        }
+        self.stale = false;
+        let (Ok(first), Ok(second)) = (ctx.market.amm(self.first), ctx.market.amm(self.second)) else {
+            return Vec::new();
+        };
+        let max_in = self.max_amount_in.min(ctx.account.balance(self.token_in));
+        let Ok(Some(arb)) = find_two_pool_arb(first, second, self.token_in, self.token_mid, max_in) else {
+            return Vec::new();
+        };
+
+        let (buy, sell) = match arb.route {
+            Route::FirstToSecond => ((self.first, first), (self.second, second)),
+            Route::SecondToFirst => ((self.second, second), (self.first, first)),
+        };
+        let Ok(mid_amount) = buy.1.quote_exact_in(self.token_in, self.token_mid, arb.amount_in) else {
+            return Vec::new();
+        };
+        vec![
+            Action::Swap {
+                pool: buy.0,
+                token_in: self.token_in,
+                token_out: self.token_mid,
+                amount_in: arb.amount_in,
+                min_amount_out: mid_amount,
+            },
+            // the second leg must at least pay back the first
+            Action::Swap {
+                pool: sell.0,
+                token_in: self.token_mid,
+                token_out: self.token_in,
+                amount_in: mid_amount,
+                min_amount_out: arb.amount_in,
+            }
+        ]
+    }
+}
+
+/// Tally of what one agent asked for.
+#[derive(Debug, PartialEq, Clone, Default, Serialize)]
+pub struct AgentStats {
+    pub actions: usize,
+    pub failures: usize,
+}
+
+enum MarketTick {
+    Block,
+}
+
+// a price process and the token pair each of its prices is quoted for
+type PriceFeed = (Box<dyn PriceProcess>, Vec<(TokenId, TokenId)>);
+
+/// Runs agents against a market block by block on a seeded simulation.
+/// Within a block the price feeds move first, then agents act in the order
+/// they were added. With a [`Journal`] set, every price move and action is
+/// written to it along with the state it left.
+pub struct MarketSim {
+    market: Market,
+    agents: Vec<(Box<dyn Agent>, Account, AgentStats)>,
+    feeds: Vec<PriceFeed>,
+    events: Receiver<PoolEvent>,
+    sim: Simulation<MarketTick>,
+    journal: Option<Journal>,
+}
+
+impl MarketSim {
+    pub fn new(market: Market, seed: u64, block_time: u64) -> MarketSim {
+        let events = market.bus().subscribe();
+        let mut sim = Simulation::new(seed, block_time);
+        sim.schedule_next_block(MarketTick::Block);
+        MarketSim { market, agents: Vec::new(), feeds: Vec::new(), events, sim, journal: None }
+    }
+
+    /// Makes `process` drive the reference price of each of `pairs`, in order,
+    /// stepping it once per block.
+    pub fn add_price_feed(&mut self, process: Box<dyn PriceProcess>, pairs: Vec<(TokenId, TokenId)>) -> Result<(), PoolError> {
+        let prices = process.prices();
+        if prices.len() != pairs.len() {
+            return Err(PoolError::InvalidParameter { name: "pairs", value: pairs.len() as f64 });
+        }
+        for ((base, quote), price) in pairs.iter().zip(prices) {
+            self.market.set_reference_price(*base, *quote, price);
+        }
+        self.feeds.push((process, pairs));
+        Ok(())
+    }
+
+    /// Returns the index under which the agent's account and stats can be looked up.
+    pub fn add_agent(&mut self, agent: Box<dyn Agent>, account: Account) -> usize {
+        self.agents.push((agent, account, AgentStats::default()));
+        self.agents.len() - 1
+    }
+
+    pub fn market(&self) -> &Market {
+        &self.market
+    }
+
+    /// For changes from outside the agents, e.g. a new reference price.
+    pub fn market_mut(&mut self) -> &mut Market {
+        &mut self.market
+    }
+
+    pub fn now(&self) -> Clock {
+        self.sim.now()
+    }
+
+    pub fn account(&self, agent: usize) -> &Account {
+        &self.agents[agent].1
+    }
+
+    pub fn stats(&self, agent: usize) -> &AgentStats {
+        &self.agents[agent].2
+    }
+
+    pub fn agents(&self) -> usize {
+        self.agents.len()
+    }
+
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    /// Stops journaling and hands the journal back, e.g. to [`Journal::finish`] it.
+    pub fn take_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
+    /// Carries out `action` for agent `agent`'s account, as if the agent had asked
+    /// for it, without telling the agent.
+    pub fn execute(&mut self, agent: usize, action: &Action) -> Result<(), PoolError> {
+        let (_, account, stats) = self.agents.get_mut(agent).ok_or(PoolError::InvalidParameter {
+            name: "agent",
+            value: agent as f64,
+        })?;
+        let result = self.market.execute(account, action);
+        stats.actions += 1;
+        if result.is_err() {
+            stats.failures += 1;
+        }
+        result
+    }
+
+    /// Plays `blocks` blocks, calling `after_block` at the end of each.
+    pub fn run_blocks(&mut self, blocks: u64, mut after_block: impl FnMut(Clock, &Market)) {
+        let end = (self.sim.now().block + blocks) * self.sim.block_time();
+        let dt = (self.sim.block_time() as f64) / SECONDS_PER_YEAR;
+        let (market, agents, feeds, events) = (&mut self.market, &mut self.agents, &mut self.feeds, &self.events);
+        let journal = &mut self.journal;
+        self.sim.run_until(end, |sim, tick| {
+            match tick {
+                MarketTick::Block => {
+                    let clock = sim.now();
+                    for (process, pairs) in feeds.iter_mut() {
+                        let prices = process.step(dt, sim.rng());
+                        for ((base, quote), price) in pairs.iter().zip(prices) {
+                            market.set_reference_price(*base, *quote, price);
+                            if let Some(journal) = journal.as_mut() {
+                                let state_hash = journal.state_hash(market, &accounts(agents));
+                                journal.write(&JournalRecord::Price {
+                                    block: clock.block,
+                                    timestamp: clock.timestamp,
+                                    base: *base,
+                                    quote: *quote,
+                                    price,
+                                    state_hash,
+                                });
+                            }
+                        }
+                    }
+                    run_block(clock, market, agents, events, journal.as_mut(), sim.rng());
+                    after_block(clock, market);
+                    sim.schedule_next_block(MarketTick::Block);
+                }
+            }
+        });
+    }
+}
+
+fn accounts(agents: &[(Box<dyn Agent>, Account, AgentStats)]) -> Vec<&Account> {
+    agents.iter().map(|(_, account, _)| account).collect()
+}
+
+fn run_block(
+    clock: Clock,
+    market: &Market,
+    agents: &mut [(Box<dyn Agent>, Account, AgentStats)],
+    events: &Receiver<PoolEvent>,
+    mut journal: Option<&mut Journal>,
+    rng: &mut dyn RngCore
+) {
+    let changes: Vec<PoolEvent> = events.try_iter().collect();
+    for k in 0..agents.len() {
+        let (agent, account, _) = &mut agents[k];
+        let mut ctx = AgentContext { clock, market, account, rng: &mut *rng };
+        let mut actions = Vec::new();
+        for event in &changes {
+            actions.extend(agent.on_event(event, &mut ctx));
+        }
+        actions.extend(agent.on_block(&mut ctx));
+
+        for action in actions {
+            let (agent, account, stats) = &mut agents[k];
+            let result = market.execute(account, &action);
+            stats.actions += 1;
+            let failed = result.is_err();
+            agent.on_result(&action, &result);
+            if failed {
+                stats.failures += 1;
+            }
+            if let Some(journal) = journal.as_deref_mut() {
+                let state_hash = journal.state_hash(market, &accounts(agents));
+                journal.write(&JournalRecord::Action {
+                    block: clock.block,
+                    timestamp: clock.timestamp,
+                    agent: k,
+                    error: result.err().map(|e| e.to_string()),
+                    action,
+                    state_hash,
+                });
+            }
+            if failed {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::{check_conservation, totals, Holdings};
+    use crate::math::price_to_sqrtp;
+    use crate::price::{Gbm, LagStats};
+    use crate::token::{Token, TokenRegistry};
+    use crate::v2::Pool;
+    use crate::v3::UniswapV3Pool;
+
+    fn set_up_tokens() -> (Token, Token) {
+        let mut registry = TokenRegistry::new();
+        let eth = registry.register("WETH", 18, "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let dai = registry.register("DAI", 18, "0x6B175474E89094C44Da98b954EedeAC495271d0F");
+        (registry.get(eth).unwrap().clone(), registry.get(dai).unwrap().clone())
+    }
+
+    #[test]
+    fn informed_trader_and_arbitrageur_close_gaps() {
+        let (weth, dai) = set_up_tokens();
+        let mut market = Market::new();
+        let cheap = market.add_v2("cheap", Pool::new(weth.id, dai.id, 100.0, 180000.0, 0.003));
+        let dear = market.add_v2("dear", Pool::new(weth.id, dai.id, 100.0, 220000.0, 0.003));
+        market.set_reference_price(weth.id, dai.id, 2000.0);
+
+        let mut sim = MarketSim::new(market, 1, 12);
+        let informed = sim.add_agent(
+            Box::new(InformedTrader::new(cheap, weth.id, dai.id, 0.01)),
+            Account::new(1, &[(dai.id, 100000.0)])
+        );
+        let arbitrageur = sim.add_agent(
+            Box::new(Arbitrageur::new(cheap, dear, weth.id, dai.id, 50.0)),
+            Account::new(2, &[(weth.id, 50.0)])
+        );
+        sim.run_blocks(20, |_, _| {});
+
+        let spot = |id| sim.market().amm(id).unwrap().spot_price(weth.id, dai.id).unwrap();
+        // within the fee of the reference price, and of each other
+        assert!((spot(cheap) / 2000.0 - 1.0).abs() < 0.01);
+        assert!((spot(dear) / spot(cheap) - 1.0).abs() < 0.01);
+        assert!(sim.account(informed).balance(weth.id) > 0.0);
+        assert!(sim.account(arbitrageur).balance(weth.id) > 50.0);
+        assert_eq!(sim.stats(arbitrageur).failures, 0);
+    }
+
+    #[test]
+    fn informed_trader_tracks_a_moving_price() {
+        let (weth, dai) = set_up_tokens();
+        let mut market = Market::new();
+        let tracked = market.add_v2("tracked", Pool::new(weth.id, dai.id, 1000.0, 2000000.0, 0.003));
+        let idle = market.add_v2("idle", Pool::new(weth.id, dai.id, 1000.0, 2000000.0, 0.003));
+
+        let mut sim = MarketSim::new(market, 3, 12);
+        // a very volatile price, so that it moves well past the fee within a few blocks
+        sim.add_price_feed(Box::new(Gbm::new(2000.0, 0.0, 20.0).unwrap()), vec![(weth.id, dai.id)]).unwrap();
+        assert!(sim.add_price_feed(Box::new(Gbm::new(1.0, 0.0, 1.0).unwrap()), Vec::new()).is_err());
+        sim.add_agent(
+            Box::new(InformedTrader::new(tracked, weth.id, dai.id, 0.003)),
+            Account::new(1, &[(weth.id, 1000.0), (dai.id, 2000000.0)])
+        );
+
+        let (mut tracked_lag, mut idle_lag) = (LagStats::new(0.01), LagStats::new(0.01));
+        sim.run_blocks(100, |_, market| {
+            tracked_lag.record(market.deviation(tracked, weth.id, dai.id).unwrap());
+            idle_lag.record(market.deviation(idle, weth.id, dai.id).unwrap());
+        });
+
+        assert_ne!(sim.market().reference_price(weth.id, dai.id), Some(2000.0));
+        assert_eq!(tracked_lag.samples, 100);
+        assert!(idle_lag.frequency() > 0.5);
+        assert!(tracked_lag.mean_abs() < idle_lag.mean_abs() / 2.0);
+        assert!(tracked_lag.frequency() < idle_lag.frequency());
+    }
+
+    #[test]
+    fn liquidity_providers_and_noise() {
+        let (weth, dai) = set_up_tokens();
+        let funds = [(weth.id, 1000000000000.0), (dai.id, 1000000000000000.0)];
+
+        let run = |seed: u64| {
+            let mut market = Market::new();
+            let v3 = market.add_v3("v3", UniswapV3Pool::new(&weth, &dai, price_to_sqrtp(2000.0, 18, 18), 0.003));
+            let v2 = market.add_v2("v2", Pool::new(weth.id, dai.id, 0.0, 0.0, 0.003));
+            let MarketPool::V3(pool) = market.pool(v3).unwrap().clone() else {
+                panic!("added as V3");
+            };
+
+            let mut sim = MarketSim::new(market, seed, 12);
+            let range_lp = sim.add_agent(Box::new(RangeLp::new(v3, 600, 10000000000.0)), Account::new(1, &funds));
+            sim.add_agent(
+                Box::new(PassiveLp::new(Action::Deposit { pool: v2, amount_x: 1000.0, amount_y: 2000000.0 })),
+                Account::new(2, &funds)
+            );
+            let noise = sim.add_agent(
+                Box::new(NoiseTrader::new(v3, (weth.id, dai.id), 0.9, 0.05)),
+                Account::new(3, &[(weth.id, 5000000.0), (dai.id, 10000000000.0)])
+            );
+            let supply = totals(&[sim.account(range_lp), sim.account(noise), &*pool as &dyn Holdings]);
+
+            sim.run_blocks(50, |_, _| {});
+
+            check_conservation(&supply, &[sim.account(range_lp), sim.account(noise), &*pool]).unwrap();
+            assert_eq!(sim.stats(range_lp).failures, 0);
+            (pool.tick(), sim.stats(noise).actions, sim.market().amm(v2).unwrap().version())
+        };
+
+        let (tick, trades, v2_version) = run(5);
+        assert!(trades > 30);
+        assert_eq!(v2_version, 1);
+        assert_eq!(run(5), (tick, trades, v2_version));
+    }
+}
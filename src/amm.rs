@@ -0,0 +1,77 @@
+use crate::error::PoolError;
+use crate::ledger::Account;
+use crate::token::TokenId;
+
+/// Common interface of every pool type, so search code can put any pool on
+/// any leg. Amounts and prices are in raw base units; quotes include the pool
+/// fee and never change pool state.
+pub trait Amm {
+    fn tokens(&self) -> Vec<TokenId>;
+
+    fn fee(&self) -> f64;
+
+    /// The marginal amount of `token_out` received per unit of `token_in`, before fees.
+    fn spot_price(&self, token_in: TokenId, token_out: TokenId) -> Result<f64, PoolError>;
+
+    fn quote_exact_in(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64
+    ) -> Result<f64, PoolError>;
+
+    fn quote_exact_out(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_out: f64
+    ) -> Result<f64, PoolError>;
+
+    /// Executes the swap against `trader` and returns the amount of `token_out` paid out.
+    fn apply_swap(
+        &self,
+        trader: &mut Account,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64,
+        min_amount_out: f64
+    ) -> Result<f64, PoolError>;
+
+    /// The number of initialized ticks a swap of `amount_in` would cross, for pools
+    /// whose gas cost depends on it.
+    fn ticks_crossed(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        _amount_in: f64
+    ) -> Result<usize, PoolError> {
+        check_pair(self, token_in, token_out)?;
+        Ok(0)
+    }
+
+    /// Increases with every change to the pool state, so results can name the state
+    /// they were computed against.
+    fn version(&self) -> u64;
+
+    /// A silent copy of the pool at its current version, for searching against a
+    /// state that cannot move underneath.
+    fn snapshot(&self) -> Box<dyn Amm + Send + Sync>;
+}
+
+/// Verifies that `token_in` and `token_out` are two different tokens of `pool`.
+pub fn check_pair<A: Amm + ?Sized>(
+    pool: &A,
+    token_in: TokenId,
+    token_out: TokenId
+) -> Result<(), PoolError> {
+    let tokens = pool.tokens();
+    for token in [token_in, token_out] {
+        if !tokens.contains(&token) {
+            return Err(PoolError::UnknownToken { token });
+        }
+    }
+    if token_in == token_out {
+        return Err(PoolError::UnknownToken { token: token_out });
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+
+/// Amount in integer base units, as stored on chain (wei for an 18-decimal token).
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
+pub struct RawAmount {
+    pub value: u128,
+    pub decimals: u8,
+}
+
+/// Human-readable amount, i.e. the raw amount divided by `10^decimals`.
+#[derive(Debug, PartialEq, PartialOrd, Copy, Clone)]
+pub struct Amount {
+    pub value: f64,
+    pub decimals: u8,
+}
+
+pub fn scale(decimals: u8) -> f64 {
+    10f64.powi(decimals as i32)
+}
+
+impl RawAmount {
+    pub fn new(value: u128, decimals: u8) -> RawAmount {
+        RawAmount { value, decimals }
+    }
+
+    pub fn as_f64(self) -> f64 {
+        self.value as f64
+    }
+
+    pub fn to_human(self) -> Amount {
+        Amount {
+            value: self.as_f64() / scale(self.decimals),
+            decimals: self.decimals,
+        }
+    }
+}
+
+impl Amount {
+    pub fn new(value: f64, decimals: u8) -> Amount {
+        Amount { value, decimals }
+    }
+
+    /// For amounts coming out of the pool math, which works on base units held in f64.
+    pub fn from_raw_f64(raw: f64, decimals: u8) -> Amount {
+        Amount {
+            value: raw / scale(decimals),
+            decimals,
+        }
+    }
+
+    pub fn raw_f64(self) -> f64 {
+        self.value * scale(self.decimals)
+    }
+
+    /// Rounds to the nearest base unit; negative amounts saturate to zero.
+    pub fn to_raw(self) -> RawAmount {
+        RawAmount {
+            value: self.raw_f64().round() as u128,
+            decimals: self.decimals,
+        }
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.*}", self.decimals.min(8) as usize, self.value)
+    }
+}
+
+impl fmt::Display for RawAmount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.to_human().fmt(f)
+    }
+}
+
+/// Converts a human price (token1 per token0) to the ratio of base units the pool works with.
+pub fn to_raw_price(price: f64, decimals_0: u8, decimals_1: u8) -> f64 {
+    price * scale(decimals_1) / scale(decimals_0)
+}
+
+pub fn to_human_price(raw_price: f64, decimals_0: u8, decimals_1: u8) -> f64 {
+    raw_price * scale(decimals_0) / scale(decimals_1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_and_human_round_trip() {
+        let usdc = RawAmount::new(2_500_000, 6);
+        assert_eq!(usdc.to_human(), Amount::new(2.5, 6));
+        assert_eq!(Amount::new(2.5, 6).to_raw(), usdc);
+
+        let weth = Amount::new(1.5, 18).to_raw();
+        assert_eq!(weth.value, 1_500_000_000_000_000_000);
+        assert_eq!(weth.to_string(), "1.50000000");
+    }
+
+    #[test]
+    fn price_across_decimals() {
+        // 2000 USDC per WETH, with WETH (18 decimals) as token0 and USDC (6 decimals) as token1
+        let raw = to_raw_price(2000.0, 18, 6);
+        assert_eq!(raw, 2e-9);
+        assert!((to_human_price(raw, 18, 6) - 2000.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,335 @@
+use crate::amm::Amm;
+use crate::error::PoolError;
+use crate::gas::GasModel;
+use crate::token::TokenId;
+
+const GOLDEN_RATIO: f64 = 0.618_033_988_749_895;
+
+const SEARCH_ITERATIONS: usize = 100;
+
+/// Order in which a round trip visits the two pools handed to [`find_two_pool_arb`].
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Route {
+    FirstToSecond,
+    SecondToFirst,
+}
+
+/// Most profitable round trip between two pools. Profit and gas cost are in
+/// raw units of the input token.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TwoPoolArb {
+    pub route: Route,
+    pub token_in: TokenId,
+    pub token_mid: TokenId,
+    pub amount_in: f64,
+    pub profit: f64,
+    pub gas_cost: f64,
+}
+
+impl TwoPoolArb {
+    pub fn net_profit(&self) -> f64 {
+        self.profit - self.gas_cost
+    }
+}
+
+/// Quotes `x_in` of `token_in` into `pool1` for `token_mid`, sells the proceeds
+/// back into `pool2`, and returns what is left over after repaying `x_in`. Neither
+/// pool is modified.
+pub fn calc_two_pool_arb_profit<A: Amm + ?Sized, B: Amm + ?Sized>(
+    x_in: f64,
+    pool1: &A,
+    pool2: &B,
+    token_in: TokenId,
+    token_mid: TokenId
+) -> Result<f64, PoolError> {
+    let change = pool1.quote_exact_in(token_in, token_mid, x_in)?;
+    let back = pool2.quote_exact_in(token_mid, token_in, change)?;
+    Ok(back - x_in)
+}
+
+/// Prices the gas of the same round trip in `token_in`, counting the ticks each leg
+/// crosses. `native_price` is the raw amount of `token_in` worth one wei of the
+/// native token.
+pub fn calc_two_pool_gas_cost<A: Amm + ?Sized, B: Amm + ?Sized>(
+    x_in: f64,
+    pool1: &A,
+    pool2: &B,
+    token_in: TokenId,
+    token_mid: TokenId,
+    gas: &GasModel,
+    native_price: f64
+) -> Result<f64, PoolError> {
+    let change = pool1.quote_exact_in(token_in, token_mid, x_in)?;
+    let ticks_crossed =
+        pool1.ticks_crossed(token_in, token_mid, x_in)? +
+        pool2.ticks_crossed(token_mid, token_in, change)?;
+    Ok(gas.cost(2, ticks_crossed, native_price))
+}
+
+// [find_peak] is the size up to [max_amt_in] maximizing [objective], or 0.0 if none profits. Sizes too large for the pools are skipped.
+fn find_peak<F: Fn(f64) -> Result<f64, PoolError>>(max_amt_in: f64, objective: F) -> Result<f64, PoolError> {
+    let mut hi = max_amt_in;
+    let mut feasible = false;
+    for _ in 0..SEARCH_ITERATIONS {
+        match objective(hi) {
+            Ok(_) => {
+                feasible = true;
+                break;
+            }
+            Err(e) if e.is_size_limit() => {
+                hi /= 2.0;
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        }
+    }
+    if !feasible {
+        return Ok(0.0);
+    }
+
+    // the profit of a round trip is concave in the input size, so a golden-section search finds the peak
+    let mut lo = 0.0;
+    let mut a = hi - GOLDEN_RATIO * (hi - lo);
+    let mut b = lo + GOLDEN_RATIO * (hi - lo);
+    let mut profit_a = objective(a)?;
+    let mut profit_b = objective(b)?;
+    for _ in 0..SEARCH_ITERATIONS {
+        if profit_a < profit_b {
+            lo = a;
+            a = b;
+            profit_a = profit_b;
+            b = lo + GOLDEN_RATIO * (hi - lo);
+            profit_b = objective(b)?;
+        } else {
+            hi = b;
+            b = a;
+            profit_b = profit_a;
+            a = hi - GOLDEN_RATIO * (hi - lo);
+            profit_a = objective(a)?;
+        }
+    }
+
+    let (opt_amt, max_profit) = if profit_a > profit_b { (a, profit_a) } else { (b, profit_b) };
+    if max_profit > 0.0 {
+        Ok(opt_amt)
+    } else {
+        Ok(0.0)
+    }
+}
+
+/// Returns the input size up to `max_amt_in` with the highest gross profit, or 0.0
+/// when no size is profitable.
+pub fn find_optimal_arb<A: Amm + ?Sized, B: Amm + ?Sized>(
+    pool1: &A,
+    pool2: &B,
+    token_in: TokenId,
+    token_mid: TokenId,
+    max_amt_in: f64
+) -> Result<f64, PoolError> {
+    find_peak(max_amt_in, |amt| calc_two_pool_arb_profit(amt, pool1, pool2, token_in, token_mid))
+}
+
+/// Sizes a trade of `token_in` into `pool` that profits most when one raw unit of
+/// `token_out` is worth `price` raw units of `token_in` elsewhere, e.g. at a
+/// reference price. Returns 0.0 when no size profits.
+pub fn find_optimal_reference_trade<A: Amm + ?Sized>(
+    pool: &A,
+    token_in: TokenId,
+    token_out: TokenId,
+    price: f64,
+    max_amt_in: f64
+) -> Result<f64, PoolError> {
+    find_peak(max_amt_in, |amt| Ok(pool.quote_exact_in(token_in, token_out, amt)? * price - amt))
+}
+
+/// `find_optimal_arb` with the gas cost of each size subtracted from its profit.
+pub fn find_optimal_net_arb<A: Amm + ?Sized, B: Amm + ?Sized>(
+    pool1: &A,
+    pool2: &B,
+    token_in: TokenId,
+    token_mid: TokenId,
+    max_amt_in: f64,
+    gas: &GasModel,
+    native_price: f64
+) -> Result<f64, PoolError> {
+    find_peak(max_amt_in, |amt| {
+        let profit = calc_two_pool_arb_profit(amt, pool1, pool2, token_in, token_mid)?;
+        let gas_cost = calc_two_pool_gas_cost(amt, pool1, pool2, token_in, token_mid, gas, native_price)?;
+        Ok(profit - gas_cost)
+    })
+}
+
+/// Searches both directions between `pool1` and `pool2`, which may be of different
+/// types, and returns the better one. Returns None when neither direction is
+/// profitable.
+pub fn find_two_pool_arb<A: Amm + ?Sized, B: Amm + ?Sized>(
+    pool1: &A,
+    pool2: &B,
+    token_in: TokenId,
+    token_mid: TokenId,
+    max_amt_in: f64
+) -> Result<Option<TwoPoolArb>, PoolError> {
+    find_two_pool_net_arb(pool1, pool2, token_in, token_mid, max_amt_in, &GasModel::free(), 0.0)
+}
+
+/// `find_two_pool_arb` ranked by profit net of gas. Returns None when neither
+/// direction covers its gas.
+pub fn find_two_pool_net_arb<A: Amm + ?Sized, B: Amm + ?Sized>(
+    pool1: &A,
+    pool2: &B,
+    token_in: TokenId,
+    token_mid: TokenId,
+    max_amt_in: f64,
+    gas: &GasModel,
+    native_price: f64
+) -> Result<Option<TwoPoolArb>, PoolError> {
+    let forward = find_optimal_net_arb(pool1, pool2, token_in, token_mid, max_amt_in, gas, native_price)?;
+    let backward = find_optimal_net_arb(pool2, pool1, token_in, token_mid, max_amt_in, gas, native_price)?;
+
+    let mut best: Option<TwoPoolArb> = None;
+    for (route, amount_in) in [(Route::FirstToSecond, forward), (Route::SecondToFirst, backward)] {
+        if amount_in <= 0.0 {
+            continue;
+        }
+        let (profit, gas_cost) = match route {
+            Route::FirstToSecond =>
+                (
+                    calc_two_pool_arb_profit(amount_in, pool1, pool2, token_in, token_mid)?,
+                    calc_two_pool_gas_cost(amount_in, pool1, pool2, token_in, token_mid, gas, native_price)?,
+                ),
+            Route::SecondToFirst =>
+                (
+                    calc_two_pool_arb_profit(amount_in, pool2, pool1, token_in, token_mid)?,
+                    calc_two_pool_gas_cost(amount_in, pool2, pool1, token_in, token_mid, gas, native_price)?,
+                ),
+        };
+        let arb = TwoPoolArb { route, token_in, token_mid, amount_in, profit, gas_cost };
+        if arb.net_profit() > 0.0 && best.as_ref().is_none_or(|b| arb.net_profit() > b.net_profit()) {
+            best = Some(arb);
+        }
+    }
+    Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::Account;
+    use crate::math::{get_q96, price_to_sqrtp};
+    use crate::token::TokenRegistry;
+    use crate::v2::Pool;
+    use crate::v3::UniswapV3Pool;
+
+    #[test]
+    fn find_optimal_amount() {
+        let mut registry = TokenRegistry::new();
+        let eth = registry.register("WETH", 18, "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let dai = registry.register("DAI", 18, "0x6B175474E89094C44Da98b954EedeAC495271d0F");
+        let pool1 = Pool::new(eth, dai, 4.0, 3500.0, 0.03);
+        let pool2 = Pool::new(eth, dai, 4.0, 4000.0, 0.03);
+
+        // selling ETH where it is dear and buying it back where it is cheap composes into
+        // out = K x / (D + E x), which peaks at x = (sqrt(K D) - D) / E
+        let gamma: f64 = 0.97;
+        let k = gamma * gamma * 4000.0 * 4.0;
+        let d = 4.0 * 3500.0;
+        let e = gamma * (3500.0 + gamma * 4000.0);
+        let expected = ((k * d).sqrt() - d) / e;
+
+        let b1 = find_optimal_arb(&pool2, &pool1, eth, dai, 2.0).unwrap();
+        assert!((b1 - expected).abs() < 1e-9);
+        let profit = calc_two_pool_arb_profit(b1, &pool2, &pool1, eth, dai).unwrap();
+        assert!((profit - ((k * expected) / (d + e * expected) - expected)).abs() < 1e-12);
+        assert!(profit > 0.0);
+
+        assert_eq!(find_optimal_arb(&pool1, &pool2, eth, dai, 2.0).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn trade_against_reference_price() {
+        let (eth, dai) = (TokenId(0), TokenId(1));
+        let pool = Pool::new(eth, dai, 100.0, 200000.0, 0.003);
+
+        // buying ETH at 2000 DAI when it is worth 2500 elsewhere pays p out - x with
+        // out = g x X / (Y + g x), which peaks at x = (sqrt(g X Y p) - Y) / g
+        let gamma: f64 = 0.997;
+        let expected = ((gamma * 100.0 * 200000.0 * 2500.0).sqrt() - 200000.0) / gamma;
+        let amount = find_optimal_reference_trade(&pool, dai, eth, 2500.0, 100000.0).unwrap();
+        assert!((amount - expected).abs() / expected < 1e-6);
+
+        // selling ETH into the pool does not pay at that price
+        assert_eq!(find_optimal_reference_trade(&pool, eth, dai, 1.0 / 2500.0, 10.0).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn mixed_v2_v3_arb() {
+        let mut registry = TokenRegistry::new();
+        let eth = registry.register("WETH", 18, "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let dai = registry.register("DAI", 18, "0x6B175474E89094C44Da98b954EedeAC495271d0F");
+        let (weth, dai_token) = (registry.get(eth).unwrap(), registry.get(dai).unwrap());
+
+        let lp = Account::new(1, &[(eth, 1000000000000.0), (dai, 1000000000000000.0)]);
+        let sqrtp = price_to_sqrtp(5000.0, 18, 18);
+        let v3 = UniswapV3Pool::new(weth, dai_token, sqrtp, 0.003);
+        v3.mint(&lp, 80000, 90000, 1000000000000.0).unwrap();
+        let v2 = Pool::new(eth, dai, 10000000000.0, 55000000000000.0, 0.003);
+
+        // inside one range the V3 pool trades like a constant product pool on its virtual
+        // reserves, so selling ETH into V2 and buying it back from V3 pays out K x / (D + E x)
+        let gamma: f64 = 0.997;
+        let x_v = (1000000000000.0 * get_q96()) / sqrtp;
+        let y_v = (1000000000000.0 * sqrtp) / get_q96();
+        let k = gamma * gamma * x_v * 55000000000000.0;
+        let d = y_v * 10000000000.0;
+        let e = gamma * (y_v + 55000000000000.0);
+        let expected = ((k * d).sqrt() - d) / e;
+
+        let best = find_two_pool_arb(&v2, &v3, eth, dai, 10000000000.0).unwrap().unwrap();
+        assert_eq!(best.route, Route::FirstToSecond);
+        assert!((best.amount_in - expected).abs() / expected < 1e-6);
+        let expected_profit = (k * expected) / (d + e * expected) - expected;
+        assert!((best.profit - expected_profit).abs() / expected_profit < 1e-6);
+
+        let flipped = find_two_pool_arb(&v3, &v2, eth, dai, 10000000000.0).unwrap().unwrap();
+        assert_eq!(flipped.route, Route::SecondToFirst);
+        assert_eq!(flipped.amount_in, best.amount_in);
+
+        // once the V2 pool trades at the V3 price there is nothing left to take
+        let balanced = Pool::new(eth, dai, 10000000000.0, 50000000000000.0, 0.003);
+        assert_eq!(find_two_pool_arb(&balanced, &v3, eth, dai, 10000000000.0).unwrap(), None);
+    }
+
+    #[test]
+    fn gas_is_netted_from_profit() {
+        let mut registry = TokenRegistry::new();
+        let eth = registry.register("WETH", 18, "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let dai = registry.register("DAI", 18, "0x6B175474E89094C44Da98b954EedeAC495271d0F");
+        let (weth, dai_token) = (registry.get(eth).unwrap(), registry.get(dai).unwrap());
+
+        let lp = Account::new(1, &[(eth, weth.amount(1000.0).raw_f64()), (dai, dai_token.amount(10000000.0).raw_f64())]);
+        let v3 = UniswapV3Pool::new(weth, dai_token, price_to_sqrtp(5000.0, 18, 18), 0.003);
+        v3.mint(&lp, 84000, 86000, 1000000000000000000000.0).unwrap();
+        v3.mint(&lp, 80000, 84000, 1000000000000000000000.0).unwrap();
+        let v2 = Pool::new(eth, dai, weth.amount(100.0).raw_f64(), dai_token.amount(550000.0).raw_f64(), 0.003);
+
+        let gross = find_two_pool_arb(&v2, &v3, eth, dai, weth.amount(100.0).raw_f64()).unwrap().unwrap();
+        assert_eq!(gross.gas_cost, 0.0);
+
+        let gas = GasModel::new(30000000000.0, 1000000000.0);
+        let net = find_two_pool_net_arb(&v2, &v3, eth, dai, weth.amount(100.0).raw_f64(), &gas, 1.0)
+            .unwrap()
+            .unwrap();
+        let ticks = v3.ticks_crossed(dai, eth, v2.quote_exact_in(eth, dai, net.amount_in).unwrap()).unwrap();
+        assert_eq!(net.gas_cost, gas.cost(2, ticks, 1.0));
+        assert!(net.net_profit() > 0.0);
+        assert!(net.net_profit() <= gross.profit);
+
+        // the same trade is not worth it once gas costs 1e15 wei (1000000 gwei)
+        let expensive = GasModel::new(1000000000000000.0, 0.0);
+        assert_eq!(
+            find_two_pool_net_arb(&v2, &v3, eth, dai, weth.amount(100.0).raw_f64(), &expensive, 1.0).unwrap(),
+            None
+        );
+    }
+}
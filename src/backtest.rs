@@ -0,0 +1,557 @@
+use crate::error::PoolError;
+use crate::events::PoolId;
+use crate::ledger::Account;
+use crate::market::{Market, MarketPool};
+use crate::opportunity::Opportunity;
+use crate::price::LagStats;
+use crate::searcher::{SearchMetrics, Searcher};
+use crate::sink::ChannelSink;
+use crate::token::TokenId;
+use crate::v2::{add, remove, swap, sync};
+use crate::v3::{v3_liquidity, v3_swap, v3_sync};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+// id of the account that replays everyone else's trades
+const CHAIN_ACCOUNT: i32 = -1;
+// raw balance of each token given to the replaying account, more than any pool holds
+const CHAIN_BALANCE: f64 = 1e60;
+// relative difference between the replayed and the recorded state that counts as drift
+const TRACKING_TOLERANCE: f64 = 1e-4;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordedKind {
+    Swap,
+    Mint,
+    Burn,
+    Sync,
+}
+
+/// One pool log as it was emitted on chain, one row of a CSV file or one
+/// line of a JSON lines file. Amounts are raw. Swap amounts are signed from
+/// the pool's side, so the token paid in is positive; mint and burn amounts
+/// are the sizes paid in or out. Which fields a log needs depends on its
+/// kind and pool:
+///
+/// - V2 swap, mint and burn: `amount0`, `amount1`
+/// - V2 sync: `reserve0`, `reserve1`, the reserves after the change
+/// - V3 swap: `amount0`, `amount1`, and optionally `sqrt_price_x96` after it
+/// - V3 mint and burn: `tick_lower`, `tick_upper`, `liquidity`
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecordedEvent {
+    pub block: u64,
+    pub pool: String,
+    pub event: RecordedKind,
+    #[serde(default)]
+    pub amount0: Option<f64>,
+    #[serde(default)]
+    pub amount1: Option<f64>,
+    #[serde(default)]
+    pub sqrt_price_x96: Option<f64>,
+    #[serde(default)]
+    pub tick_lower: Option<i32>,
+    #[serde(default)]
+    pub tick_upper: Option<i32>,
+    #[serde(default)]
+    pub liquidity: Option<f64>,
+    #[serde(default)]
+    pub reserve0: Option<f64>,
+    #[serde(default)]
+    pub reserve1: Option<f64>,
+}
+
+#[derive(Debug)]
+pub enum BacktestError {
+    Io(io::Error),
+    Parse(String),
+    /// `at` says which log is wrong, e.g. `event 3 (block 17000001, v2)`
+    Invalid {
+        at: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for BacktestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BacktestError::Io(e) => write!(f, "{}", e),
+            BacktestError::Parse(message) => write!(f, "cannot parse event log: {}", message),
+            BacktestError::Invalid { at, message } => write!(f, "{}: {}", at, message),
+        }
+    }
+}
+
+impl std::error::Error for BacktestError {}
+
+impl From<io::Error> for BacktestError {
+    fn from(e: io::Error) -> BacktestError {
+        BacktestError::Io(e)
+    }
+}
+
+fn invalid(at: &str, message: impl fmt::Display) -> BacktestError {
+    BacktestError::Invalid { at: at.to_string(), message: message.to_string() }
+}
+
+// [field] is [value], or an error at [at] naming the missing field.
+fn field<T>(value: Option<T>, name: &str, at: &str) -> Result<T, BacktestError> {
+    value.ok_or_else(|| invalid(at, format!("needs {}", name)))
+}
+
+/// Parses a CSV event log with a header row naming the fields of [`RecordedEvent`].
+pub fn read_csv<R: Read>(reader: R) -> Result<Vec<RecordedEvent>, BacktestError> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .map_err(|e| BacktestError::Parse(e.to_string()))
+}
+
+/// Parses an event log with one JSON object per line, skipping blank lines.
+pub fn read_json_lines<R: BufRead>(reader: R) -> Result<Vec<RecordedEvent>, BacktestError> {
+    let mut events = Vec::new();
+    for (k, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line)
+            .map_err(|e| BacktestError::Parse(format!("line {}: {}", k + 1, e)))?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// Reads a `.csv` or `.jsonl` event log.
+pub fn load_events(path: &Path) -> Result<Vec<RecordedEvent>, BacktestError> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("csv") => read_csv(File::open(path)?),
+        Some("jsonl") => read_json_lines(BufReader::new(File::open(path)?)),
+        _ => Err(invalid(&path.display().to_string(), "event logs end in .csv or .jsonl")),
+    }
+}
+
+/// A recorded log turned into the change it makes to a pool of the market.
+#[derive(Debug, PartialEq, Clone)]
+enum Change {
+    Swap {
+        token_in: TokenId,
+        amount_in: f64,
+        // what the pool paid out on chain
+        amount_out: f64,
+        sqrt_price_x96: Option<f64>,
+    },
+    Deposit {
+        amount_0: f64,
+        amount_1: f64,
+    },
+    Withdraw {
+        amount_0: f64,
+        amount_1: f64,
+    },
+    Sync {
+        reserve_0: f64,
+        reserve_1: f64,
+    },
+    // changes liquidity in a V3 range, burning it when negative
+    Liquidity {
+        lower_tick: i32,
+        upper_tick: i32,
+        liquidity_delta: f64,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Replay {
+    block: u64,
+    pool: PoolId,
+    change: Change,
+}
+
+// [resolve] checks that [event] carries what its kind needs for the pool it names and returns the change it makes.
+fn resolve(market: &Market, names: &HashMap<&str, PoolId>, event: &RecordedEvent, at: &str) -> Result<Replay, BacktestError> {
+    let id = *names.get(event.pool.as_str()).ok_or_else(|| invalid(at, "no pool of that name in the market"))?;
+    let pool = market.pool(id).map_err(|e| invalid(at, e))?;
+    let kind = event.event;
+    let amounts = || -> Result<(f64, f64), BacktestError> {
+        Ok((field(event.amount0, "amount0", at)?, field(event.amount1, "amount1", at)?))
+    };
+
+    let change = match (pool, kind) {
+        (MarketPool::Other(_), _) => {
+            return Err(invalid(at, "only V2 and V3 pools can be replayed"));
+        }
+        (_, RecordedKind::Swap) => {
+            let (amount_0, amount_1) = amounts()?;
+            let tokens = pool.amm().tokens();
+            let (token_in, amount_in, amount_out) = match (amount_0 > 0.0, amount_1 > 0.0) {
+                (true, false) => (tokens[0], amount_0, -amount_1),
+                (false, true) => (tokens[1], amount_1, -amount_0),
+                _ => {
+                    return Err(invalid(at, "a swap pays exactly one token into the pool"));
+                }
+            };
+            let sqrt_price_x96 = match pool {
+                MarketPool::V3(_) => event.sqrt_price_x96,
+                _ => None,
+            };
+            Change::Swap { token_in, amount_in, amount_out, sqrt_price_x96 }
+        }
+        (MarketPool::V2(_), RecordedKind::Mint) => {
+            let (amount_0, amount_1) = amounts()?;
+            Change::Deposit { amount_0: amount_0.abs(), amount_1: amount_1.abs() }
+        }
+        (MarketPool::V2(_), RecordedKind::Burn) => {
+            let (amount_0, amount_1) = amounts()?;
+            Change::Withdraw { amount_0: amount_0.abs(), amount_1: amount_1.abs() }
+        }
+        (MarketPool::V2(_), RecordedKind::Sync) => Change::Sync {
+            reserve_0: field(event.reserve0, "reserve0", at)?,
+            reserve_1: field(event.reserve1, "reserve1", at)?,
+        },
+        (MarketPool::V3(_), RecordedKind::Sync) => {
+            return Err(invalid(at, "sync logs only come from V2 pools"));
+        }
+        (MarketPool::V3(_), RecordedKind::Mint | RecordedKind::Burn) => {
+            let liquidity = field(event.liquidity, "liquidity", at)?.abs();
+            Change::Liquidity {
+                lower_tick: field(event.tick_lower, "tick_lower", at)?,
+                upper_tick: field(event.tick_upper, "tick_upper", at)?,
+                liquidity_delta: if kind == RecordedKind::Mint { liquidity } else { -liquidity },
+            }
+        }
+    };
+    Ok(Replay { block: event.block, pool: id, change })
+}
+
+// [apply] replays [change] into [pool], records the drift from the log, and resets to the logged state where given.
+fn apply(pool: &MarketPool, change: &Change, account: &mut Account, tracking: &mut LagStats) -> Result<(), PoolError> {
+    match (pool, change) {
+        (MarketPool::V2(pool), Change::Swap { token_in, amount_in, amount_out, .. }) => {
+            let out = swap(account, pool, *token_in, *amount_in, 0.0)?;
+            tracking.record(out / amount_out - 1.0);
+        }
+        (MarketPool::V2(pool), Change::Deposit { amount_0, amount_1 }) => add(pool, *amount_0, *amount_1)?,
+        (MarketPool::V2(pool), Change::Withdraw { amount_0, amount_1 }) => remove(pool, *amount_0, *amount_1)?,
+        (MarketPool::V2(pool), Change::Sync { reserve_0, reserve_1 }) => {
+            let (x, y) = pool.reserves();
+            tracking.record((x / reserve_0 - 1.0).abs().max((y / reserve_1 - 1.0).abs()));
+            sync(pool, *reserve_0, *reserve_1);
+        }
+        (MarketPool::V3(pool), Change::Swap { token_in, amount_in, amount_out, sqrt_price_x96 }) => {
+            let result = v3_swap(account, pool, *token_in, *amount_in, 0.0);
+            if let Ok((_, out)) = result {
+                tracking.record(out / amount_out - 1.0);
+            }
+            if let Some(sqrt_price_x96) = sqrt_price_x96 {
+                v3_sync(pool, *sqrt_price_x96)?;
+            }
+            result?;
+        }
+        (MarketPool::V3(pool), Change::Liquidity { lower_tick, upper_tick, liquidity_delta }) => {
+            // the range may hold liquidity minted before the log started, so no position is checked
+            v3_liquidity(pool, *lower_tick, *upper_tick, *liquidity_delta)?;
+        }
+        // [resolve] only pairs changes with pools that make them
+        _ => unreachable!("{:?} cannot be replayed into this pool", change),
+    }
+    Ok(())
+}
+
+/// What happened on chain in the block after an opportunity was found.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    // every leg of the route was traded the same way on chain
+    TakenOnChain,
+    // the pools moved and the route no longer pays
+    Closed,
+    // the route still paid after the next block
+    Persisted,
+    // the log ended before another block
+    Open,
+}
+
+/// An opportunity the searcher found at the end of `block`. A gap that stays
+/// open over several blocks is found in each of them but `counted` towards
+/// the PnL only once, when it opens.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct FoundOpportunity {
+    pub block: u64,
+    pub opportunity: Opportunity,
+    pub outcome: Outcome,
+    pub counted: bool,
+}
+
+/// What a backtest replayed and found. `tracking` measures how far the
+/// pool models drift from the recorded outcomes; `pnl` is the cumulative
+/// net value, after gas, in raw units of the searcher's numeraire at the
+/// end of each block.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct BacktestReport {
+    pub blocks: usize,
+    pub events: usize,
+    pub failed_events: usize,
+    pub tracking: LagStats,
+    pub opportunities: Vec<FoundOpportunity>,
+    pub pnl: Vec<(u64, f64)>,
+    pub search: SearchMetrics,
+}
+
+impl BacktestReport {
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.opportunities.iter().filter(|o| o.outcome == outcome).count()
+    }
+
+    pub fn total_pnl(&self) -> f64 {
+        self.pnl.last().map_or(0.0, |(_, pnl)| *pnl)
+    }
+}
+
+fn route(opportunity: &Opportunity) -> Vec<&str> {
+    opportunity.route.iter().map(|leg| leg.pool.as_str()).collect()
+}
+
+// [outcome] judges [opportunity] by the next block's [changes] and the opportunities [found] after it.
+fn outcome(opportunity: &Opportunity, market: &Market, changes: &[Replay], found: &[Opportunity]) -> Outcome {
+    let traded_like = |leg: &crate::opportunity::Leg| {
+        changes.iter().any(|replay| {
+            market.name(replay.pool) == Some(leg.pool.as_str()) &&
+                matches!(replay.change, Change::Swap { token_in, .. } if token_in == leg.token_in)
+        })
+    };
+    let touched = changes.iter().any(|replay| {
+        opportunity.route.iter().any(|leg| market.name(replay.pool) == Some(leg.pool.as_str()))
+    });
+    if opportunity.route.iter().all(traded_like) {
+        Outcome::TakenOnChain
+    } else if !touched || found.iter().any(|o| route(o) == route(opportunity)) {
+        Outcome::Persisted
+    } else {
+        Outcome::Closed
+    }
+}
+
+/// Replays `log`, in block order, into `market` and searches after each block
+/// with `searcher`, which must watch the pools under their market ids. Logs the
+/// model cannot replay are counted in `failed_events` and skipped.
+pub fn run_backtest(market: &Market, searcher: &mut Searcher, log: &[RecordedEvent]) -> Result<BacktestReport, BacktestError> {
+    let names: HashMap<&str, PoolId> = (0..market.len())
+        .map(|k| (market.name(PoolId(k)).unwrap(), PoolId(k)))
+        .collect();
+    let mut replays = Vec::with_capacity(log.len());
+    for (k, event) in log.iter().enumerate() {
+        let at = format!("event {} (block {}, {})", k + 1, event.block, event.pool);
+        if k > 0 && event.block < log[k - 1].block {
+            return Err(invalid(&at, "logs must be in block order"));
+        }
+        replays.push(resolve(market, &names, event, &at)?);
+    }
+
+    let tokens = (0..market.len()).flat_map(|k| market.amm(PoolId(k)).unwrap().tokens());
+    let balances: Vec<(TokenId, f64)> = tokens.map(|token| (token, CHAIN_BALANCE)).collect();
+    let mut account = Account::new(CHAIN_ACCOUNT, &balances);
+    let changes = market.bus().subscribe();
+    let (mut sink, published) = ChannelSink::new();
+
+    let mut report = BacktestReport {
+        blocks: 0,
+        events: log.len(),
+        failed_events: 0,
+        tracking: LagStats::new(TRACKING_TOLERANCE),
+        opportunities: Vec::new(),
+        pnl: Vec::new(),
+        search: SearchMetrics::default(),
+    };
+    // opportunities found at the end of the previous block, by index into the report
+    let mut pending: Vec<usize> = Vec::new();
+    let mut pnl = 0.0;
+    for block in replays.chunk_by(|a, b| a.block == b.block) {
+        for replay in block {
+            let pool = market.pool(replay.pool).unwrap();
+            if apply(pool, &replay.change, &mut account, &mut report.tracking).is_err() {
+                report.failed_events += 1;
+            }
+        }
+        searcher.handle(&changes.try_iter().collect::<Vec<_>>(), &mut sink)?;
+        let found: Vec<Opportunity> = published.try_iter().collect();
+
+        for &k in &pending {
+            report.opportunities[k].outcome = outcome(&report.opportunities[k].opportunity, market, block, &found);
+        }
+        let number = block[0].block;
+        let start = report.opportunities.len();
+        for opportunity in found {
+            // a gap still open from the previous block was already counted
+            let counted = !pending.iter().any(|&k| {
+                let earlier = &report.opportunities[k];
+                earlier.outcome == Outcome::Persisted && route(&earlier.opportunity) == route(&opportunity)
+            });
+            if counted {
+                pnl += opportunity.net_value;
+            }
+            report.opportunities.push(FoundOpportunity { block: number, opportunity, outcome: Outcome::Open, counted });
+        }
+        pending = (start..report.opportunities.len()).collect();
+        report.pnl.push((number, pnl));
+        report.blocks += 1;
+    }
+    report.search = searcher.metrics().clone();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::GasModel;
+    use crate::math::price_to_sqrtp;
+    use crate::searcher::{PoolPair, Valuation};
+    use crate::token::TokenRegistry;
+    use crate::v2::Pool;
+    use crate::v3::UniswapV3Pool;
+
+    const WETH: TokenId = TokenId(0);
+    const DAI: TokenId = TokenId(1);
+
+    // [set_up] is a market of two WETH/DAI pools at the same price and a searcher over them.
+    fn set_up() -> (Market, Searcher) {
+        let mut market = Market::new();
+        market.add_v2("a", Pool::new(WETH, DAI, 100.0, 200000.0, 0.003));
+        market.add_v2("b", Pool::new(WETH, DAI, 100.0, 200000.0, 0.003));
+        let mut searcher = Searcher::new(WETH, Valuation::Reference(1.0), GasModel::free(), 1.0, 0.005);
+        for k in 0..market.len() {
+            let id = PoolId(k);
+            searcher.add_pool(market.name(id).unwrap(), market.pool(id).unwrap().shared());
+        }
+        searcher.add_pair(PoolPair { first: PoolId(0), second: PoolId(1), token_in: WETH, token_mid: DAI, max_amount_in: 10.0 });
+        (market, searcher)
+    }
+
+    // [log] is a [kind] log of [pool] with no fields set.
+    fn log(block: u64, pool: &str, event: RecordedKind) -> RecordedEvent {
+        RecordedEvent {
+            block,
+            pool: pool.to_string(),
+            event,
+            amount0: None,
+            amount1: None,
+            sqrt_price_x96: None,
+            tick_lower: None,
+            tick_upper: None,
+            liquidity: None,
+            reserve0: None,
+            reserve1: None,
+        }
+    }
+
+    fn swap_log(block: u64, pool: &str, amount0: f64, amount1: f64) -> RecordedEvent {
+        RecordedEvent { amount0: Some(amount0), amount1: Some(amount1), ..log(block, pool, RecordedKind::Swap) }
+    }
+
+    #[test]
+    fn opportunities_are_judged_by_the_next_block() {
+        let (market, mut searcher) = set_up();
+        let log = vec![
+            // someone dumps WETH into a, opening a gap to b
+            swap_log(1, "a", 10.0, -18130.0),
+            // a block that leaves both prices alone: the gap persists
+            RecordedEvent { reserve0: Some(100.0), reserve1: Some(200000.0), ..log(2, "b", RecordedKind::Sync) },
+            // an arbitrageur buys WETH from a and sells it to b, narrowing the gap
+            swap_log(3, "a", -4.62, 8000.0),
+            swap_log(3, "b", 4.0, -7670.0),
+        ];
+
+        let report = run_backtest(&market, &mut searcher, &log).unwrap();
+
+        assert_eq!((report.blocks, report.events, report.failed_events), (3, 4, 0));
+        let outcomes: Vec<(u64, Outcome, bool)> = report.opportunities
+            .iter()
+            .map(|o| (o.block, o.outcome, o.counted))
+            .collect();
+        assert_eq!(outcomes, vec![(1, Outcome::Persisted, true), (2, Outcome::TakenOnChain, false), (3, Outcome::Open, true)]);
+        assert_eq!(report.count(Outcome::TakenOnChain), 1);
+        let first = &report.opportunities[0].opportunity;
+        assert_eq!(route(first), vec!["b", "a"]);
+        let last = &report.opportunities[2].opportunity;
+        assert_eq!(report.total_pnl(), first.net_value + last.net_value);
+        assert_eq!(report.pnl.iter().map(|p| p.0).collect::<Vec<_>>(), vec![1, 2, 3]);
+        // the model reproduced every recorded outcome closely
+        assert_eq!(report.tracking.samples, 4);
+        assert!(report.tracking.max_abs < 0.001);
+    }
+
+    #[test]
+    fn logs_parse_from_csv_and_json_lines() {
+        let csv = "block,pool,event,amount0,amount1,sqrt_price_x96,tick_lower,tick_upper,liquidity,reserve0,reserve1\n\
+                   1,v3,mint,,,,80000,90000,1e21,,\n\
+                   1,v3,swap,1e18,-5e21,5.6e30,,,,,\n\
+                   2,v2,sync,,,,,,,100,550000\n";
+        let from_csv = read_csv(csv.as_bytes()).unwrap();
+        let jsonl = "{\"block\":1,\"pool\":\"v3\",\"event\":\"mint\",\"tick_lower\":80000,\"tick_upper\":90000,\"liquidity\":1e21}\n\
+                     \n\
+                     {\"block\":1,\"pool\":\"v3\",\"event\":\"swap\",\"amount0\":1e18,\"amount1\":-5e21,\"sqrt_price_x96\":5.6e30}\n\
+                     {\"block\":2,\"pool\":\"v2\",\"event\":\"sync\",\"reserve0\":100,\"reserve1\":550000}\n";
+        let from_json = read_json_lines(jsonl.as_bytes()).unwrap();
+
+        assert_eq!(from_csv.len(), 3);
+        assert_eq!(from_csv, from_json);
+        assert_eq!(from_csv[0].tick_lower, Some(80000));
+        assert_eq!(from_csv[2].reserve1, Some(550000.0));
+        assert!(read_json_lines("{\"block\":1,\"pool\":\"v2\",\"typo\":1}".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn bad_logs_point_at_the_problem() {
+        let (market, mut searcher) = set_up();
+        let mut error = |events: Vec<RecordedEvent>| run_backtest(&market, &mut searcher, &events).unwrap_err().to_string();
+
+        assert_eq!(error(vec![swap_log(1, "c", 1.0, -1.0)]), "event 1 (block 1, c): no pool of that name in the market");
+        assert_eq!(error(vec![swap_log(1, "a", 1.0, 1.0)]), "event 1 (block 1, a): a swap pays exactly one token into the pool");
+        assert_eq!(
+            error(vec![swap_log(2, "a", 1.0, -1.0), swap_log(1, "a", 1.0, -1.0)]),
+            "event 2 (block 1, a): logs must be in block order"
+        );
+        assert_eq!(error(vec![log(1, "a", RecordedKind::Sync)]), "event 1 (block 1, a): needs reserve0");
+
+        // a withdrawal larger than the pool is skipped, not fatal
+        let burn = RecordedEvent { amount0: Some(1000.0), amount1: Some(1.0), ..log(1, "a", RecordedKind::Burn) };
+        assert_eq!(run_backtest(&market, &mut searcher, &[burn]).unwrap().failed_events, 1);
+    }
+
+    #[test]
+    fn burns_remove_liquidity_minted_before_the_log() {
+        let mut registry = TokenRegistry::new();
+        let weth = registry.register("WETH", 18, "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let dai = registry.register("DAI", 18, "0x6B175474E89094C44Da98b954EedeAC495271d0F");
+        let pool = UniswapV3Pool::new(registry.get(weth).unwrap(), registry.get(dai).unwrap(), price_to_sqrtp(2000.0, 18, 18), 0.003);
+        let (lower, upper) = (pool.tick() - 600, pool.tick() + 600);
+        let lp = Account::new(7, &[(weth, 1e24), (dai, 1e27)]);
+        pool.mint(&lp, lower, upper, 3e21).unwrap();
+        let mut market = Market::new();
+        market.add_v3("v3", pool);
+        let mut searcher = Searcher::new(weth, Valuation::Reference(1.0), GasModel::free(), 1.0, 0.005);
+
+        let burn = |liquidity| RecordedEvent {
+            tick_lower: Some(lower),
+            tick_upper: Some(upper),
+            liquidity: Some(liquidity),
+            ..log(1, "v3", RecordedKind::Burn)
+        };
+        let report = run_backtest(&market, &mut searcher, &[burn(1e21)]).unwrap();
+
+        assert_eq!(report.failed_events, 0);
+        let Ok(MarketPool::V3(pool)) = market.pool(PoolId(0)) else { panic!() };
+        assert_eq!(pool.liquidity(), 2e21);
+        assert_eq!(pool.ticks()[0].liquidity_gross, 2e21);
+        // more than the range holds is still refused
+        assert_eq!(run_backtest(&market, &mut searcher, &[burn(5e21)]).unwrap().failed_events, 1);
+        assert_eq!(pool.liquidity(), 2e21);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::ledger::LedgerError;
+use crate::token::TokenId;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PoolError {
+    InsufficientLiquidity,
+    InvalidTickRange {
+        lower: i32,
+        upper: i32,
+    },
+    InsufficientBalance {
+        account: i32,
+        token: TokenId,
+        needed: f64,
+        available: f64,
+    },
+    SlippageExceeded {
+        min_out: f64,
+        amount_out: f64,
+    },
+    PriceLimitReached {
+        amount_remaining: f64,
+    },
+    NotInitialized,
+    UnknownToken {
+        token: TokenId,
+    },
+    InvalidParameter {
+        name: &'static str,
+        value: f64,
+    },
+    NoConvergence,
+    Ledger(LedgerError),
+}
+
+impl PoolError {
+    /// True for errors caused by the size of a trade rather than by broken pool
+    /// state, i.e. a smaller trade may still succeed.
+    pub fn is_size_limit(&self) -> bool {
+        matches!(
+            self,
+            PoolError::InsufficientLiquidity |
+                PoolError::InsufficientBalance { .. } |
+                PoolError::SlippageExceeded { .. } |
+                PoolError::PriceLimitReached { .. }
+        )
+    }
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolError::InsufficientLiquidity => write!(f, "insufficient liquidity"),
+            PoolError::InvalidTickRange { lower, upper } =>
+                write!(f, "invalid tick range {}..{}", lower, upper),
+            PoolError::InsufficientBalance { account, token, needed, available } =>
+                write!(
+                    f,
+                    "account {} needs {} of token {} but holds {}",
+                    account,
+                    needed,
+                    token.0,
+                    available
+                ),
+            PoolError::SlippageExceeded { min_out, amount_out } =>
+                write!(f, "amount out {} is below the minimum {}", amount_out, min_out),
+            PoolError::PriceLimitReached { amount_remaining } =>
+                write!(f, "price limit reached with {} left to swap", amount_remaining),
+            PoolError::NotInitialized => write!(f, "pool or tick not initialized"),
+            PoolError::UnknownToken { token } =>
+                write!(f, "token {} cannot be swapped in this pool", token.0),
+            PoolError::InvalidParameter { name, value } =>
+                write!(f, "invalid value {} for {}", value, name),
+            PoolError::NoConvergence => write!(f, "pool invariant did not converge"),
+            PoolError::Ledger(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for PoolError {}
+
+impl From<LedgerError> for PoolError {
+    fn from(e: LedgerError) -> PoolError {
+        match e {
+            LedgerError::InsufficientBalance { account, token, needed, available } =>
+                PoolError::InsufficientBalance { account, token, needed, available },
+            e => PoolError::Ledger(e),
+        }
+    }
+}
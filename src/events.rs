@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Index of a pool among those a searcher watches.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PoolId(pub usize);
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum EventKind {
+    Mint,
+    Burn,
+    Swap,
+    // reserves or parameters were set from outside, e.g. a Sync log or a timestamp change
+    Sync,
+}
+
+/// A change to a pool's state. `version` is the pool version the change
+/// produced; `emitted_at` is when it happened, for latency measurements.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct PoolEvent {
+    pub pool: PoolId,
+    pub kind: EventKind,
+    pub version: u64,
+    pub emitted_at: Instant,
+}
+
+/// Broadcast bus: every subscriber gets every event.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<Sender<PoolEvent>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Arc<EventBus> {
+        Arc::new(EventBus::default())
+    }
+
+    pub fn subscribe(&self) -> Receiver<PoolEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Sends `event` to every subscriber, forgetting those that have hung up.
+    pub fn publish(&self, event: PoolEvent) {
+        self.subscribers.lock().unwrap().retain(|s| s.send(event).is_ok());
+    }
+
+    /// Drops every subscription, so receivers see the end of the stream once they have drained it.
+    pub fn close(&self) {
+        self.subscribers.lock().unwrap().clear();
+    }
+}
+
+/// A pool's handle on a bus. Every pool type takes one through its
+/// `with_events` builder and emits each change to its state through it. The
+/// default publisher is silent, which is what snapshots and pools nobody
+/// watches use.
+#[derive(Default, Clone)]
+pub struct Publisher {
+    target: Option<(PoolId, Arc<EventBus>)>,
+}
+
+impl Publisher {
+    pub fn new(bus: &Arc<EventBus>, pool: PoolId) -> Publisher {
+        Publisher { target: Some((pool, Arc::clone(bus))) }
+    }
+
+    pub fn emit(&self, kind: EventKind, version: u64) {
+        if let Some((pool, bus)) = &self.target {
+            bus.publish(PoolEvent { pool: *pool, kind, version, emitted_at: Instant::now() });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broadcast_to_every_subscriber() {
+        let bus = EventBus::new();
+        let (first, second) = (bus.subscribe(), bus.subscribe());
+        let publisher = Publisher::new(&bus, PoolId(3));
+
+        publisher.emit(EventKind::Swap, 7);
+        Publisher::default().emit(EventKind::Mint, 1);
+        drop(second);
+        publisher.emit(EventKind::Burn, 8);
+        bus.close();
+
+        let events: Vec<(PoolId, EventKind, u64)> = first
+            .iter()
+            .map(|e| (e.pool, e.kind, e.version))
+            .collect();
+        assert_eq!(events, vec![(PoolId(3), EventKind::Swap, 7), (PoolId(3), EventKind::Burn, 8)]);
+    }
+}
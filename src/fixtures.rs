@@ -0,0 +1,14 @@
+//! Tokens and recorded pool state shared by the tests of several modules.
+
+use crate::token::{Token, TokenRegistry};
+
+/// The USDC/WETH 0.3% V3 pool at block 19000000, as a subgraph dump.
+pub const USDC_WETH: &str = include_str!("../scenarios/usdc-weth-3000.json");
+
+/// USDC and WETH with their mainnet decimals and addresses, the tokens of [`USDC_WETH`].
+pub fn usdc_weth() -> (Token, Token) {
+    let mut registry = TokenRegistry::new();
+    let usdc = registry.register("USDC", 6, "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+    let weth = registry.register("WETH", 18, "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+    (registry.get(usdc).unwrap().clone(), registry.get(weth).unwrap().clone())
+}
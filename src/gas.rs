@@ -0,0 +1,77 @@
+// typical mainnet costs of a Uniswap style swap, an extra initialized tick and a flash loan
+const SWAP_GAS: f64 = 100000.0;
+const TICK_CROSSED_GAS: f64 = 25000.0;
+const FLASH_LOAN_GAS: f64 = 60000.0;
+
+/// Execution cost of a route. Gas prices are in wei of the native token per
+/// unit of gas (EIP-1559 base fee plus priority fee).
+#[derive(Debug, PartialEq, Clone)]
+pub struct GasModel {
+    pub gas_per_swap: f64,
+    pub gas_per_tick_crossed: f64,
+    pub flash_loan_gas: f64,
+    pub flash_loan: bool,
+    pub base_fee: f64,
+    pub priority_fee: f64,
+}
+
+impl GasModel {
+    /// Uses typical gas amounts per swap, tick crossed and flash loan; routes are
+    /// assumed to be funded by a flash loan.
+    pub fn new(base_fee: f64, priority_fee: f64) -> GasModel {
+        GasModel {
+            gas_per_swap: SWAP_GAS,
+            gas_per_tick_crossed: TICK_CROSSED_GAS,
+            flash_loan_gas: FLASH_LOAN_GAS,
+            flash_loan: true,
+            base_fee,
+            priority_fee,
+        }
+    }
+
+    /// A model where execution costs nothing, which reduces net profit to gross profit.
+    pub fn free() -> GasModel {
+        GasModel {
+            gas_per_swap: 0.0,
+            gas_per_tick_crossed: 0.0,
+            flash_loan_gas: 0.0,
+            flash_loan: false,
+            base_fee: 0.0,
+            priority_fee: 0.0,
+        }
+    }
+
+    pub fn gas_price(&self) -> f64 {
+        self.base_fee + self.priority_fee
+    }
+
+    pub fn gas_used(&self, swaps: usize, ticks_crossed: usize) -> f64 {
+        let flash_loan_gas = if self.flash_loan { self.flash_loan_gas } else { 0.0 };
+        (swaps as f64) * self.gas_per_swap +
+            (ticks_crossed as f64) * self.gas_per_tick_crossed +
+            flash_loan_gas
+    }
+
+    /// The price of a route with `swaps` legs crossing `ticks_crossed` ticks, in
+    /// raw units of the profit token. `native_price` is the raw amount of the
+    /// profit token worth one wei of the native token.
+    pub fn cost(&self, swaps: usize, ticks_crossed: usize, native_price: f64) -> f64 {
+        self.gas_used(swaps, ticks_crossed) * self.gas_price() * native_price
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cost_of_a_route() {
+        let gas = GasModel::new(30000000000.0, 1000000000.0);
+
+        assert_eq!(gas.gas_used(2, 3), 335000.0);
+        // 335000 gas at 31 gwei, paid in ETH and converted to DAI at 2000 DAI per ETH
+        assert_eq!(gas.cost(2, 3, 1.0), 10385000000000000.0);
+        assert_eq!(gas.cost(2, 3, 2000.0), 20770000000000000000.0);
+        assert_eq!(GasModel::free().cost(2, 3, 2000.0), 0.0);
+    }
+}
@@ -0,0 +1,318 @@
+use crate::events::PoolId;
+use crate::ledger::{Account, Holdings};
+use crate::market::{Action, Market};
+use crate::scenario::{Scenario, ScenarioError};
+use crate::token::TokenId;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+
+/// One line of a run journal. A journal starts with the scenario the run
+/// was built from, which holds its seed, followed by every reference price
+/// move and every action in the order they happened. Each of those carries
+/// the [`state_hash`] of the market right after it.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum JournalRecord {
+    Start {
+        scenario: Box<Scenario>,
+    },
+    Price {
+        block: u64,
+        timestamp: u64,
+        base: TokenId,
+        quote: TokenId,
+        price: f64,
+        state_hash: u64,
+    },
+    Action {
+        block: u64,
+        timestamp: u64,
+        // index of the agent whose account the action was carried out for
+        agent: usize,
+        action: Action,
+        error: Option<String>,
+        state_hash: u64,
+    },
+}
+
+/// 64-bit FNV-1a, which unlike the standard library's hasher is fixed
+/// across Rust releases, so journals stay comparable.
+struct StateHasher(u64);
+
+impl StateHasher {
+    fn new() -> StateHasher {
+        StateHasher(0xcbf29ce484222325)
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        for byte in value.to_le_bytes() {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn write_f64(&mut self, value: f64) {
+        self.write_u64(value.to_bits());
+    }
+
+    // [write_holdings] hashes balances in token order, whatever order the holder keeps them in.
+    fn write_holdings(&mut self, holder: &dyn Holdings) {
+        let mut holdings = holder.holdings();
+        holdings.sort_by_key(|(token, _)| *token);
+        self.write_u64(holdings.len() as u64);
+        for (token, amount) in holdings {
+            self.write_u64(token.0 as u64);
+            self.write_f64(amount);
+        }
+    }
+}
+
+/// Digests everything a run can change: each pool's version, mid price and
+/// balances (`pools`, in market order), `accounts` and the reference prices.
+pub fn state_hash(market: &Market, pools: &[Arc<dyn Holdings>], accounts: &[&Account]) -> u64 {
+    let mut hasher = StateHasher::new();
+    for (k, holdings) in pools.iter().enumerate() {
+        let Ok(amm) = market.amm(PoolId(k)) else {
+            continue;
+        };
+        hasher.write_u64(amm.version());
+        if let [a, b, ..] = amm.tokens().as_slice() {
+            hasher.write_f64(amm.spot_price(*a, *b).unwrap_or(0.0));
+        }
+        hasher.write_holdings(&**holdings);
+    }
+    for account in accounts {
+        hasher.write_holdings(*account);
+    }
+    for ((base, quote), price) in market.reference_prices() {
+        hasher.write_u64(base.0 as u64);
+        hasher.write_u64(quote.0 as u64);
+        hasher.write_f64(price);
+    }
+    hasher.0
+}
+
+/// Writes journal records as JSON lines. Write errors do not stop the run:
+/// the first one is kept and returned by [`Journal::finish`].
+pub struct Journal {
+    writer: Box<dyn Write>,
+    pools: Vec<Arc<dyn Holdings>>,
+    failure: Option<io::Error>,
+}
+
+impl Journal {
+    /// Journals to `writer` a market whose pools' balances `pools` hold, in market order.
+    pub fn new(writer: Box<dyn Write>, pools: Vec<Arc<dyn Holdings>>) -> Journal {
+        Journal { writer, pools, failure: None }
+    }
+
+    pub fn state_hash(&self, market: &Market, accounts: &[&Account]) -> u64 {
+        state_hash(market, &self.pools, accounts)
+    }
+
+    pub fn write(&mut self, record: &JournalRecord) {
+        if self.failure.is_some() {
+            return;
+        }
+        let line = serde_json::to_string(record).map_err(io::Error::from);
+        if let Err(e) = line.and_then(|line| writeln!(self.writer, "{}", line)) {
+            self.failure = Some(e);
+        }
+    }
+
+    /// Flushes the journal and reports the first write that failed.
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.failure.take() {
+            Some(e) => Err(e),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+/// Parses a journal written by [`Journal`].
+pub fn read_journal<R: BufRead>(reader: R) -> Result<Vec<JournalRecord>, ScenarioError> {
+    let mut records = Vec::new();
+    for (k, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line)
+            .map_err(|e| ScenarioError::Parse(format!("journal line {}: {}", k + 1, e)))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// The first journal record whose replay did not end as recorded. `line`
+/// counts from the start record, which is line 1.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Divergence {
+    pub line: usize,
+    pub block: u64,
+    pub timestamp: u64,
+    pub agent: Option<String>,
+    pub action: Option<Action>,
+    pub expected_error: Option<String>,
+    pub actual_error: Option<String>,
+    pub expected_hash: u64,
+    pub actual_hash: u64,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {} (block {}, timestamp {})", self.line, self.block, self.timestamp)?;
+        if let (Some(agent), Some(action)) = (&self.agent, &self.action) {
+            write!(f, ", {} doing {:?}", agent, action)?;
+        }
+        if self.expected_error != self.actual_error {
+            write!(f, ": expected {:?}, got {:?}", self.expected_error, self.actual_error)?;
+        }
+        write!(f, ": expected state {:016x}, got {:016x}", self.expected_hash, self.actual_hash)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ReplayReport {
+    // records replayed before the first divergence, or all of them
+    pub replayed: usize,
+    pub divergence: Option<Divergence>,
+}
+
+/// Re-executes a journal's price moves and actions on its scenario, without
+/// agents or random draws, stopping at the first record that differs.
+pub fn replay(records: &[JournalRecord]) -> Result<ReplayReport, ScenarioError> {
+    let Some(JournalRecord::Start { scenario }) = records.first() else {
+        return Err(ScenarioError::Invalid {
+            at: "journal line 1".to_string(),
+            message: "a journal starts with the scenario it ran".to_string(),
+        });
+    };
+    let mut run = scenario.build()?;
+    let mut replayed = 0;
+    for (k, record) in records.iter().enumerate().skip(1) {
+        let line = k + 1;
+        let (divergence, expected_hash) = match record {
+            JournalRecord::Start { .. } => {
+                return Err(ScenarioError::Invalid {
+                    at: format!("journal line {}", line),
+                    message: "a journal has one start record".to_string(),
+                });
+            }
+            JournalRecord::Price { block, timestamp, base, quote, price, state_hash } => {
+                run.sim.market_mut().set_reference_price(*base, *quote, *price);
+                let divergence = Divergence {
+                    line,
+                    block: *block,
+                    timestamp: *timestamp,
+                    agent: None,
+                    action: None,
+                    expected_error: None,
+                    actual_error: None,
+                    expected_hash: *state_hash,
+                    actual_hash: 0,
+                };
+                (divergence, *state_hash)
+            }
+            JournalRecord::Action { block, timestamp, agent, action, error, state_hash } => {
+                let Some(name) = run.agent_name(*agent).map(str::to_string) else {
+                    return Err(ScenarioError::Invalid {
+                        at: format!("journal line {}", line),
+                        message: format!("the scenario has no agent {}", agent),
+                    });
+                };
+                let actual_error = run.sim.execute(*agent, action).err().map(|e| e.to_string());
+                let divergence = Divergence {
+                    line,
+                    block: *block,
+                    timestamp: *timestamp,
+                    agent: Some(name),
+                    action: Some(action.clone()),
+                    expected_error: error.clone(),
+                    actual_error,
+                    expected_hash: *state_hash,
+                    actual_hash: 0,
+                };
+                (divergence, *state_hash)
+            }
+        };
+        let actual_hash = run.state_hash();
+        if actual_hash != expected_hash || divergence.expected_error != divergence.actual_error {
+            return Ok(ReplayReport { replayed, divergence: Some(Divergence { actual_hash, ..divergence }) });
+        }
+        replayed += 1;
+    }
+    Ok(ReplayReport { replayed, divergence: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::JsonLinesSink;
+    use std::io::BufReader;
+    use std::sync::Mutex;
+
+    const V2_SCENARIO: &str = include_str!("../scenarios/v2.toml");
+
+    /// A writer whose bytes can be read back once the journal has been handed over.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // [journal] runs the V2 demo for a few blocks and returns its journal.
+    fn journal() -> Vec<JournalRecord> {
+        let mut scenario = Scenario::from_toml(V2_SCENARIO).unwrap();
+        scenario.blocks = 20;
+        let mut run = scenario.build().unwrap();
+        let buffer = Shared::default();
+        run.record(Box::new(buffer.clone()));
+        run.run(&mut JsonLinesSink::new(io::sink())).unwrap();
+
+        let bytes = buffer.0.lock().unwrap().clone();
+        read_journal(BufReader::new(bytes.as_slice())).unwrap()
+    }
+
+    #[test]
+    fn journals_replay_to_the_same_states() {
+        let records = journal();
+        let actions = records.iter().filter(|r| matches!(r, JournalRecord::Action { .. })).count();
+        assert!(actions > 10);
+        assert!(matches!(&records[0], JournalRecord::Start { scenario } if scenario.seed == 42 && scenario.blocks == 20));
+
+        let report = replay(&records).unwrap();
+        assert_eq!(report, ReplayReport { replayed: records.len() - 1, divergence: None });
+    }
+
+    #[test]
+    fn replay_pinpoints_the_first_divergence() {
+        let mut records = journal();
+        // nudge the fifth swap, as a change to the code or a lost random draw would
+        let (k, _) = records
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| matches!(r, JournalRecord::Action { action: Action::Swap { .. }, .. }))
+            .nth(4)
+            .unwrap();
+        if let JournalRecord::Action { action: Action::Swap { amount_in, .. }, .. } = &mut records[k] {
+            *amount_in *= 1.0001;
+        }
+
+        let divergence = replay(&records).unwrap().divergence.unwrap();
+        assert_eq!(divergence.line, k + 1);
+        assert_ne!(divergence.expected_hash, divergence.actual_hash);
+        assert!(divergence.to_string().starts_with(&format!("line {} (block", k + 1)));
+
+        assert!(replay(&records[1..]).is_err());
+    }
+}
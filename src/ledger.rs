@@ -0,0 +1,261 @@
+use crate::token::TokenId;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum EntryReason {
+    Swap,
+    Mint,
+    Burn,
+    Fee,
+    Flash,
+}
+
+/// One debit (negative delta) or credit (positive delta) on an account.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LedgerEntry {
+    pub seq: usize,
+    pub token: TokenId,
+    pub delta: f64,
+    pub reason: EntryReason,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum LedgerError {
+    InsufficientBalance {
+        account: i32,
+        token: TokenId,
+        needed: f64,
+        available: f64,
+    },
+    ConservationViolated {
+        token: TokenId,
+        before: f64,
+        after: f64,
+    },
+    // a credit or debit of a negative or non-finite amount
+    InvalidAmount {
+        token: TokenId,
+        amount: f64,
+    },
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LedgerError::InsufficientBalance { account, token, needed, available } =>
+                write!(
+                    f,
+                    "account {} needs {} of token {} but holds {}",
+                    account,
+                    needed,
+                    token.0,
+                    available
+                ),
+            LedgerError::ConservationViolated { token, before, after } =>
+                write!(f, "total of token {} changed from {} to {}", token.0, before, after),
+            LedgerError::InvalidAmount { token, amount } =>
+                write!(f, "cannot move {} of token {}", amount, token.0),
+        }
+    }
+}
+
+impl std::error::Error for LedgerError {}
+
+/// Multi-asset account. Balances only change through [`Account::credit`] and
+/// [`Account::debit`], each of which appends to the account history.
+pub struct Account {
+    pub id: i32,
+    balances: RwLock<HashMap<TokenId, f64>>,
+    history: RwLock<Vec<LedgerEntry>>,
+}
+
+impl Account {
+    pub fn new(id: i32, balances: &[(TokenId, f64)]) -> Account {
+        Account {
+            id,
+            balances: RwLock::new(balances.iter().copied().collect()),
+            history: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn balance(&self, token: TokenId) -> f64 {
+        self.balances.read().unwrap().get(&token).copied().unwrap_or(0.0)
+    }
+
+    pub fn history(&self) -> Vec<LedgerEntry> {
+        self.history.read().unwrap().clone()
+    }
+
+    /// Checks that the account can pay `amount` of `token` without touching the balance.
+    pub fn ensure(&self, token: TokenId, amount: f64) -> Result<(), LedgerError> {
+        let available = self.balance(token);
+        if available < amount {
+            return Err(LedgerError::InsufficientBalance {
+                account: self.id,
+                token,
+                needed: amount,
+                available,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn credit(&self, token: TokenId, amount: f64, reason: EntryReason) -> Result<(), LedgerError> {
+        check_amount(token, amount)?;
+        self.record(token, amount, reason);
+        Ok(())
+    }
+
+    pub fn debit(&self, token: TokenId, amount: f64, reason: EntryReason) -> Result<(), LedgerError> {
+        check_amount(token, amount)?;
+        self.ensure(token, amount)?;
+        self.record(token, -amount, reason);
+        Ok(())
+    }
+
+    /// Debits `amount` paid into a swap whose fee is the fraction `fee` of it,
+    /// recording the fee and the rest as separate entries. The fee is debited
+    /// first so that spending a whole balance leaves no rounding residue.
+    pub fn debit_with_fee(&self, token: TokenId, amount: f64, fee: f64) -> Result<(), LedgerError> {
+        check_amount(token, amount)?;
+        self.ensure(token, amount)?;
+        let fee_amount = amount * fee;
+        self.debit(token, fee_amount, EntryReason::Fee)?;
+        self.debit(token, amount - fee_amount, EntryReason::Swap)
+    }
+
+    fn record(&self, token: TokenId, delta: f64, reason: EntryReason) {
+        let mut balances = self.balances.write().unwrap();
+        let mut history = self.history.write().unwrap();
+        *balances.entry(token).or_insert(0.0) += delta;
+        let seq = history.len();
+        history.push(LedgerEntry { seq, token, delta, reason });
+    }
+}
+
+// [check_amount] verifies that [amount] can be moved: a debit of a negative amount would be a credit and the other way round.
+fn check_amount(token: TokenId, amount: f64) -> Result<(), LedgerError> {
+    if !amount.is_finite() || amount < 0.0 {
+        return Err(LedgerError::InvalidAmount { token, amount });
+    }
+    Ok(())
+}
+
+/// Anything holding token balances: accounts and pools.
+pub trait Holdings {
+    fn holdings(&self) -> Vec<(TokenId, f64)>;
+}
+
+impl Holdings for Account {
+    fn holdings(&self) -> Vec<(TokenId, f64)> {
+        self.balances
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(t, b)| (*t, *b))
+            .collect()
+    }
+}
+
+pub fn totals(holders: &[&dyn Holdings]) -> HashMap<TokenId, f64> {
+    let mut totals = HashMap::new();
+    for holder in holders {
+        for (token, amount) in holder.holdings() {
+            *totals.entry(token).or_insert(0.0) += amount;
+        }
+    }
+    totals
+}
+
+/// Compares the current totals of `holders` with `before`, allowing for float
+/// rounding relative to the size of each total.
+pub fn check_conservation(
+    before: &HashMap<TokenId, f64>,
+    holders: &[&dyn Holdings]
+) -> Result<(), LedgerError> {
+    let after = totals(holders);
+    let mut tokens: Vec<&TokenId> = before.keys().chain(after.keys()).collect();
+    tokens.sort();
+    tokens.dedup();
+    for token in tokens {
+        let b = before.get(token).copied().unwrap_or(0.0);
+        let a = after.get(token).copied().unwrap_or(0.0);
+        if (a - b).abs() > 1e-9 * b.abs().max(a.abs()).max(1.0) {
+            return Err(LedgerError::ConservationViolated { token: *token, before: b, after: a });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debit_and_credit_are_recorded() {
+        let eth = TokenId(0);
+        let dai = TokenId(1);
+        let account = Account::new(1, &[(eth, 10.0)]);
+
+        account.debit(eth, 4.0, EntryReason::Swap).unwrap();
+        account.credit(dai, 100.0, EntryReason::Swap).unwrap();
+
+        assert_eq!(account.balance(eth), 6.0);
+        assert_eq!(account.balance(dai), 100.0);
+        let history = account.history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].delta, -4.0);
+        assert_eq!(history[1].reason, EntryReason::Swap);
+
+        // a whole balance spent with a fee leaves nothing behind
+        account.debit_with_fee(eth, 6.0, 0.003).unwrap();
+        assert_eq!(account.balance(eth), 0.0);
+        let reasons: Vec<EntryReason> = account.history()[2..].iter().map(|e| e.reason).collect();
+        assert_eq!(reasons, vec![EntryReason::Fee, EntryReason::Swap]);
+        assert!(account.debit_with_fee(dai, 101.0, 0.003).is_err());
+        assert_eq!(account.history().len(), 4);
+    }
+
+    #[test]
+    fn insufficient_balance() {
+        let eth = TokenId(0);
+        let account = Account::new(7, &[(eth, 1.0)]);
+
+        let err = account.debit(eth, 2.0, EntryReason::Mint).unwrap_err();
+
+        assert_eq!(err, LedgerError::InsufficientBalance {
+            account: 7,
+            token: eth,
+            needed: 2.0,
+            available: 1.0,
+        });
+        assert_eq!(account.balance(eth), 1.0);
+        assert!(account.history().is_empty());
+
+        assert_eq!(
+            account.credit(eth, -1.0, EntryReason::Burn),
+            Err(LedgerError::InvalidAmount { token: eth, amount: -1.0 })
+        );
+        assert!(account.credit(eth, f64::NAN, EntryReason::Burn).is_err());
+        assert!(account.debit(eth, -1.0, EntryReason::Mint).is_err());
+        assert_eq!(account.balance(eth), 1.0);
+        assert!(account.history().is_empty());
+    }
+
+    #[test]
+    fn conservation_across_accounts() {
+        let eth = TokenId(0);
+        let a = Account::new(1, &[(eth, 5.0)]);
+        let b = Account::new(2, &[]);
+        let before = totals(&[&a, &b]);
+
+        a.debit(eth, 2.0, EntryReason::Flash).unwrap();
+        b.credit(eth, 2.0, EntryReason::Flash).unwrap();
+        assert!(check_conservation(&before, &[&a, &b]).is_ok());
+
+        b.credit(eth, 1.0, EntryReason::Fee).unwrap();
+        assert!(check_conservation(&before, &[&a, &b]).is_err());
+    }
+}
@@ -1,724 +1,731 @@
-use rand::Rng;
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::sync::RwLock;
-use std::thread;
-use std::time::Duration;
-
-mod math;
-
-#[derive(PartialEq, Copy, Clone)]
-enum Token {
-    Eth,
-    Dai,
+use clap::{Args, Parser, Subcommand};
+use dotenv::dotenv;
+use rusty_arb::amount::{to_human_price, Amount};
+use rusty_arb::arb;
+use rusty_arb::backtest::{load_events, run_backtest, BacktestReport, Outcome};
+use rusty_arb::events::PoolId;
+use rusty_arb::gas::GasModel;
+use rusty_arb::journal::{self, read_journal};
+use rusty_arb::market::MarketPool;
+use rusty_arb::math::tick_to_price;
+use rusty_arb::opportunity::{NamedPool, Opportunity, PriceSource};
+use rusty_arb::rpc::RpcClient;
+use rusty_arb::scenario::{LoadedMarket, MarketFile, Scenario, ScenarioError, ScenarioReport};
+use rusty_arb::searcher::{PoolPair, SearchMetrics, Searcher, Valuation};
+use rusty_arb::sink::{ChannelSink, JsonLinesSink, OpportunitySink};
+use rusty_arb::token::Token;
+use rusty_arb::v3::TickLiquidity;
+use serde::Serialize;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process;
+
+// scenarios the V2 and V3 demos run, built into the binary
+const V2_SCENARIO: &str = include_str!("../scenarios/v2.toml");
+const V3_SCENARIO: &str = include_str!("../scenarios/v3.toml");
+
+/// Simulate AMM pools and search them for arbitrage. Options can also be
+/// set through the environment or a `.env` file.
+#[derive(Parser)]
+#[command(name = "rusty-arb", version)]
+struct Cli {
+    /// Print machine-readable JSON instead of text
+    #[arg(long, global = true, env = "RUSTY_ARB_JSON")]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
 }
 
-fn price_to_tick(price: f64) -> f64 {
-    price.log(1.001).floor()
+#[derive(Subcommand)]
+enum Command {
+    /// Run a scenario file, or the built-in `v2` or `v3` demo
+    Simulate {
+        #[arg(env = "RUSTY_ARB_SCENARIO", default_value = "v3")]
+        scenario: String,
+        /// Seed of the run, replacing the scenario's
+        #[arg(long, env = "RUSTY_ARB_SEED")]
+        seed: Option<u64>,
+        /// Number of blocks to run, replacing the scenario's
+        #[arg(long, env = "RUSTY_ARB_BLOCKS")]
+        blocks: Option<u64>,
+        /// Append opportunities to this file as JSON lines
+        #[arg(long, env = "RUSTY_ARB_OPPORTUNITIES")]
+        opportunities: Option<PathBuf>,
+        /// Journal every price move and action of the run to this file, for `replay`
+        #[arg(long, env = "RUSTY_ARB_JOURNAL")]
+        journal: Option<PathBuf>,
+    },
+    /// Re-execute a journal written by `simulate --journal` and check every state it recorded
+    Replay {
+        journal: PathBuf,
+    },
+    /// Quote a swap in every pool of a pool file that trades the token
+    Quote {
+        pool_file: PathBuf,
+        /// Symbol of the token to sell
+        #[arg(long = "in")]
+        token_in: String,
+        /// Symbol of the token to buy; defaults to the other token of two-token pools
+        #[arg(long = "out")]
+        token_out: Option<String>,
+        /// Amount to sell, in whole tokens
+        #[arg(long)]
+        amount: f64,
+        /// Only quote this pool
+        #[arg(long)]
+        pool: Option<String>,
+    },
+    /// Search every pair of pools in a pool file for a round trip, once
+    Search {
+        pools_file: PathBuf,
+        #[command(flatten)]
+        options: SearchOptions,
+    },
+    /// Replay recorded pool logs (.csv or .jsonl) into the pools of a pool
+    /// file and report what a searcher would have found after each block
+    Backtest {
+        pools_file: PathBuf,
+        events_file: PathBuf,
+        #[command(flatten)]
+        options: SearchOptions,
+    },
+    /// Print the state of the pools in a pool file, with the tick layout of V3 pools
+    Inspect {
+        pool_file: PathBuf,
+        /// Only inspect this pool
+        #[arg(long)]
+        pool: Option<String>,
+    },
+    /// Read the state of a deployed V3 pool from an Ethereum node, as JSON a
+    /// `v3_chain` pool in a pool file can import
+    Fetch {
+        /// Address of the pool
+        address: String,
+        /// Block to read the state at; defaults to the latest
+        #[arg(long)]
+        block: Option<u64>,
+        /// URL of the node's JSON-RPC endpoint
+        #[arg(long, env = "RUSTY_ARB_RPC_URL")]
+        rpc_url: String,
+        /// Write the state to this file rather than standard output
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
 }
 
-fn tick_to_price(tick: i32) -> f64 {
-    let base: f64 = 1.001;
-    let num: f64 = base.powi(tick);
-    num.sqrt() * math::get_q96()
-}
-
-fn price_to_sqrtp(price: f64) -> f64 {
-    price.sqrt() * math::get_q96()
-}
-
-fn liquidity0(amount: f64, pa: f64, pb: f64) -> f64 {
-    let q96 = math::get_q96();
-    if pa > pb {
-        return (amount * (pa * pb)) / q96 / (pb - pa);
-    } else {
-        return (amount * (pa * pb)) / q96 / (pa - pb);
+// [run_scenario] builds and plays [scenario], publishing opportunities to [sink] and journaling to [journal] if given.
+fn run_scenario(
+    scenario: &Scenario,
+    journal: Option<&Path>,
+    sink: &mut dyn OpportunitySink
+) -> Result<ScenarioReport, ScenarioError> {
+    let mut run = scenario.build()?;
+    if let Some(path) = journal {
+        run.record(Box::new(BufWriter::new(File::create(path)?)));
     }
+    run.run(sink)
 }
 
-fn liquidity1(amount: f64, pa: f64, pb: f64) -> f64 {
-    let q96 = math::get_q96();
-    if pa > pb {
-        return (amount * q96) / (pb - pa);
-    } else {
-        return (amount * q96) / (pa - pb);
+// [print_report] describes a finished run in text.
+fn print_report(report: &ScenarioReport) {
+    for (name, lag) in &report.lags {
+        println!(
+            "{} trails the reference price by {:.4} on average, {:.4} at most, and by more than its fee in {:.0}% of blocks",
+            name,
+            lag.mean_abs(),
+            lag.max_abs,
+            lag.frequency() * 100.0
+        );
     }
-}
-
-fn calc_amount0(liq: f64, lower_tick: f64, upper_tick: f64) -> f64 {
-    let q96 = math::get_q96();
-    if upper_tick > lower_tick {
-        return (liq * q96 * (upper_tick - lower_tick)) / lower_tick / upper_tick;
-    } else {
-        return (liq * q96 * (lower_tick - upper_tick)) / upper_tick / lower_tick;
+    if let Some(metrics) = &report.search {
+        report_search(metrics);
     }
 }
 
-fn calc_amount1(liq: f64, lower_tick: f64, upper_tick: f64) -> f64 {
-    let q96 = math::get_q96();
-    if upper_tick > lower_tick {
-        return (liq * (upper_tick - lower_tick)) / q96;
-    } else {
-        return (liq * (lower_tick - upper_tick)) / q96;
-    }
+// [report_search] prints what an event-driven search did and how quickly it reacted.
+fn report_search(metrics: &SearchMetrics) {
+    println!(
+        "{} pool updates, {} searches ({} failed), {} opportunities; update to opportunity {:?} mean, {:?} max",
+        metrics.events,
+        metrics.evaluations,
+        metrics.failures,
+        metrics.opportunities,
+        metrics.to_opportunity.mean(),
+        metrics.to_opportunity.max
+    );
 }
 
-fn calc_price_diff(amount_in: f64, liquidity: f64) -> f64 {
-    (amount_in * math::get_q96()) / liquidity
+// [print_json] writes [value] to stdout as one line of JSON.
+fn print_json<T: Serialize>(value: &T) -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string(value)?);
+    Ok(())
 }
 
-fn get_next_sqrt_price_from_input(
-    sqrt_price_current_x96: f64,
-    liquidity: f64,
-    amount_remaining: f64,
-    zero_for_one: bool
-) -> f64 {
-    let q96 = math::get_q96();
-    if zero_for_one {
-        return 
-            (liquidity * q96 * sqrt_price_current_x96) /
-            (liquidity * q96 + amount_remaining * sqrt_price_current_x96)
-        
-    } else {
-        return sqrt_price_current_x96 + (amount_remaining * q96) / liquidity;
+// [load_scenario] reads a scenario file, or a built-in demo when [name] is `v2` or `v3` and no such file exists.
+fn load_scenario(name: &str) -> Result<Scenario, ScenarioError> {
+    match name {
+        "v2" if !Path::new(name).exists() => Scenario::from_toml(V2_SCENARIO),
+        "v3" if !Path::new(name).exists() => Scenario::from_toml(V3_SCENARIO),
+        path => Scenario::load(Path::new(path)),
     }
 }
 
-fn compute_swap_step(
-    sqrt_price_current_x96: f64,
-    sqrt_price_target_x96: f64,
-    liquidity: f64,
-    amount_remaining: f64
-) -> (f64, f64, f64) {
-    let zero_for_one = sqrt_price_target_x96 >= sqrt_price_current_x96;
+#[derive(Serialize)]
+struct SimulateOutput {
+    report: ScenarioReport,
+    opportunities: Vec<Opportunity>,
+}
 
-    let amount_in_pre_calc = if zero_for_one {
-        calc_amount0(liquidity, sqrt_price_current_x96, sqrt_price_target_x96)
-    } else {
-        calc_amount1(liquidity, sqrt_price_current_x96, sqrt_price_target_x96)
+fn simulate(
+    name: &str,
+    seed: Option<u64>,
+    blocks: Option<u64>,
+    opportunities: Option<&Path>,
+    journal: Option<&Path>,
+    json: bool
+) -> Result<(), Box<dyn Error>> {
+    let mut scenario = load_scenario(name)?;
+    scenario.seed = seed.unwrap_or(scenario.seed);
+    scenario.blocks = blocks.unwrap_or(scenario.blocks);
+
+    // opportunities go to the file if there is one, else into the JSON output or onto stdout as JSON lines
+    let (mut channel, found) = ChannelSink::new();
+    let report = match (opportunities, json) {
+        (Some(path), _) => run_scenario(&scenario, journal, &mut JsonLinesSink::file(path)?)?,
+        (None, true) => run_scenario(&scenario, journal, &mut channel)?,
+        (None, false) => run_scenario(&scenario, journal, &mut JsonLinesSink::stdout())?,
     };
-
-    let sqrt_price_next_x96: f64;
-
-    if amount_remaining >= amount_in_pre_calc {
-        sqrt_price_next_x96 = sqrt_price_target_x96;
+    if json {
+        print_json(&(SimulateOutput { report, opportunities: found.try_iter().collect() }))
     } else {
-        sqrt_price_next_x96 = get_next_sqrt_price_from_input(
-            sqrt_price_current_x96,
-            liquidity,
-            amount_remaining,
-            zero_for_one
-        );
+        print_report(&report);
+        Ok(())
     }
+}
 
-    let amount_in = calc_amount0(liquidity, sqrt_price_current_x96, sqrt_price_next_x96);
-
-    let amount_out = calc_amount1(liquidity, sqrt_price_current_x96, sqrt_price_next_x96);
-
-    if zero_for_one {
-        (sqrt_price_next_x96, amount_in, amount_out)
-    } else {
-        (sqrt_price_next_x96, amount_out, amount_in)
+// [replay] re-executes the journal at [path] and fails if any state differs from the recorded one.
+fn replay(path: &Path, json: bool) -> Result<(), Box<dyn Error>> {
+    let records = read_journal(BufReader::new(File::open(path)?))?;
+    let report = journal::replay(&records)?;
+    if json {
+        print_json(&report)?;
+    } else if report.divergence.is_none() {
+        println!("Replayed {} records; every state matched the journal", report.replayed);
+    }
+    match report.divergence {
+        Some(divergence) => Err(format!("replay diverged at {}", divergence).into()),
+        None => Ok(()),
     }
 }
 
-struct Tick {
-    liquidity: RwLock<f64>,
-    initialized: RwLock<bool>,
+fn fetch(address: &str, block: Option<u64>, rpc_url: &str, out: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let dump = RpcClient::new(rpc_url).dump_v3(address, block)?;
+    let text = serde_json::to_string_pretty(&dump)?;
+    match out {
+        Some(path) => {
+            std::fs::write(path, text + "\n")?;
+            eprintln!(
+                "Wrote {} initialized ticks of {} at block {} to {}",
+                dump.ticks.len(),
+                address,
+                dump.block_number.unwrap_or_default(),
+                path.display()
+            );
+        }
+        None => println!("{}", text),
+    }
+    Ok(())
 }
 
-struct Position {
-    liquidity: RwLock<f64>,
+fn load_market(path: &Path) -> Result<LoadedMarket, ScenarioError> {
+    MarketFile::load(path)?.build()
 }
 
-struct UniswapV3Pool {
-    token_0: Token,
-    token_1: Token,
-    min_tick: i32,
-    max_tick: i32,
-    balance_0: RwLock<f64>,
-    balance_1: RwLock<f64>,
-    tick_mapping: RwLock<HashMap<i32, Tick>>,
-    liquidity_mapping: RwLock<HashMap<i32, f64>>,
-    position_mapping: RwLock<HashMap<i32, Position>>,
-    sqrt_price_x96: RwLock<f64>,
-    tick: RwLock<i32>,
-    liquidity: RwLock<f64>,
+fn find_token<'a>(loaded: &'a LoadedMarket, symbol: &str) -> Result<&'a Token, Box<dyn Error>> {
+    Ok(loaded.token(symbol).ok_or_else(|| format!("unknown token {:?}", symbol))?)
 }
 
-impl UniswapV3Pool {
-    fn update(&mut self, tick: i32, liquidity_delta: f64) -> bool {
-        let default_tick = Tick {
-            liquidity: RwLock::new(0.0),
-            initialized: RwLock::new(false),
-        };
-
-        let tick_map = &mut self.tick_mapping.write().unwrap();
-
-        let info = tick_map.entry(tick).or_insert(default_tick);
-
-        let liquidity_before = *info.liquidity.read().unwrap();
-
-        let liquidity_after = liquidity_before + liquidity_delta;
-
-        if liquidity_before == 0.0 {
-            *info.initialized.write().unwrap() = true;
-            self.liquidity_mapping.write().unwrap().insert(tick, liquidity_after);
+// [selected_pools] is the pool named [name], or every pool when no name is given.
+fn selected_pools(loaded: &LoadedMarket, name: Option<&str>) -> Result<Vec<PoolId>, Box<dyn Error>> {
+    match name {
+        Some(name) => {
+            let id = loaded.pool_id(name).ok_or_else(|| format!("unknown pool {:?}", name))?;
+            Ok(vec![id])
         }
+        None => Ok((0..loaded.market.len()).map(PoolId).collect()),
+    }
+}
 
-        *info.liquidity.write().unwrap() = liquidity_after;
+fn human(token: &Token, raw: f64) -> f64 {
+    Amount::from_raw_f64(raw, token.decimals).value
+}
 
-        let flipped = (liquidity_after == 0.0) != (liquidity_before == 0.0);
+/// A swap quoted by the `quote` command. Amounts are in whole tokens and
+/// prices in the out token per in token.
+#[derive(Serialize)]
+struct QuoteOutput {
+    pool: String,
+    token_in: String,
+    token_out: String,
+    amount_in: f64,
+    amount_out: f64,
+    fee: f64,
+    spot_price: f64,
+    execution_price: f64,
+    price_impact: f64,
+}
 
-        flipped
+fn quote(
+    path: &Path,
+    token_in: &str,
+    token_out: Option<&str>,
+    amount: f64,
+    pool: Option<&str>,
+    json: bool
+) -> Result<(), Box<dyn Error>> {
+    if !amount.is_finite() || amount <= 0.0 {
+        return Err(format!("--amount must be a positive number, not {}", amount).into());
     }
-
-    fn _update_position(
-        &mut self,
-        owner: &Trader,
-        lower_tick: i32,
-        upper_tick: i32,
-        liquidity_delta: f64
-    ) {
-        let flipped_lower = self.update(lower_tick, liquidity_delta);
-        let flipped_upper = self.update(upper_tick, liquidity_delta);
-
-        if flipped_lower {
-            self.liquidity_mapping.write().unwrap().insert(lower_tick, 1.0);
+    let loaded = load_market(path)?;
+    let token_in = find_token(&loaded, token_in)?;
+    let token_out = token_out.map(|symbol| find_token(&loaded, symbol)).transpose()?;
+    let raw_in = token_in.amount(amount).raw_f64();
+
+    let mut quotes = Vec::new();
+    for id in selected_pools(&loaded, pool)? {
+        let amm = loaded.market.amm(id)?;
+        let tokens = amm.tokens();
+        if !tokens.contains(&token_in.id) {
+            continue;
         }
-        if flipped_upper {
-            self.liquidity_mapping.write().unwrap().insert(upper_tick, 1.0);
-        }
-
-        let default_position = Position {
-            liquidity: RwLock::new(0.0),
-        };
-
-        let position_map = &mut self.position_mapping.write().unwrap();
-
-        let position = position_map.entry(owner.id).or_insert(default_position);
-
-        *position.liquidity.write().unwrap() += liquidity_delta;
-
-        if liquidity_delta < 0.0 {
-            if flipped_lower {
-                self.liquidity_mapping.write().unwrap().remove(&lower_tick);
-            }
-            if flipped_upper {
-                self.liquidity_mapping.write().unwrap().remove(&upper_tick);
+        let out = match (token_out, tokens.as_slice()) {
+            (Some(out), _) => out,
+            (None, [a, b]) => loaded.registry.get(if *a == token_in.id { *b } else { *a }).unwrap(),
+            (None, _) => {
+                let name = loaded.market.name(id).unwrap();
+                return Err(format!("{} has more than two tokens, so give --out", name).into());
             }
+        };
+        if !tokens.contains(&out.id) {
+            continue;
         }
+        let raw_out = amm.quote_exact_in(token_in.id, out.id, raw_in)?;
+        let spot_price = to_human_price(amm.spot_price(token_in.id, out.id)?, token_in.decimals, out.decimals);
+        let execution_price = human(out, raw_out) / amount;
+        quotes.push(QuoteOutput {
+            pool: loaded.market.name(id).unwrap().to_string(),
+            token_in: token_in.symbol.clone(),
+            token_out: out.symbol.clone(),
+            amount_in: amount,
+            amount_out: human(out, raw_out),
+            fee: amm.fee(),
+            spot_price,
+            execution_price,
+            price_impact: 1.0 - execution_price / spot_price,
+        });
     }
 
-    fn _modify_position(
-        &mut self,
-        owner: &Trader,
-        lower_tick: i32,
-        upper_tick: i32,
-        liquidity_delta: f64
-    ) -> (f64, f64) {
-        let mut amount0: f64 = 0.0;
-        let mut amount1: f64 = 0.0;
-        let sqrt_price_x96 = *self.sqrt_price_x96.read().unwrap();
-        let tick = *self.tick.read().unwrap();
-        self._update_position(owner, lower_tick, upper_tick, liquidity_delta);
-        if liquidity_delta != 0.0 {
-            if tick < lower_tick {
-                amount0 = calc_amount0(
-                    liquidity_delta,
-                    tick_to_price(lower_tick),
-                    tick_to_price(upper_tick)
-                );
-            } else if tick < upper_tick {
-                amount0 = calc_amount0(liquidity_delta, sqrt_price_x96, tick_to_price(upper_tick));
-
-                amount1 = calc_amount1(liquidity_delta, tick_to_price(lower_tick), sqrt_price_x96);
-                *self.liquidity.write().unwrap() += liquidity_delta;
-            } else {
-                amount1 = calc_amount1(
-                    liquidity_delta,
-                    tick_to_price(lower_tick),
-                    tick_to_price(upper_tick)
-                );
-            }
-        }
-        (amount0, amount1)
+    if json {
+        return print_json(&quotes);
     }
-
-    fn mint(&mut self, owner: &Trader, lower_tick: i32, upper_tick: i32, liquidity_delta: f64) {
-        if !(lower_tick >= upper_tick || lower_tick < self.min_tick || upper_tick > self.max_tick)
-            && liquidity_delta != 0.
-        {
-            let (amount0, amount1) =
-                self._modify_position(owner, lower_tick, upper_tick, liquidity_delta);
-            if amount0 > 0. {
-                *self.balance_0.write().unwrap() += amount0
-            }
-            if amount1 > 0. {
-                *self.balance_1.write().unwrap() += amount1
-            }
-
-            if self.token_0 == Token::Eth {
-                *owner.amt_eth.write().unwrap() -= amount0;
-                *owner.amt_dai.write().unwrap() -= amount1;
-            } else {
-                *owner.amt_eth.write().unwrap() -= amount1;
-                *owner.amt_dai.write().unwrap() -= amount0;
-            }
-        }
+    if quotes.is_empty() {
+        println!("No pool trades {}", token_in.symbol);
     }
+    for q in &quotes {
+        println!(
+            "{}: {} {} -> {} {} at {} (spot {}, impact {:.4}%, fee {}%)",
+            q.pool,
+            q.amount_in,
+            q.token_in,
+            q.amount_out,
+            q.token_out,
+            q.execution_price,
+            q.spot_price,
+            q.price_impact * 100.0,
+            q.fee * 100.0
+        );
+    }
+    Ok(())
 }
 
-struct SwapState {
-    amount_specified_remaining: f64,
-    amount_calculated: f64,
-    sqrt_price_x96: f64,
-    tick: i32,
-    liquidity: f64,
-}
-
-struct StepState {
-    sqrt_price_start_x96: f64,
-    next_tick: i32,
-    sqrt_price_next_x96: f64,
-    amount_in: f64,
-    amount_out: f64,
+/// The round trip the `search` and `backtest` commands look for, and what
+/// it costs to make.
+#[derive(Args)]
+struct SearchOptions {
+    /// Symbol of the token the round trip starts and ends in
+    #[arg(long = "in")]
+    token_in: String,
+    /// Symbol of the token bought on the first leg
+    #[arg(long)]
+    mid: String,
+    /// Largest amount to start with, in whole tokens
+    #[arg(long)]
+    max_amount: f64,
+    /// Base fee in wei per gas
+    #[arg(long, env = "RUSTY_ARB_BASE_FEE", default_value_t = 0.0)]
+    base_fee: f64,
+    /// Priority fee in wei per gas
+    #[arg(long, env = "RUSTY_ARB_PRIORITY_FEE", default_value_t = 0.0)]
+    priority_fee: f64,
+    /// Raw units of the starting token worth one wei
+    #[arg(long, env = "RUSTY_ARB_NATIVE_PRICE", default_value_t = 1.0)]
+    native_price: f64,
+    #[arg(long, env = "RUSTY_ARB_SLIPPAGE", default_value_t = 0.005)]
+    slippage_tolerance: f64,
 }
 
-// [next_initialized_tick] returns -1 if there is no tick available in the provided direction of liquidity. Returns the tick with liquidity if one is found.
-fn next_initialized_tick(liquidity_mapping: HashMap<i32, f64>, tick: i32, is_up: bool) -> i32 {
-    let liquidity_map = liquidity_mapping;
-
-    let mut sorted_keys: Vec<i32> = liquidity_map.into_keys().collect();
-    sorted_keys.sort_unstable();
-    let start_index: i32;
-    if is_up {
-        start_index = match sorted_keys.iter().position(|&x| x >= tick) {
-            None => -1,
-            Some(x) => x as i32,
-        };
-    } else {
-        start_index = match sorted_keys.iter().position(|&x| x <= tick) {
-            None => -1,
-            Some(x) => x as i32,
-        };
-    }
-    match sorted_keys.get(start_index as usize) {
-        None => -1,
-        x => *x.unwrap(),
+impl SearchOptions {
+    fn gas(&self) -> GasModel {
+        GasModel::new(self.base_fee, self.priority_fee)
     }
 }
 
-fn cross(tick_mapping: &HashMap<i32, Tick>, next_tick: i32) -> f64 {
-    let tick = tick_mapping.get(&next_tick).unwrap();
-    *tick.liquidity.read().unwrap()
-}
-
-fn v3_swap(
-    trader: &mut Trader,
-    pool: &UniswapV3Pool,
-    token_in: Token,
-    amount_specified: f64,
-    fee: f64
-) {
-    let zero_for_one: bool = token_in == pool.token_0;
-
-    let mut state = SwapState {
-        amount_specified_remaining: amount_specified,
-        amount_calculated: 0.0,
-        sqrt_price_x96: *pool.sqrt_price_x96.read().unwrap(),
-        tick: pool.tick.read().unwrap().clone(),
-        liquidity: *pool.liquidity.read().unwrap(),
+// [pair_pools] lists the pools trading both [token_in] and [token_mid], the ones a round trip can go through.
+fn pair_pools(loaded: &LoadedMarket, token_in: &Token, token_mid: &Token) -> Vec<PoolId> {
+    let trades_pair = |id: &PoolId| {
+        let tokens = loaded.market.amm(*id).unwrap().tokens();
+        tokens.contains(&token_in.id) && tokens.contains(&token_mid.id)
     };
+    (0..loaded.market.len()).map(PoolId).filter(trades_pair).collect()
+}
 
-    while state.amount_specified_remaining > 0.0 {
-        let next_tick = next_initialized_tick(
-            pool.liquidity_mapping.read().unwrap().clone(),
-            state.tick,
-            zero_for_one
-        );
-        let sqrt_price_next_x96 = tick_to_price(next_tick);
-
-        let (next_sqrt_price_x96, amount_in, amount_out) = compute_swap_step(
-            state.sqrt_price_x96,
-            sqrt_price_next_x96,
-            state.liquidity,
-            state.amount_specified_remaining
-        );
-
-        let step = StepState {
-            sqrt_price_start_x96: state.sqrt_price_x96,
-            next_tick: next_tick,
-            sqrt_price_next_x96: next_sqrt_price_x96,
-            amount_in: amount_in,
-            amount_out: amount_out,
-        };
-
-        if step.amount_in == 0.0 {
-            return;
+fn search(path: &Path, options: &SearchOptions, json: bool) -> Result<(), Box<dyn Error>> {
+    let loaded = load_market(path)?;
+    let token_in = find_token(&loaded, &options.token_in)?;
+    let token_mid = find_token(&loaded, &options.mid)?;
+    let max_amount_in = token_in.amount(options.max_amount).raw_f64();
+    let pools = pair_pools(&loaded, token_in, token_mid);
+
+    let mut found = Vec::new();
+    for (k, first) in pools.iter().enumerate() {
+        for second in &pools[k + 1..] {
+            let (a, b) = (loaded.market.amm(*first)?, loaded.market.amm(*second)?);
+            let best = arb::find_two_pool_net_arb(
+                a,
+                b,
+                token_in.id,
+                token_mid.id,
+                max_amount_in,
+                &options.gas(),
+                options.native_price
+            )?;
+            let Some(best) = best else {
+                continue;
+            };
+            // profit is made in the starting token, so it is its own numeraire
+            found.push(
+                Opportunity::from_arb(
+                    &best,
+                    &NamedPool { name: loaded.market.name(*first).unwrap(), pool: a },
+                    &NamedPool { name: loaded.market.name(*second).unwrap(), pool: b },
+                    token_in.id,
+                    &PriceSource::Reference(1.0),
+                    options.slippage_tolerance
+                )?
+            );
         }
+    }
 
-        state.sqrt_price_x96 = next_sqrt_price_x96;
-        state.amount_specified_remaining -= step.amount_in;
-        state.amount_calculated += step.amount_out;
-
-        if state.sqrt_price_x96 == step.sqrt_price_next_x96 {
-            let mut liquidity_delta = cross(&*pool.tick_mapping.read().unwrap(), step.next_tick);
-
-            if zero_for_one {
-                liquidity_delta = -liquidity_delta;
-            }
-
-            state.liquidity += liquidity_delta;
+    if json {
+        return print_json(&found);
+    }
+    if found.is_empty() {
+        println!("No round trip covers its gas");
+    }
+    for o in &found {
+        let route: Vec<&str> = o.route
+            .iter()
+            .map(|leg| leg.pool.as_str())
+            .collect();
+        println!(
+            "{}: {} {} in, {} {} net profit after {} gas",
+            route.join(" -> "),
+            human(token_in, o.amount_in),
+            token_in.symbol,
+            human(token_in, o.net_profit),
+            token_in.symbol,
+            human(token_in, o.gas_cost)
+        );
+    }
+    Ok(())
+}
 
-            state.tick = step.next_tick;
-        } else {
-            state.tick = price_to_tick(state.sqrt_price_x96) as i32;
-        }
-        if *pool.liquidity.write().unwrap() != state.liquidity {
-            *pool.liquidity.write().unwrap() = state.liquidity;
+// [backtest] replays [events_path] into [pools_path], searching every pair trading the round trip's tokens.
+fn backtest(pools_path: &Path, events_path: &Path, options: &SearchOptions, json: bool) -> Result<(), Box<dyn Error>> {
+    let loaded = load_market(pools_path)?;
+    let log = load_events(events_path)?;
+    let token_in = find_token(&loaded, &options.token_in)?;
+    let token_mid = find_token(&loaded, &options.mid)?;
+
+    // profit is made in the starting token, so it is its own numeraire
+    let mut searcher = Searcher::new(
+        token_in.id,
+        Valuation::Reference(1.0),
+        options.gas(),
+        options.native_price,
+        options.slippage_tolerance
+    );
+    for k in 0..loaded.market.len() {
+        let id = PoolId(k);
+        searcher.add_pool(loaded.market.name(id).unwrap(), loaded.market.pool(id)?.shared());
+    }
+    let pools = pair_pools(&loaded, token_in, token_mid);
+    for (k, first) in pools.iter().enumerate() {
+        for second in &pools[k + 1..] {
+            searcher.add_pair(PoolPair {
+                first: *first,
+                second: *second,
+                token_in: token_in.id,
+                token_mid: token_mid.id,
+                max_amount_in: token_in.amount(options.max_amount).raw_f64(),
+            });
         }
     }
 
-    let mut pooltick = pool.tick.write().unwrap();
-    if state.tick != *pooltick {
-        *pooltick = state.tick;
-        *pool.sqrt_price_x96.write().unwrap() = state.sqrt_price_x96;
+    let report = run_backtest(&loaded.market, &mut searcher, &log)?;
+    if json {
+        return print_json(&report);
     }
-    let (amount0, amount1) = if zero_for_one {
-        (amount_specified - state.amount_specified_remaining, state.amount_calculated)
-    } else {
-        (state.amount_calculated, amount_specified - state.amount_specified_remaining)
-    };
+    print_backtest(&report, token_in);
+    Ok(())
+}
 
-    if zero_for_one {
-        *pool.balance_0.write().unwrap() += amount0;
-        *pool.balance_1.write().unwrap() -= amount1;
-    } else {
-        *pool.balance_0.write().unwrap() -= amount0;
-        *pool.balance_1.write().unwrap() += amount1;
-    }
-    if token_in == Token::Eth {
-        *trader.amt_eth.write().unwrap() -= amount0;
-        *trader.amt_dai.write().unwrap() += (1.0 - fee) * amount1;
-    } else {
-        *trader.amt_dai.write().unwrap() -= amount1;
-        *trader.amt_eth.write().unwrap() += (1.0 - fee) * amount0;
+// [print_backtest] describes a finished backtest in text, with amounts in whole units of [token].
+fn print_backtest(report: &BacktestReport, token: &Token) {
+    println!(
+        "Replayed {} logs over {} blocks ({} could not be replayed); the pool models were off the recorded outcome by {:.6} on average, {:.6} at most",
+        report.events,
+        report.blocks,
+        report.failed_events,
+        report.tracking.mean_abs(),
+        report.tracking.max_abs
+    );
+    for found in &report.opportunities {
+        let route: Vec<&str> = found.opportunity.route
+            .iter()
+            .map(|leg| leg.pool.as_str())
+            .collect();
+        println!(
+            "block {}: {}, {} {} net profit, {:?}{}",
+            found.block,
+            route.join(" -> "),
+            human(token, found.opportunity.net_value),
+            token.symbol,
+            found.outcome,
+            if found.counted { "" } else { " (still open from the block before)" }
+        );
     }
+    println!(
+        "{} opportunities: {} taken on chain in the next block, {} closed, {} persisted, {} open at the end of the log",
+        report.opportunities.len(),
+        report.count(Outcome::TakenOnChain),
+        report.count(Outcome::Closed),
+        report.count(Outcome::Persisted),
+        report.count(Outcome::Open)
+    );
+    println!("Cumulative PnL after gas: {} {}", human(token, report.total_pnl()), token.symbol);
 }
 
-struct Trader {
-    id: i32,
-    amt_eth: RwLock<f64>,
-    amt_dai: RwLock<f64>,
+/// The state of one pool, as printed by the `inspect` command. Balances are
+/// in whole tokens; V3 pools add their tick layout.
+#[derive(Serialize)]
+struct PoolView {
+    name: String,
+    kind: &'static str,
+    fee: f64,
+    version: u64,
+    balances: Vec<(String, f64)>,
+    // price of the second token per first token, for two-token pools
+    spot_price: Option<f64>,
+    v3: Option<V3View>,
 }
 
-fn calc_two_pool_arb_profit(
-    x_in: f64,
-    pool1: &UniswapV3Pool,
-    pool2: &UniswapV3Pool,
-    token_in: Token
-) -> f64 {
-    let pool1_copy = pool1.clone();
-    let pool2_copy = pool2.clone();
-    let mut example_trader = Trader {
-        id: 1,
-        amt_dai: RwLock::new(100.0),
-        amt_eth: RwLock::new(10000000000000.0),
-    };
-
-    let start_dai: f64  = 100.0;
-    let start_eth: f64 = 10000000000000.0;
-
-    if token_in == Token::Eth {
-        v3_swap(&mut example_trader, &pool1_copy, Token::Eth, x_in, 0.03);
-
-        let change = *example_trader.amt_dai.read().unwrap() - start_dai;
-
-        v3_swap(&mut example_trader, &pool2_copy, Token::Dai, change, 0.03);
-
-        // let profit = *example_trader.amt_eth.read().unwrap() - start_eth;
-    } else {
-        v3_swap(&mut example_trader, &pool1_copy, Token::Dai, x_in, 0.03);
-
-        let change = *example_trader.amt_eth.read().unwrap() - start_eth;
+#[derive(Serialize)]
+struct V3View {
+    tick: i32,
+    sqrt_price_x96: f64,
+    liquidity: f64,
+    ticks: Vec<TickLiquidity>,
+}
 
-        v3_swap(&mut example_trader, &pool2_copy, Token::Eth, change, 0.03);
+fn inspect(path: &Path, pool: Option<&str>, json: bool) -> Result<(), Box<dyn Error>> {
+    let loaded = load_market(path)?;
+    let token = |id| loaded.registry.get(id).unwrap();
+    let mut views = Vec::new();
+    for id in selected_pools(&loaded, pool)? {
+        let amm = loaded.market.amm(id)?;
+        let spot_price = match amm.tokens().as_slice() {
+            [a, b] => Some(to_human_price(amm.spot_price(*a, *b)?, token(*a).decimals, token(*b).decimals)),
+            _ => None,
+        };
+        let v3 = match loaded.market.pool(id)? {
+            MarketPool::V3(pool) => Some(V3View {
+                tick: pool.tick(),
+                sqrt_price_x96: pool.sqrt_price_x96(),
+                liquidity: pool.liquidity(),
+                ticks: pool.ticks(),
+            }),
+            _ => None,
+        };
+        views.push(PoolView {
+            name: loaded.market.name(id).unwrap().to_string(),
+            kind: loaded.config(id).unwrap().kind(),
+            fee: amm.fee(),
+            version: amm.version(),
+            balances: loaded
+                .holdings(id)
+                .into_iter()
+                .map(|(t, raw)| (token(t).symbol.clone(), human(token(t), raw)))
+                .collect(),
+            spot_price,
+            v3,
+        });
+    }
 
+    if json {
+        return print_json(&views);
     }
-    let profit = *example_trader.amt_eth.read().unwrap() - start_eth;
-    let profit = *example_trader.amt_dai.read().unwrap() - start_dai;
+    for view in &views {
+        let balances: Vec<String> = view.balances
+            .iter()
+            .map(|(symbol, amount)| format!("{} {}", amount, symbol))
+            .collect();
+        println!(
+            "{} ({}, fee {}%, version {}): {}",
+            view.name,
+            view.kind,
+            view.fee * 100.0,
+            view.version,
+            balances.join(", ")
+        );
+        if let Some(price) = view.spot_price {
+            println!("  spot price {}", price);
+        }
+        if let Some(v3) = &view.v3 {
+            let tokens = loaded.market.amm(loaded.pool_id(&view.name).unwrap())?.tokens();
+            println!("  tick {}, active liquidity {}", v3.tick, v3.liquidity);
+            print_tick_layout(&v3.ticks, v3.tick, token(tokens[0]), token(tokens[1]));
+        }
+    }
+    Ok(())
+}
 
-    return profit;
+// [print_tick_layout] lists the liquidity in force between each pair of initialized ticks, marking the range the price is in.
+fn print_tick_layout(ticks: &[TickLiquidity], current: i32, token_0: &Token, token_1: &Token) {
+    let mut liquidity = 0.0;
+    for pair in ticks.windows(2) {
+        liquidity += pair[0].liquidity_net;
+        let marker = if pair[0].tick <= current && current < pair[1].tick { "*" } else { " " };
+        println!(
+            "  {} [{}, {}) liquidity {} between {} and {} {} per {}",
+            marker,
+            pair[0].tick,
+            pair[1].tick,
+            liquidity,
+            tick_to_price(pair[0].tick, token_0.decimals, token_1.decimals),
+            tick_to_price(pair[1].tick, token_0.decimals, token_1.decimals),
+            token_1.symbol,
+            token_0.symbol
+        );
+    }
 }
 
-fn find_optimal_arb(
-    pool1: &UniswapV3Pool,
-    pool2: &UniswapV3Pool,
-    token_in: Token,
-    max_amt_in: f64
-) -> f64 {
-    let mut amt = 1.0;
-    let mut max_out = f64::MIN;
-    let mut opt_amt = 0.0;
-    while amt <= max_amt_in {
-        let amt_out = calc_two_pool_arb_profit(amt, pool1, pool2, token_in);
-        if amt_out > max_out {
-            max_out = amt_out;
-            opt_amt = amt;
-        }
-        amt += 100.0;
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    match cli.command {
+        Command::Simulate { scenario, seed, blocks, opportunities, journal } =>
+            simulate(&scenario, seed, blocks, opportunities.as_deref(), journal.as_deref(), cli.json),
+        Command::Replay { journal } => replay(&journal, cli.json),
+        Command::Quote { pool_file, token_in, token_out, amount, pool } =>
+            quote(&pool_file, &token_in, token_out.as_deref(), amount, pool.as_deref(), cli.json),
+        Command::Search { pools_file, options } => search(&pools_file, &options, cli.json),
+        Command::Backtest { pools_file, events_file, options } =>
+            backtest(&pools_file, &events_file, &options, cli.json),
+        Command::Inspect { pool_file, pool } => inspect(&pool_file, pool.as_deref(), cli.json),
+        Command::Fetch { address, block, rpc_url, out } => fetch(&address, block, &rpc_url, out.as_deref()),
     }
-    opt_amt
 }
 
 fn main() {
-    let trader = Trader {
-        id: 1,
-        amt_eth: RwLock::new(2000.0),
-        amt_dai: RwLock::new(10000.0),
-    };
-
-    let mut pool1 = UniswapV3Pool {
-        liquidity: RwLock::new(0.0),
-        max_tick: math::get_max_tick(),
-        min_tick: math::get_min_tick(),
-        position_mapping: RwLock::new(HashMap::new()),
-        tick_mapping: RwLock::new(HashMap::new()),
-        liquidity_mapping: RwLock::new(HashMap::new()),
-        sqrt_price_x96: RwLock::new(5602277097478614198912276234240.0),
-        tick: RwLock::new(85176),
-        token_0: Token::Eth,
-        token_1: Token::Dai,
-        balance_0: RwLock::new(0.0),
-        balance_1: RwLock::new(0.0),
-    };
-
-    let mut pool2 = UniswapV3Pool {
-        liquidity: RwLock::new(0.0),
-        max_tick: math::get_max_tick(),
-        min_tick: math::get_min_tick(),
-        position_mapping: RwLock::new(HashMap::new()),
-        tick_mapping: RwLock::new(HashMap::new()),
-        liquidity_mapping: RwLock::new(HashMap::new()),
-        sqrt_price_x96: RwLock::new(5602277097478614198912276234240.0),
-        tick: RwLock::new(85176),
-        token_0: Token::Eth,
-        token_1: Token::Dai,
-        balance_0: RwLock::new(0.0),
-        balance_1: RwLock::new(0.0),
-    };
-
-    pool1.mint(&trader, -86000, 86000, 100000000000000.0);
-    pool2.mint(&trader, -86000, 86000, 1000000000000000000.0);
-
-    let safepool1 = Arc::new(RwLock::new(pool1));
-    let safepool2 = Arc::new(RwLock::new(pool2));
-
-    let viewpool1 = Arc::clone(&safepool1);
-    let viewpool2 = Arc::clone(&safepool2);
-
-    let mut handles = vec![];
-
-    let writer = thread::spawn(move || {
-        for _ in 0..20 {
-            let mut rng = rand::thread_rng();
-            let randomness = rng.gen_range(0..10);
-
-            if randomness > 5 {
-                *&Arc::clone(&safepool1).write().unwrap().mint(&trader, -86000, 86000, 20000.0);
-                *&Arc::clone(&safepool2).write().unwrap().mint(&trader, -86000, 86000, 20000.0);
-            } else {
-                *&Arc::clone(&safepool1).write().unwrap().mint(&trader, -86000, 86000, -10000.0);
-                *&Arc::clone(&safepool2).write().unwrap().mint(&trader, -86000, 86000, -10000.0);
-            }
-        }
-        thread::sleep(Duration::from_millis(1000));
-    });
-
-    let searcher = thread::spawn(move || {
-        for _ in 0..10 {
-            let b1 = find_optimal_arb(
-                &Arc::clone(&viewpool1).read().unwrap(),
-                &Arc::clone(&viewpool2).read().unwrap(),
-                Token::Eth,
-                1000000.0
-            );
-            let b2 = find_optimal_arb(
-                &Arc::clone(&viewpool2).read().unwrap(),
-                &Arc::clone(&viewpool1).read().unwrap(),
-                Token::Eth,
-                1000000.0
-            );
-
-            println!(
-                "Profit from sending {:?}, {:?}",
-                b1,
-                calc_two_pool_arb_profit(
-                    b1,
-                    &Arc::clone(&viewpool1).read().unwrap(),
-                    &Arc::clone(&viewpool2).read().unwrap(),
-                    Token::Eth
-                )
-            );
-            println!(
-                "Profit from sending {:?}, {:?}",
-                b2,
-                calc_two_pool_arb_profit(
-                    b1,
-                    &Arc::clone(&viewpool2).read().unwrap(),
-                    &Arc::clone(&viewpool1).read().unwrap(),
-                    Token::Eth
-                )
-            );
-            thread::sleep(Duration::from_millis(2000));
-        }
-    });
-
-    handles.push(writer);
-    handles.push(searcher);
-    for i in handles {
-        i.join().unwrap();
+    dotenv().ok();
+    if let Err(e) = run(Cli::parse()) {
+        eprintln!("error: {}", e);
+        process::exit(1);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io;
 
-    fn set_up_pool(
-        mint: bool,
-        lower_tick: i32,
-        upper_tick: i32,
-        liquidity: f64
-    ) -> (Trader, UniswapV3Pool) {
-        let trader = Trader {
-            id: 2,
-            amt_eth: RwLock::new(10000000000.0),
-            amt_dai: RwLock::new(10000000000.0),
-        };
-        let mut pool = UniswapV3Pool {
-            liquidity: RwLock::new(0.0),
-            max_tick: math::get_max_tick(),
-            min_tick: math::get_min_tick(),
-            position_mapping: RwLock::new(HashMap::new()),
-            tick_mapping: RwLock::new(HashMap::new()),
-            liquidity_mapping: RwLock::new(HashMap::new()),
-            sqrt_price_x96: RwLock::new(5602277097478614198912276234240.0),
-            tick: RwLock::new(85176),
-            token_0: Token::Eth,
-            token_1: Token::Dai,
-            balance_0: RwLock::new(0.0),
-            balance_1: RwLock::new(0.0),
-        };
-        if mint {
-            pool.mint(&trader, lower_tick, upper_tick, liquidity);
-        }
-
-        (trader, pool)
+    // [run_demo] plays a built-in scenario from [seed] and returns its report.
+    fn run_demo(name: &str, seed: u64, sink: &mut dyn OpportunitySink) -> ScenarioReport {
+        let mut scenario = load_scenario(name).unwrap();
+        scenario.seed = seed;
+        run_scenario(&scenario, None, sink).unwrap()
     }
 
     #[test]
-    fn price_to_sqrt_price() {
-        assert_eq!(price_to_sqrtp(5000.0), 5.602277097478614e30);
+    fn v3_demo_searches_on_every_update() {
+        let report = run_demo("v3", 42, &mut JsonLinesSink::new(io::sink()));
+        assert_eq!(report.blocks, 20);
+        assert_eq!(report.agents.len(), 5);
+        assert_eq!(report.lags.len(), 2);
+        let search = report.search.unwrap();
+        assert!(search.events > 0);
+        assert_eq!(search.failures, 0);
+        assert_eq!(search.to_evaluation.count, search.evaluations);
+        assert_eq!(search.to_opportunity.count, search.opportunities);
     }
 
     #[test]
-    fn v3_test_mint() {
-        let trader = Trader {
-            id: 2,
-            amt_eth: RwLock::new(2000.0),
-            amt_dai: RwLock::new(10000.0),
-        };
-        let mut pool = UniswapV3Pool {
-            liquidity: RwLock::new(0.0),
-            max_tick: math::get_max_tick(),
-            min_tick: math::get_min_tick(),
-            position_mapping: RwLock::new(HashMap::new()),
-            tick_mapping: RwLock::new(HashMap::new()),
-            liquidity_mapping: RwLock::new(HashMap::new()),
-            sqrt_price_x96: RwLock::new(5602277097478614198912276234240.0),
-            tick: RwLock::new(85176),
-            token_0: Token::Eth,
-            token_1: Token::Dai,
-            balance_0: RwLock::new(0.0),
-            balance_1: RwLock::new(0.0),
-        };
-
-        pool.mint(&trader, 84222, 86129, 1517882343751509868544.0);
-
-        assert_eq!(*pool.sqrt_price_x96.read().unwrap(), 5602277097478614198912276234240.0);
-    }
-    #[test]
-    fn v3_test_remove() {
-        let trader = Trader {
-            id: 2,
-            amt_eth: RwLock::new(2000.0),
-            amt_dai: RwLock::new(10000.0),
-        };
-        let mut pool = UniswapV3Pool {
-            liquidity: RwLock::new(0.0),
-            max_tick: math::get_max_tick(),
-            min_tick: math::get_min_tick(),
-            position_mapping: RwLock::new(HashMap::new()),
-            tick_mapping: RwLock::new(HashMap::new()),
-            liquidity_mapping: RwLock::new(HashMap::new()),
-            sqrt_price_x96: RwLock::new(5602277097478614198912276234240.0),
-            tick: RwLock::new(85176),
-            token_0: Token::Eth,
-            token_1: Token::Dai,
-            balance_0: RwLock::new(0.0),
-            balance_1: RwLock::new(0.0),
-        };
-
-        pool.mint(&trader, 84222, 86129, 1517882343751509868544.0);
-
-        let liq = *pool.liquidity.read().unwrap();
-
-        assert_eq!(liq, 1517882343751509868544.0);
-
-        pool.mint(&trader, 84222, 86129, -1517882343751509868544.0);
-
-        assert_eq!(*pool.sqrt_price_x96.read().unwrap(), 5602277097478614198912276234240.0);
-        let new_liquidity = *pool.liquidity.read().unwrap();
-        assert_eq!(new_liquidity, 0.0)
+    fn v2_demo_sends_every_opportunity() {
+        let (mut sink, found) = ChannelSink::new();
+        let report = run_demo("v2", 42, &mut sink);
+        let search = report.search.unwrap();
+        assert!(search.opportunities > 0);
+        assert_eq!(found.try_iter().count(), search.opportunities);
     }
 
     #[test]
-    fn test_swap_eth() {
-        let (mut trader, pool) = set_up_pool(true, -86000, 86000, 100000000000.0);
-        let original = *trader.amt_eth.read().unwrap();
-        let og_dai = *trader.amt_dai.read().unwrap();
-
-        v3_swap(&mut trader, &pool, Token::Eth, 1000000.0, 0.03);
-
-        let post = *trader.amt_eth.read().unwrap();
-        let post_dai = *trader.amt_dai.read().unwrap();
-
-        // assert_eq!(original > post, false);
-        // assert_eq!(post_dai > og_dai, false);
+    fn demos_replay_from_their_seed() {
+        let run = |seed: u64| {
+            let mut sink = JsonLinesSink::new(Vec::new());
+            let metrics = run_demo("v2", seed, &mut sink).search.unwrap();
+            (sink.into_inner(), metrics.events, metrics.evaluations, metrics.opportunities)
+        };
 
-        assert_eq!(original > post, true);
-        assert_eq!(post_dai > og_dai, true);
+        let first = run(7);
+        assert!(first.3 > 0);
+        assert_eq!(first, run(7));
     }
 
     #[test]
-    fn test_swap_dai() {
-        let (mut trader, pool) = set_up_pool(true, -86000, 86000, 10000000000000.0);
-        let original = *trader.amt_eth.read().unwrap();
-        let og_dai = *trader.amt_dai.read().unwrap();
-
-        v3_swap(&mut trader, &pool, Token::Dai, 100.0, 0.03);
-
-        let post = *trader.amt_eth.read().unwrap();
-        let post_dai = *trader.amt_dai.read().unwrap();
-
-        assert_eq!(original < post, true);
-        assert_eq!(post_dai < og_dai, true);
+    fn commands_parse() {
+        let cli = Cli::try_parse_from(["rusty-arb", "quote", "pools.toml", "--in", "WETH", "--amount", "1.5", "--json"]);
+        let cli = cli.unwrap();
+        assert!(cli.json);
+        assert!(matches!(cli.command, Command::Quote { amount, ref token_in, .. } if amount == 1.5 && token_in == "WETH"));
+        // --mid and --max-amount are required
+        assert!(Cli::try_parse_from(["rusty-arb", "search", "pools.toml", "--in", "WETH"]).is_err());
+        let cli = Cli::try_parse_from(["rusty-arb", "backtest", "pools.toml", "logs.csv", "--in", "WETH", "--mid", "DAI", "--max-amount", "10"]);
+        assert!(matches!(cli.unwrap().command, Command::Backtest { options, .. } if options.mid == "DAI" && options.max_amount == 10.0));
+        let cli = Cli::try_parse_from(["rusty-arb", "simulate", "v2", "--journal", "run.jsonl"]).unwrap();
+        assert!(matches!(cli.command, Command::Simulate { journal: Some(ref path), .. } if path == Path::new("run.jsonl")));
+        let cli = Cli::try_parse_from(["rusty-arb", "fetch", "0x8ad5", "--block", "19000000", "--rpc-url", "http://localhost:8545"]);
+        assert!(matches!(cli.unwrap().command, Command::Fetch { block: Some(19000000), out: None, .. }));
+        assert_eq!(load_scenario("v2").unwrap().seed, 42);
     }
 
     #[test]
-    fn benchmark_search_for_arb() {
-        main()
+    fn quote_rejects_bad_amounts() {
+        let pools = Path::new("scenarios/chain.toml");
+        for amount in [0.0, -1.0, f64::NAN] {
+            let error = quote(pools, "WETH", None, amount, None, true).unwrap_err();
+            assert!(error.to_string().starts_with("--amount must be a positive number"));
+        }
     }
 }
@@ -0,0 +1,301 @@
+use crate::amm::Amm;
+use crate::error::PoolError;
+use crate::events::{EventBus, PoolId, Publisher};
+use crate::ledger::{Account, EntryReason};
+use crate::searcher::SharedPool;
+use crate::token::TokenId;
+use crate::v2::{add, remove, Pool};
+use crate::v3::UniswapV3Pool;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A pool in a [`Market`]. V2 and V3 pools keep their concrete type so that
+/// liquidity can be added to them; any other pool can only be traded.
+#[derive(Clone)]
+pub enum MarketPool {
+    V2(Arc<Pool>),
+    V3(Arc<UniswapV3Pool>),
+    Other(SharedPool),
+}
+
+impl MarketPool {
+    pub fn amm(&self) -> &(dyn Amm + Send + Sync) {
+        match self {
+            MarketPool::V2(pool) => &**pool,
+            MarketPool::V3(pool) => &**pool,
+            MarketPool::Other(pool) => &**pool,
+        }
+    }
+
+    pub fn shared(&self) -> SharedPool {
+        match self {
+            MarketPool::V2(pool) => pool.clone(),
+            MarketPool::V3(pool) => pool.clone(),
+            MarketPool::Other(pool) => pool.clone(),
+        }
+    }
+}
+
+/// Something a market participant wants done, on behalf of its own account.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    Swap {
+        pool: PoolId,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64,
+        min_amount_out: f64,
+    },
+    // adds reserves to a V2 pool, which tracks no shares
+    Deposit {
+        pool: PoolId,
+        amount_x: f64,
+        amount_y: f64,
+    },
+    // takes reserves out of a V2 pool, up to what the account deposited
+    Withdraw {
+        pool: PoolId,
+        amount_x: f64,
+        amount_y: f64,
+    },
+    // changes liquidity in a V3 range, burning it when negative
+    Mint {
+        pool: PoolId,
+        lower_tick: i32,
+        upper_tick: i32,
+        liquidity_delta: f64,
+    },
+}
+
+impl Action {
+    pub fn pool(&self) -> PoolId {
+        match self {
+            Action::Swap { pool, .. } => *pool,
+            Action::Deposit { pool, .. } => *pool,
+            Action::Withdraw { pool, .. } => *pool,
+            Action::Mint { pool, .. } => *pool,
+        }
+    }
+}
+
+/// Pools that trade on one event bus, plus reference prices from outside
+/// the pools, e.g. a centralized exchange.
+pub struct Market {
+    bus: Arc<EventBus>,
+    pools: Vec<(String, MarketPool)>,
+    reference_prices: HashMap<(TokenId, TokenId), f64>,
+    // reserves each account has deposited into each V2 pool and not yet withdrawn
+    deposits: Mutex<HashMap<(PoolId, i32), (f64, f64)>>,
+}
+
+impl Default for Market {
+    fn default() -> Market {
+        Market::new()
+    }
+}
+
+impl Market {
+    pub fn new() -> Market {
+        Market {
+            bus: EventBus::new(),
+            pools: Vec::new(),
+            reference_prices: HashMap::new(),
+            deposits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Carries the change events of every pool added to the market.
+    pub fn bus(&self) -> &Arc<EventBus> {
+        &self.bus
+    }
+
+    /// The id the next pool added will get.
+    pub fn next_id(&self) -> PoolId {
+        PoolId(self.pools.len())
+    }
+
+    pub fn add_v2(&mut self, name: &str, pool: Pool) -> PoolId {
+        let pool = pool.with_events(Publisher::new(&self.bus, self.next_id()));
+        self.push(name, MarketPool::V2(Arc::new(pool)))
+    }
+
+    pub fn add_v3(&mut self, name: &str, pool: UniswapV3Pool) -> PoolId {
+        let pool = pool.with_events(Publisher::new(&self.bus, self.next_id()));
+        self.push(name, MarketPool::V3(Arc::new(pool)))
+    }
+
+    /// Adds a pool that can only be traded. It should already publish to `bus` under `next_id`.
+    pub fn add_pool(&mut self, name: &str, pool: SharedPool) -> PoolId {
+        self.push(name, MarketPool::Other(pool))
+    }
+
+    fn push(&mut self, name: &str, pool: MarketPool) -> PoolId {
+        let id = self.next_id();
+        self.pools.push((name.to_string(), pool));
+        id
+    }
+
+    pub fn len(&self) -> usize {
+        self.pools.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty()
+    }
+
+    pub fn pool(&self, id: PoolId) -> Result<&MarketPool, PoolError> {
+        self.pools
+            .get(id.0)
+            .map(|(_, pool)| pool)
+            .ok_or(PoolError::InvalidParameter { name: "pool", value: id.0 as f64 })
+    }
+
+    pub fn amm(&self, id: PoolId) -> Result<&(dyn Amm + Send + Sync), PoolError> {
+        Ok(self.pool(id)?.amm())
+    }
+
+    pub fn name(&self, id: PoolId) -> Option<&str> {
+        self.pools.get(id.0).map(|(name, _)| name.as_str())
+    }
+
+    /// Records that one raw unit of `base` is worth `price` raw units of `quote`.
+    pub fn set_reference_price(&mut self, base: TokenId, quote: TokenId, price: f64) {
+        self.reference_prices.insert((base, quote), price);
+    }
+
+    /// Lists every reference price set, ordered by pair.
+    pub fn reference_prices(&self) -> Vec<((TokenId, TokenId), f64)> {
+        let mut prices: Vec<((TokenId, TokenId), f64)> = self.reference_prices
+            .iter()
+            .map(|(pair, price)| (*pair, *price))
+            .collect();
+        prices.sort_by_key(|(pair, _)| *pair);
+        prices
+    }
+
+    /// The raw amount of `quote` worth one raw unit of `base`, derived from the
+    /// inverse pair if only that was set.
+    pub fn reference_price(&self, base: TokenId, quote: TokenId) -> Option<f64> {
+        self.reference_prices
+            .get(&(base, quote))
+            .copied()
+            .or_else(|| self.reference_prices.get(&(quote, base)).map(|p| 1.0 / p))
+    }
+
+    /// How far the mid price of pool `id` is from the reference price, relative to the reference.
+    pub fn deviation(&self, id: PoolId, base: TokenId, quote: TokenId) -> Option<f64> {
+        let reference = self.reference_price(base, quote)?;
+        let mid = self.amm(id).ok()?.spot_price(base, quote).ok()?;
+        Some(mid / reference - 1.0)
+    }
+
+    /// Carries out `action` for `account`, moving its tokens through the ledger.
+    pub fn execute(&self, account: &mut Account, action: &Action) -> Result<(), PoolError> {
+        match (self.pool(action.pool())?, action) {
+            (pool, Action::Swap { token_in, token_out, amount_in, min_amount_out, .. }) => {
+                pool.amm().apply_swap(account, *token_in, *token_out, *amount_in, *min_amount_out)?;
+            }
+            (MarketPool::V2(pool), Action::Deposit { pool: id, amount_x, amount_y }) => {
+                check_amounts(*amount_x, *amount_y)?;
+                account.ensure(pool.token_x(), *amount_x)?;
+                account.ensure(pool.token_y(), *amount_y)?;
+                account.debit(pool.token_x(), *amount_x, EntryReason::Mint)?;
+                account.debit(pool.token_y(), *amount_y, EntryReason::Mint)?;
+                add(pool, *amount_x, *amount_y)?;
+                let mut deposits = self.deposits.lock().unwrap();
+                let deposit = deposits.entry((*id, account.id)).or_insert((0.0, 0.0));
+                deposit.0 += amount_x;
+                deposit.1 += amount_y;
+            }
+            (MarketPool::V2(pool), Action::Withdraw { pool: id, amount_x, amount_y }) => {
+                check_amounts(*amount_x, *amount_y)?;
+                let mut deposits = self.deposits.lock().unwrap();
+                let deposit = deposits
+                    .get_mut(&(*id, account.id))
+                    .filter(|(x, y)| *amount_x <= *x && *amount_y <= *y)
+                    .ok_or(PoolError::InsufficientLiquidity)?;
+                remove(pool, *amount_x, *amount_y)?;
+                deposit.0 -= amount_x;
+                deposit.1 -= amount_y;
+                drop(deposits);
+                account.credit(pool.token_x(), *amount_x, EntryReason::Burn)?;
+                account.credit(pool.token_y(), *amount_y, EntryReason::Burn)?;
+            }
+            (MarketPool::V3(pool), Action::Mint { lower_tick, upper_tick, liquidity_delta, .. }) => {
+                pool.mint(account, *lower_tick, *upper_tick, *liquidity_delta)?;
+            }
+            (_, action) => {
+                return Err(PoolError::InvalidParameter {
+                    name: "pool",
+                    value: action.pool().0 as f64,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// [check_amounts] verifies that both sides of a deposit or withdrawal are positive and finite.
+fn check_amounts(amount_x: f64, amount_y: f64) -> Result<(), PoolError> {
+    for (name, amount) in [("amount_x", amount_x), ("amount_y", amount_y)] {
+        if !amount.is_finite() || amount <= 0.0 {
+            return Err(PoolError::InvalidParameter { name, value: amount });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::{check_conservation, totals, Holdings};
+
+    const WETH: TokenId = TokenId(0);
+    const DAI: TokenId = TokenId(1);
+
+    #[test]
+    fn actions_move_tokens_through_the_ledger() {
+        let mut market = Market::new();
+        let events = market.bus().subscribe();
+        let cp = market.add_v2("cp", Pool::new(WETH, DAI, 100.0, 200000.0, 0.003));
+        let mut lp = Account::new(1, &[(WETH, 10.0), (DAI, 20000.0)]);
+        let MarketPool::V2(pool) = market.pool(cp).unwrap().clone() else {
+            panic!("added as V2");
+        };
+        let supply = totals(&[&lp, &*pool as &dyn Holdings]);
+
+        market.execute(&mut lp, &Action::Deposit { pool: cp, amount_x: 10.0, amount_y: 20000.0 }).unwrap();
+        market.execute(&mut lp, &Action::Withdraw { pool: cp, amount_x: 5.0, amount_y: 10000.0 }).unwrap();
+        let swap = Action::Swap { pool: cp, token_in: WETH, token_out: DAI, amount_in: 1.0, min_amount_out: 0.0 };
+        market.execute(&mut lp, &swap).unwrap();
+
+        assert_eq!(pool.reserves().0, 106.0);
+        check_conservation(&supply, &[&lp, &*pool]).unwrap();
+        assert_eq!(events.try_iter().count(), 3);
+
+        let mint = Action::Mint { pool: cp, lower_tick: 0, upper_tick: 10, liquidity_delta: 1.0 };
+        assert_eq!(
+            market.execute(&mut lp, &mint),
+            Err(PoolError::InvalidParameter { name: "pool", value: 0.0 })
+        );
+
+        // withdrawals are limited to what the account deposited and still holds in the pool
+        let withdraw = |amount_x, amount_y| Action::Withdraw { pool: cp, amount_x, amount_y };
+        assert_eq!(market.execute(&mut lp, &withdraw(6.0, 1.0)), Err(PoolError::InsufficientLiquidity));
+        let mut other = Account::new(2, &[]);
+        assert_eq!(market.execute(&mut other, &withdraw(1.0, 1.0)), Err(PoolError::InsufficientLiquidity));
+        assert_eq!(
+            market.execute(&mut lp, &withdraw(-1.0, 1.0)),
+            Err(PoolError::InvalidParameter { name: "amount_x", value: -1.0 })
+        );
+        let deposit = Action::Deposit { pool: cp, amount_x: 1.0, amount_y: f64::NAN };
+        assert!(matches!(market.execute(&mut lp, &deposit), Err(PoolError::InvalidParameter { name: "amount_y", .. })));
+        assert_eq!(pool.reserves().0, 106.0);
+        market.set_reference_price(WETH, DAI, 2000.0);
+        assert_eq!(market.reference_price(DAI, WETH), Some(0.0005));
+        let deviation = market.deviation(cp, WETH, DAI).unwrap();
+        assert!((deviation - (pool.spot_price(WETH, DAI).unwrap() / 2000.0 - 1.0)).abs() < 1e-12);
+    }
+}
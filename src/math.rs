@@ -1,13 +1,216 @@
-const base: f64 = 2.0;
+use crate::amount::{to_human_price, to_raw_price};
 
-const min_tick: i32 = -887272;
+const BASE: f64 = 2.0;
+
+const TICK_BASE: f64 = 1.0001;
+
+const MIN_TICK: i32 = -887272;
 
 pub fn get_min_tick() -> i32 {
-    min_tick
+    MIN_TICK
 }
 pub fn get_max_tick() -> i32 {
-    -min_tick
+    -MIN_TICK
 }
 pub fn get_q96() -> f64 {
-    base.powf(96.0)
+    BASE.powf(96.0)
+}
+pub fn get_tick_base() -> f64 {
+    TICK_BASE
+}
+
+// Prices passed to and returned from the helpers below are human prices (token1 per token0);
+// they are scaled by the token decimals before touching ticks or sqrt prices.
+pub fn price_to_tick(price: f64, decimals_0: u8, decimals_1: u8) -> i32 {
+    raw_price_to_tick(to_raw_price(price, decimals_0, decimals_1))
+}
+
+pub fn tick_to_price(tick: i32, decimals_0: u8, decimals_1: u8) -> f64 {
+    to_human_price(get_tick_base().powi(tick), decimals_0, decimals_1)
+}
+
+pub fn price_to_sqrtp(price: f64, decimals_0: u8, decimals_1: u8) -> f64 {
+    to_raw_price(price, decimals_0, decimals_1).sqrt() * get_q96()
+}
+
+pub fn sqrtp_to_price(sqrt_price_x96: f64, decimals_0: u8, decimals_1: u8) -> f64 {
+    let sqrt_price = sqrt_price_x96 / get_q96();
+    to_human_price(sqrt_price * sqrt_price, decimals_0, decimals_1)
+}
+
+pub fn raw_price_to_tick(raw_price: f64) -> i32 {
+    raw_price.log(get_tick_base()).floor() as i32
+}
+
+pub fn sqrtp_to_tick(sqrt_price_x96: f64) -> i32 {
+    let sqrt_price = sqrt_price_x96 / get_q96();
+    raw_price_to_tick(sqrt_price * sqrt_price)
+}
+
+pub fn tick_to_sqrtp(tick: i32) -> f64 {
+    let num: f64 = get_tick_base().powi(tick);
+    num.sqrt() * get_q96()
+}
+
+pub fn calc_amount0(liq: f64, lower_tick: f64, upper_tick: f64) -> f64 {
+    let q96 = get_q96();
+    if upper_tick > lower_tick {
+        (liq * q96 * (upper_tick - lower_tick)) / lower_tick / upper_tick
+    } else {
+        (liq * q96 * (lower_tick - upper_tick)) / upper_tick / lower_tick
+    }
+}
+
+pub fn calc_amount1(liq: f64, lower_tick: f64, upper_tick: f64) -> f64 {
+    let q96 = get_q96();
+    if upper_tick > lower_tick {
+        (liq * (upper_tick - lower_tick)) / q96
+    } else {
+        (liq * (lower_tick - upper_tick)) / q96
+    }
+}
+
+pub fn get_next_sqrt_price_from_input(
+    sqrt_price_current_x96: f64,
+    liquidity: f64,
+    amount_remaining: f64,
+    zero_for_one: bool
+) -> f64 {
+    let q96 = get_q96();
+    if zero_for_one {
+        (liquidity * q96 * sqrt_price_current_x96) /
+            (liquidity * q96 + amount_remaining * sqrt_price_current_x96)
+    } else {
+        sqrt_price_current_x96 + (amount_remaining * q96) / liquidity
+    }
+}
+
+/// The sqrt price reached after `amount_out` leaves the pool, the inverse of the
+/// amount formulas above.
+pub fn get_next_sqrt_price_from_output(
+    sqrt_price_current_x96: f64,
+    liquidity: f64,
+    amount_out: f64,
+    zero_for_one: bool
+) -> f64 {
+    let q96 = get_q96();
+    if zero_for_one {
+        sqrt_price_current_x96 - (amount_out * q96) / liquidity
+    } else {
+        (liquidity * q96 * sqrt_price_current_x96) /
+            (liquidity * q96 - amount_out * sqrt_price_current_x96)
+    }
+}
+
+pub fn compute_swap_step(
+    sqrt_price_current_x96: f64,
+    sqrt_price_target_x96: f64,
+    liquidity: f64,
+    amount_remaining: f64
+) -> (f64, f64, f64) {
+    let zero_for_one = sqrt_price_current_x96 >= sqrt_price_target_x96;
+
+    let amount_in_pre_calc = if zero_for_one {
+        calc_amount0(liquidity, sqrt_price_current_x96, sqrt_price_target_x96)
+    } else {
+        calc_amount1(liquidity, sqrt_price_current_x96, sqrt_price_target_x96)
+    };
+
+    if amount_remaining < amount_in_pre_calc {
+        let sqrt_price_next_x96 = get_next_sqrt_price_from_input(
+            sqrt_price_current_x96,
+            liquidity,
+            amount_remaining,
+            zero_for_one
+        );
+        let amount_out = if zero_for_one {
+            calc_amount1(liquidity, sqrt_price_current_x96, sqrt_price_next_x96)
+        } else {
+            calc_amount0(liquidity, sqrt_price_current_x96, sqrt_price_next_x96)
+        };
+        return (sqrt_price_next_x96, amount_remaining, amount_out);
+    }
+
+    let amount0 = calc_amount0(liquidity, sqrt_price_current_x96, sqrt_price_target_x96);
+    let amount1 = calc_amount1(liquidity, sqrt_price_current_x96, sqrt_price_target_x96);
+
+    if zero_for_one {
+        (sqrt_price_target_x96, amount0, amount1)
+    } else {
+        (sqrt_price_target_x96, amount1, amount0)
+    }
+}
+
+/// Mirrors `compute_swap_step` when the output amount is fixed. Returns (next sqrt
+/// price, amount in, amount out).
+pub fn compute_swap_step_exact_out(
+    sqrt_price_current_x96: f64,
+    sqrt_price_target_x96: f64,
+    liquidity: f64,
+    amount_remaining: f64
+) -> (f64, f64, f64) {
+    let zero_for_one = sqrt_price_current_x96 >= sqrt_price_target_x96;
+
+    let amount_out_pre_calc = if zero_for_one {
+        calc_amount1(liquidity, sqrt_price_current_x96, sqrt_price_target_x96)
+    } else {
+        calc_amount0(liquidity, sqrt_price_current_x96, sqrt_price_target_x96)
+    };
+
+    if amount_remaining < amount_out_pre_calc {
+        let sqrt_price_next_x96 = get_next_sqrt_price_from_output(
+            sqrt_price_current_x96,
+            liquidity,
+            amount_remaining,
+            zero_for_one
+        );
+        let amount_in = if zero_for_one {
+            calc_amount0(liquidity, sqrt_price_current_x96, sqrt_price_next_x96)
+        } else {
+            calc_amount1(liquidity, sqrt_price_current_x96, sqrt_price_next_x96)
+        };
+        return (sqrt_price_next_x96, amount_in, amount_remaining);
+    }
+
+    let amount0 = calc_amount0(liquidity, sqrt_price_current_x96, sqrt_price_target_x96);
+    let amount1 = calc_amount1(liquidity, sqrt_price_current_x96, sqrt_price_target_x96);
+
+    if zero_for_one {
+        (sqrt_price_target_x96, amount0, amount1)
+    } else {
+        (sqrt_price_target_x96, amount1, amount0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn price_to_sqrt_price() {
+        assert_eq!(price_to_sqrtp(5000.0, 18, 18), 5.602277097478614e30);
+    }
+
+    #[test]
+    fn exact_out_step_inverts_exact_in() {
+        let sqrtp = price_to_sqrtp(5000.0, 18, 18);
+        let target = tick_to_sqrtp(84000);
+        let (next, amount_in, amount_out) = compute_swap_step(sqrtp, target, 1e12, 1000.0);
+        let (next_out, amount_in_out, _) = compute_swap_step_exact_out(sqrtp, target, 1e12, amount_out);
+
+        assert!((next_out - next).abs() / next < 1e-12);
+        assert!((amount_in_out - amount_in).abs() < 1e-6);
+    }
+
+    #[test]
+    fn prices_across_decimals() {
+        // WETH (18 decimals) as token0 priced in USDC (6 decimals)
+        let sqrtp = price_to_sqrtp(2000.0, 18, 6);
+        let tick = price_to_tick(2000.0, 18, 6);
+
+        assert_eq!(tick, -200312);
+        assert_eq!(sqrtp_to_tick(sqrtp), tick);
+        assert!((sqrtp_to_price(sqrtp, 18, 6) - 2000.0).abs() < 1e-6);
+        assert!((tick_to_price(tick, 18, 6) - 2000.0).abs() < 2000.0 * 0.0001);
+    }
 }
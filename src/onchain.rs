@@ -0,0 +1,361 @@
+use crate::math::{calc_amount0, calc_amount1, get_max_tick, sqrtp_to_tick, tick_to_sqrtp};
+use crate::snapshot::SnapshotError;
+use crate::token::Token;
+use crate::v3::{TickLiquidity, UniswapV3Pool, V3Snapshot};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fs;
+use std::path::Path;
+
+// on chain, fees are counted in hundredths of a basis point
+const FEE_UNITS: f64 = 1_000_000.0;
+
+/// A big integer as dumps write it: a JSON number, or a decimal or
+/// `0x`-prefixed hex string for values beyond what JSON numbers hold.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Quantity {
+    Text(String),
+    Number(f64),
+}
+
+impl Quantity {
+    fn to_f64(&self) -> Result<f64, String> {
+        match self {
+            Quantity::Number(value) => Ok(*value),
+            Quantity::Text(text) => {
+                let text = text.trim();
+                if let Some(hex) = text.strip_prefix("0x") {
+                    if hex.is_empty() {
+                        return Err(format!("{:?} is not a number", text));
+                    }
+                    hex.chars().try_fold(0.0, |value, c| {
+                        c.to_digit(16)
+                            .map(|digit| value * 16.0 + digit as f64)
+                            .ok_or_else(|| format!("{:?} is not a number", text))
+                    })
+                } else {
+                    text.parse().map_err(|_| format!("{:?} is not a number", text))
+                }
+            }
+        }
+    }
+
+    fn to_i64(&self) -> Result<i64, String> {
+        match self {
+            Quantity::Text(text) if !text.starts_with("0x") =>
+                text.trim().parse().map_err(|_| format!("{:?} is not an integer", text)),
+            _ => {
+                let value = self.to_f64()?;
+                if value.fract() != 0.0 || value.abs() > i64::MAX as f64 {
+                    return Err(format!("{} is not an integer", value));
+                }
+                Ok(value as i64)
+            }
+        }
+    }
+}
+
+fn number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    Quantity::deserialize(deserializer)?.to_f64().map_err(serde::de::Error::custom)
+}
+
+fn integer<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    Quantity::deserialize(deserializer)?.to_i64().map_err(serde::de::Error::custom)
+}
+
+fn optional_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    Option::<Quantity>::deserialize(deserializer)?
+        .map(|q| q.to_f64())
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+fn optional_block<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    optional_integer(deserializer)?
+        .map(|block| u64::try_from(block).map_err(|_| format!("{} is not a block number", block)))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+fn optional_integer<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    Option::<Quantity>::deserialize(deserializer)?
+        .map(|q| q.to_i64())
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DumpToken {
+    #[serde(alias = "id")]
+    pub address: String,
+    #[serde(default)]
+    pub symbol: Option<String>,
+    #[serde(default, deserialize_with = "optional_integer")]
+    pub decimals: Option<i64>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Slot0 {
+    #[serde(alias = "sqrtPrice", deserialize_with = "number")]
+    pub sqrt_price_x96: f64,
+    #[serde(deserialize_with = "integer")]
+    pub tick: i64,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DumpTick {
+    #[serde(alias = "tickIdx", deserialize_with = "integer")]
+    pub tick: i64,
+    #[serde(deserialize_with = "number")]
+    pub liquidity_net: f64,
+    #[serde(deserialize_with = "number")]
+    pub liquidity_gross: f64,
+}
+
+/// The state of a deployed V3 pool in the shape subgraph queries and RPC
+/// dumps give it: slot0, the active liquidity, the fee in hundredths of a
+/// basis point and the initialized ticks. Big integers may be numbers or
+/// strings, ticks may be named `tick` or `tickIdx`, and fields a dump
+/// carries beyond these are ignored. Token balances are optional since most
+/// dumps leave them out; see [`V3PoolDump::to_snapshot`].
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct V3PoolDump {
+    #[serde(default)]
+    pub token0: Option<DumpToken>,
+    #[serde(default)]
+    pub token1: Option<DumpToken>,
+    pub slot0: Slot0,
+    #[serde(deserialize_with = "number")]
+    pub liquidity: f64,
+    #[serde(alias = "feeTier", deserialize_with = "integer")]
+    pub fee: i64,
+    // derived from the fee for the standard fee tiers when missing
+    #[serde(default, deserialize_with = "optional_integer")]
+    pub tick_spacing: Option<i64>,
+    pub ticks: Vec<DumpTick>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub balance0: Option<f64>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub balance1: Option<f64>,
+    // the block the state was read at
+    #[serde(default, deserialize_with = "optional_block")]
+    pub block_number: Option<u64>,
+}
+
+fn invalid(at: &str, message: impl Into<String>) -> SnapshotError {
+    SnapshotError::Invalid { at: at.to_string(), message: message.into() }
+}
+
+// [default_tick_spacing] is the spacing Uniswap enables for each standard fee tier.
+fn default_tick_spacing(fee: i64) -> Option<i64> {
+    match fee {
+        100 => Some(1),
+        500 => Some(10),
+        3000 => Some(60),
+        10000 => Some(200),
+        _ => None,
+    }
+}
+
+// [check_token] verifies that what the dump says about a token agrees with [token].
+fn check_token(dumped: &Option<DumpToken>, token: &Token, at: &str) -> Result<(), SnapshotError> {
+    let Some(dumped) = dumped else {
+        return Ok(());
+    };
+    if !dumped.address.eq_ignore_ascii_case(&token.address) {
+        return Err(invalid(at, format!("is {} but {} is {}", dumped.address, token.symbol, token.address)));
+    }
+    if dumped.decimals.is_some_and(|d| d != token.decimals as i64) {
+        return Err(invalid(at, format!("has {:?} decimals but {} has {}", dumped.decimals, token.symbol, token.decimals)));
+    }
+    Ok(())
+}
+
+// [locked_amounts] is what liquidity [ticks] hold in each token at [sqrt_price_x96], as if every range had been minted into an empty pool.
+fn locked_amounts(ticks: &[TickLiquidity], sqrt_price_x96: f64) -> (f64, f64) {
+    let (mut amount_0, mut amount_1) = (0.0, 0.0);
+    let mut liquidity = 0.0;
+    for range in ticks.windows(2) {
+        liquidity += range[0].liquidity_net;
+        if liquidity <= 0.0 {
+            continue;
+        }
+        let (lower, upper) = (tick_to_sqrtp(range[0].tick), tick_to_sqrtp(range[1].tick));
+        let price = sqrt_price_x96.clamp(lower, upper);
+        if price < upper {
+            amount_0 += calc_amount0(liquidity, price, upper);
+        }
+        if price > lower {
+            amount_1 += calc_amount1(liquidity, lower, price);
+        }
+    }
+    (amount_0, amount_1)
+}
+
+impl V3PoolDump {
+    pub fn from_json(text: &str) -> Result<V3PoolDump, SnapshotError> {
+        serde_json::from_str(text).map_err(|e| SnapshotError::Parse(e.to_string()))
+    }
+
+    pub fn load(path: &Path) -> Result<V3PoolDump, SnapshotError> {
+        V3PoolDump::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Validates the dump against `token_0` and `token_1` and turns it into pool
+    /// state. Empty ticks are dropped, missing balances are set to what the ticks
+    /// lock up, and the liquidity belongs to no account.
+    pub fn to_snapshot(&self, token_0: &Token, token_1: &Token) -> Result<V3Snapshot, SnapshotError> {
+        check_token(&self.token0, token_0, "token0")?;
+        check_token(&self.token1, token_1, "token1")?;
+        if !(0..FEE_UNITS as i64).contains(&self.fee) {
+            return Err(invalid("fee", format!("{} is not a fee in hundredths of a basis point", self.fee)));
+        }
+        let spacing = match self.tick_spacing.or_else(|| default_tick_spacing(self.fee)) {
+            Some(spacing) if spacing > 0 => spacing,
+            Some(spacing) => return Err(invalid("tickSpacing", format!("{} is not positive", spacing))),
+            None => return Err(invalid("tickSpacing", format!("is needed for fee {}", self.fee))),
+        };
+        let max_tick = (get_max_tick() as i64 / spacing) * spacing;
+
+        let Slot0 { sqrt_price_x96, tick } = self.slot0;
+        if !sqrt_price_x96.is_finite() || sqrt_price_x96 <= 0.0 {
+            return Err(invalid("slot0", "sqrtPriceX96 must be positive"));
+        }
+        // the float tick of the price may land one off a tick boundary
+        if (sqrtp_to_tick(sqrt_price_x96) as i64 - tick).abs() > 1 {
+            return Err(invalid("slot0", format!("tick {} does not match sqrtPriceX96 {}", tick, sqrt_price_x96)));
+        }
+
+        let mut ticks = Vec::with_capacity(self.ticks.len());
+        for t in self.ticks.iter().filter(|t| t.liquidity_gross != 0.0) {
+            let at = format!("ticks (tick {})", t.tick);
+            if t.tick % spacing != 0 || t.tick.abs() > max_tick {
+                return Err(invalid(&at, format!("is not a usable tick at spacing {}", spacing)));
+            }
+            if t.liquidity_net.abs() > t.liquidity_gross {
+                return Err(invalid(&at, "net liquidity exceeds gross liquidity"));
+            }
+            ticks.push(TickLiquidity {
+                tick: t.tick as i32,
+                liquidity_net: t.liquidity_net,
+                liquidity_gross: t.liquidity_gross,
+            });
+        }
+        ticks.sort_by_key(|t| t.tick);
+        if let Some(pair) = ticks.windows(2).find(|pair| pair[0].tick == pair[1].tick) {
+            return Err(invalid(&format!("ticks (tick {})", pair[0].tick), "is listed twice"));
+        }
+
+        // a dump cut short, e.g. by subgraph paging, leaves the ticks disagreeing with the active liquidity
+        let tolerance = 1e-9 * ticks.iter().map(|t| t.liquidity_gross).sum::<f64>();
+        let active: f64 = ticks.iter().filter(|t| (t.tick as i64) <= tick).map(|t| t.liquidity_net).sum();
+        if (active - self.liquidity).abs() > tolerance {
+            return Err(invalid("ticks", format!("add up to liquidity {} at tick {} but the pool has {}", active, tick, self.liquidity)));
+        }
+        let unbalanced: f64 = ticks.iter().map(|t| t.liquidity_net).sum();
+        if unbalanced.abs() > tolerance {
+            return Err(invalid("ticks", format!("net liquidity adds up to {} rather than 0", unbalanced)));
+        }
+
+        let (locked_0, locked_1) = locked_amounts(&ticks, sqrt_price_x96);
+        Ok(V3Snapshot {
+            token_0: token_0.id,
+            token_1: token_1.id,
+            decimals_0: token_0.decimals,
+            decimals_1: token_1.decimals,
+            fee: self.fee as f64 / FEE_UNITS,
+            min_tick: -max_tick as i32,
+            max_tick: max_tick as i32,
+            sqrt_price_x96,
+            tick: tick as i32,
+            liquidity: self.liquidity,
+            balance_0: self.balance0.unwrap_or(locked_0),
+            balance_1: self.balance1.unwrap_or(locked_1),
+            version: 0,
+            ticks,
+            positions: Vec::new(),
+        })
+    }
+
+    pub fn to_pool(&self, token_0: &Token, token_1: &Token) -> Result<UniswapV3Pool, SnapshotError> {
+        Ok(UniswapV3Pool::from_snapshot(&self.to_snapshot(token_0, token_1)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amm::Amm;
+    use crate::fixtures::{usdc_weth, USDC_WETH};
+    use crate::ledger::{Account, Holdings};
+
+    #[test]
+    fn dumps_import_into_pools() {
+        let (usdc, weth) = usdc_weth();
+        let dump = V3PoolDump::from_json(USDC_WETH).unwrap();
+        let pool = dump.to_pool(&usdc, &weth).unwrap();
+
+        assert!((1.0 / pool.price() - 2000.0).abs() < 1e-6);
+        assert_eq!(pool.fee(), 0.003);
+        assert_eq!(pool.liquidity(), 15300000000000000000.0);
+        // the tick the subgraph kept after its liquidity was burned is dropped
+        assert_eq!(pool.ticks().len(), dump.ticks.len() - 1);
+        assert_eq!(pool.ticks()[0].tick, -887220);
+
+        // the derived balances pay out the largest swap the pool allows
+        let (usdc_held, weth_held) = (pool.holdings()[0].1, pool.holdings()[1].1);
+        assert!(usdc_held > 0.0 && weth_held > 0.0);
+        let mut trader = Account::new(1, &[(usdc.id, 1e15)]);
+        let out = pool.apply_swap(&mut trader, usdc.id, weth.id, usdc.amount(1_000_000.0).raw_f64(), 0.0).unwrap();
+        assert!(out > weth.amount(450.0).raw_f64() && out < weth.amount(500.0).raw_f64());
+        assert!(pool.holdings()[1].1 > 0.0);
+    }
+
+    #[test]
+    fn dumps_accept_numbers_hex_and_missing_fields() {
+        let (usdc, weth) = usdc_weth();
+        let dump = V3PoolDump::from_json(
+            r#"{
+                "slot0": { "sqrtPrice": 1.7715955711429571e33, "tick": "200311" },
+                "liquidity": "0xde0b6b3a7640000",
+                "feeTier": "500",
+                "ticks": [
+                    { "tick": 200300, "liquidityNet": 1e18, "liquidityGross": 1e18 },
+                    { "tick": 200320, "liquidityNet": "-1000000000000000000", "liquidityGross": "1000000000000000000" }
+                ],
+                "balance0": "5", "balance1": 7, "blockNumber": 19000000
+            }"#
+        ).unwrap();
+        assert_eq!(dump.liquidity, 1e18);
+        assert_eq!(dump.tick_spacing, None);
+
+        let snapshot = dump.to_snapshot(&usdc, &weth).unwrap();
+        assert_eq!((snapshot.fee, snapshot.max_tick), (0.0005, 887270));
+        assert_eq!((snapshot.balance_0, snapshot.balance_1), (5.0, 7.0));
+    }
+
+    #[test]
+    fn inconsistent_dumps_are_rejected() {
+        let (usdc, weth) = usdc_weth();
+        let error = |edit: &dyn Fn(&mut V3PoolDump)| {
+            let mut dump = V3PoolDump::from_json(USDC_WETH).unwrap();
+            edit(&mut dump);
+            dump.to_snapshot(&usdc, &weth).unwrap_err().to_string()
+        };
+
+        assert_eq!(
+            error(&|d| { d.ticks.pop(); }),
+            "ticks: net liquidity adds up to 300000000000000000 rather than 0"
+        );
+        assert!(error(&|d| d.liquidity *= 2.0).starts_with("ticks: add up to liquidity 15300000000000000000 at tick 200311"));
+        assert_eq!(error(&|d| d.ticks[1].tick += 1), "ticks (tick 194281): is not a usable tick at spacing 60");
+        assert!(error(&|d| d.slot0.tick = 0).starts_with("slot0: tick 0 does not match sqrtPriceX96"));
+        assert_eq!(error(&|d| { d.fee = 2500; d.tick_spacing = None; }), "tickSpacing: is needed for fee 2500");
+        assert!(error(&|d| d.token0.as_mut().unwrap().decimals = Some(18)).starts_with("token0: has Some(18) decimals"));
+        assert!(error(&|d| std::mem::swap(&mut d.token0, &mut d.token1)).starts_with("token0: is 0xc02a"));
+    }
+}
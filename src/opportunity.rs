@@ -0,0 +1,196 @@
+use crate::amm::Amm;
+use crate::arb::{Route, TwoPoolArb};
+use crate::error::PoolError;
+use crate::token::TokenId;
+use serde::Serialize;
+
+/// Where the price of the profit token in the numeraire comes from.
+pub enum PriceSource<'a> {
+    // mid price of a pool trading the profit token against the numeraire, before fees
+    PoolMid(&'a dyn Amm),
+    // raw units of the numeraire per raw unit of the profit token, e.g. from an oracle
+    Reference(f64),
+}
+
+/// Returns the raw amount of `numeraire` worth one raw unit of `token` according to `source`.
+pub fn price_in_numeraire(
+    token: TokenId,
+    numeraire: TokenId,
+    source: &PriceSource
+) -> Result<f64, PoolError> {
+    if token == numeraire {
+        return Ok(1.0);
+    }
+    match source {
+        PriceSource::PoolMid(pool) => pool.spot_price(token, numeraire),
+        PriceSource::Reference(price) => Ok(*price),
+    }
+}
+
+/// A pool on a route, with the name it is reported under.
+pub struct NamedPool<'a> {
+    pub name: &'a str,
+    pub pool: &'a dyn Amm,
+}
+
+/// One swap of a route. `min_amount_out` is the expected output less the
+/// slippage tolerance, ready to hand to the pool as its minimum;
+/// `pool_version` is the pool state the leg was quoted against.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Leg {
+    pub pool: String,
+    pub token_in: TokenId,
+    pub token_out: TokenId,
+    pub amount_in: f64,
+    pub amount_out: f64,
+    pub min_amount_out: f64,
+    pub pool_version: u64,
+}
+
+/// Arbitrage result with its units spelled out. Amounts are raw units of
+/// `profit_token`; `net_value` is the net profit in raw units of `numeraire`.
+/// Each leg names the version of its pool it was quoted against, so an
+/// executor can drop the route once any of its pools has moved on.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Opportunity {
+    pub route: Vec<Leg>,
+    pub token_in: TokenId,
+    pub amount_in: f64,
+    pub profit_token: TokenId,
+    pub gross_profit: f64,
+    pub gas_cost: f64,
+    pub net_profit: f64,
+    pub numeraire: TokenId,
+    pub net_value: f64,
+    pub slippage_tolerance: f64,
+}
+
+impl Opportunity {
+    /// Quotes each leg of `arb` through the pools it was searched over and values
+    /// the net profit in `numeraire` at the price from `source`. Minimum outputs
+    /// allow for `slippage_tolerance`, a fraction of the expected output.
+    pub fn from_arb(
+        arb: &TwoPoolArb,
+        pool1: &NamedPool,
+        pool2: &NamedPool,
+        numeraire: TokenId,
+        source: &PriceSource,
+        slippage_tolerance: f64
+    ) -> Result<Opportunity, PoolError> {
+        let (first, second) = match arb.route {
+            Route::FirstToSecond => (pool1, pool2),
+            Route::SecondToFirst => (pool2, pool1),
+        };
+
+        let mut route = Vec::new();
+        let mut amount_in = arb.amount_in;
+        for (named, token_in, token_out) in [
+            (first, arb.token_in, arb.token_mid),
+            (second, arb.token_mid, arb.token_in),
+        ] {
+            let pool_version = named.pool.version();
+            let amount_out = named.pool.quote_exact_in(token_in, token_out, amount_in)?;
+            route.push(Leg {
+                pool: named.name.to_string(),
+                token_in,
+                token_out,
+                amount_in,
+                amount_out,
+                min_amount_out: amount_out * (1.0 - slippage_tolerance),
+                pool_version,
+            });
+            amount_in = amount_out;
+        }
+
+        let price = price_in_numeraire(arb.token_in, numeraire, source)?;
+        Ok(Opportunity {
+            route,
+            token_in: arb.token_in,
+            amount_in: arb.amount_in,
+            profit_token: arb.token_in,
+            gross_profit: arb.profit,
+            gas_cost: arb.gas_cost,
+            net_profit: arb.net_profit(),
+            numeraire,
+            net_value: arb.net_profit() * price,
+            slippage_tolerance,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::find_two_pool_arb;
+    use crate::v2::Pool;
+
+    const WETH: TokenId = TokenId(0);
+    const DAI: TokenId = TokenId(1);
+    const USDC: TokenId = TokenId(2);
+
+    fn arb() -> TwoPoolArb {
+        TwoPoolArb {
+            route: Route::FirstToSecond,
+            token_in: WETH,
+            token_mid: DAI,
+            amount_in: 10.0,
+            profit: 0.5,
+            gas_cost: 0.1,
+        }
+    }
+
+    #[test]
+    fn valued_at_pool_mid_price() {
+        let pool = Pool::new(WETH, DAI, 100.0, 200000.0, 0.003);
+        let named = NamedPool { name: "cp", pool: &pool };
+
+        let opportunity = Opportunity::from_arb(&arb(), &named, &named, DAI, &PriceSource::PoolMid(&pool), 0.0).unwrap();
+
+        assert_eq!(opportunity.net_profit, 0.4);
+        assert_eq!(opportunity.net_value, 800.0);
+        assert_eq!(
+            Opportunity::from_arb(&arb(), &named, &named, USDC, &PriceSource::PoolMid(&pool), 0.0),
+            Err(PoolError::UnknownToken { token: USDC })
+        );
+    }
+
+    #[test]
+    fn valued_at_reference_price() {
+        let pool = Pool::new(WETH, DAI, 100.0, 200000.0, 0.003);
+        let named = NamedPool { name: "cp", pool: &pool };
+
+        let opportunity = Opportunity::from_arb(&arb(), &named, &named, USDC, &PriceSource::Reference(2500.0), 0.0).unwrap();
+        assert_eq!(opportunity.numeraire, USDC);
+        assert_eq!(opportunity.net_value, 1000.0);
+
+        // profit already in the numeraire is taken at face value
+        let same = Opportunity::from_arb(&arb(), &named, &named, WETH, &PriceSource::Reference(2500.0), 0.0).unwrap();
+        assert_eq!(same.net_value, same.net_profit);
+    }
+
+    #[test]
+    fn route_legs_follow_the_search() {
+        let cheap = Pool::new(WETH, DAI, 100.0, 200000.0, 0.003);
+        let dear = Pool::new(WETH, DAI, 100.0, 220000.0, 0.003);
+        let best = find_two_pool_arb(&cheap, &dear, WETH, DAI, 10.0).unwrap().unwrap();
+
+        let opportunity = Opportunity::from_arb(
+            &best,
+            &NamedPool { name: "cheap", pool: &cheap },
+            &NamedPool { name: "dear", pool: &dear },
+            WETH,
+            &PriceSource::Reference(1.0),
+            0.005
+        ).unwrap();
+
+        let legs: Vec<(&str, TokenId, TokenId)> = opportunity.route
+            .iter()
+            .map(|l| (l.pool.as_str(), l.token_in, l.token_out))
+            .collect();
+        assert_eq!(legs, vec![("dear", WETH, DAI), ("cheap", DAI, WETH)]);
+        assert_eq!(opportunity.route[1].amount_in, opportunity.route[0].amount_out);
+        assert_eq!(opportunity.route[1].amount_out - opportunity.amount_in, opportunity.gross_profit);
+        assert_eq!(opportunity.route[0].min_amount_out, opportunity.route[0].amount_out * 0.995);
+        assert_eq!(opportunity.route[0].pool_version, dear.version());
+    }
+}
@@ -0,0 +1,360 @@
+use crate::error::PoolError;
+use rand::{Rng, RngCore};
+use serde::Serialize;
+use std::f64::consts::PI;
+
+// seconds in the year that drifts and volatilities are quoted over
+pub const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 3600.0;
+
+/// Exogenous ("true") prices that pools are measured and traded against.
+/// Drifts and volatilities are annualized; `dt` is in years.
+pub trait PriceProcess {
+    fn prices(&self) -> Vec<f64>;
+
+    /// Moves the prices forward by `dt` years and returns them.
+    fn step(&mut self, dt: f64, rng: &mut dyn RngCore) -> Vec<f64>;
+}
+
+/// Draws from N(0, 1) with the Box-Muller transform.
+pub fn standard_normal(rng: &mut dyn RngCore) -> f64 {
+    // 1 - u keeps the logarithm finite
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+/// Draws the number of events of a Poisson process with mean `lambda`, by Knuth's method.
+pub fn poisson(lambda: f64, rng: &mut dyn RngCore) -> u32 {
+    if lambda <= 0.0 {
+        return 0;
+    }
+    let limit = (-lambda).exp();
+    let mut product: f64 = rng.gen();
+    let mut count = 0;
+    while product > limit {
+        product *= rng.gen::<f64>();
+        count += 1;
+    }
+    count
+}
+
+fn check_volatility(name: &'static str, value: f64) -> Result<(), PoolError> {
+    if value < 0.0 || !value.is_finite() {
+        return Err(PoolError::InvalidParameter { name, value });
+    }
+    Ok(())
+}
+
+fn check_price(value: f64) -> Result<(), PoolError> {
+    if value <= 0.0 || !value.is_finite() {
+        return Err(PoolError::InvalidParameter { name: "price", value });
+    }
+    Ok(())
+}
+
+/// Geometric Brownian motion, dS = mu S dt + sigma S dW, stepped exactly in
+/// log space.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Gbm {
+    price: f64,
+    drift: f64,
+    volatility: f64,
+}
+
+impl Gbm {
+    pub fn new(price: f64, drift: f64, volatility: f64) -> Result<Gbm, PoolError> {
+        check_price(price)?;
+        check_volatility("volatility", volatility)?;
+        Ok(Gbm { price, drift, volatility })
+    }
+}
+
+impl PriceProcess for Gbm {
+    fn prices(&self) -> Vec<f64> {
+        vec![self.price]
+    }
+
+    fn step(&mut self, dt: f64, rng: &mut dyn RngCore) -> Vec<f64> {
+        let z = standard_normal(rng);
+        self.price *= ((self.drift - 0.5 * self.volatility * self.volatility) * dt + self.volatility * dt.sqrt() * z).exp();
+        self.prices()
+    }
+}
+
+/// Merton jump-diffusion: GBM plus jumps arriving `intensity` times a year
+/// on average, each multiplying the price by a lognormal factor with log
+/// mean `jump_mean` and log volatility `jump_volatility`. The drift is
+/// compensated, so `drift` stays the expected growth rate.
+#[derive(Debug, PartialEq, Clone)]
+pub struct JumpDiffusion {
+    gbm: Gbm,
+    intensity: f64,
+    jump_mean: f64,
+    jump_volatility: f64,
+}
+
+impl JumpDiffusion {
+    pub fn new(
+        price: f64,
+        drift: f64,
+        volatility: f64,
+        intensity: f64,
+        jump_mean: f64,
+        jump_volatility: f64
+    ) -> Result<JumpDiffusion, PoolError> {
+        check_volatility("intensity", intensity)?;
+        check_volatility("jump_volatility", jump_volatility)?;
+        Ok(JumpDiffusion {
+            gbm: Gbm::new(price, drift, volatility)?,
+            intensity,
+            jump_mean,
+            jump_volatility,
+        })
+    }
+
+    // [mean_jump] is E[J - 1] for one jump factor J.
+    fn mean_jump(&self) -> f64 {
+        (self.jump_mean + 0.5 * self.jump_volatility * self.jump_volatility).exp() - 1.0
+    }
+}
+
+impl PriceProcess for JumpDiffusion {
+    fn prices(&self) -> Vec<f64> {
+        self.gbm.prices()
+    }
+
+    fn step(&mut self, dt: f64, rng: &mut dyn RngCore) -> Vec<f64> {
+        let drift = self.gbm.drift;
+        self.gbm.drift = drift - self.intensity * self.mean_jump();
+        self.gbm.step(dt, rng);
+        self.gbm.drift = drift;
+
+        for _ in 0..poisson(self.intensity * dt, rng) {
+            self.gbm.price *= (self.jump_mean + self.jump_volatility * standard_normal(rng)).exp();
+        }
+        self.prices()
+    }
+}
+
+/// GBMs for several assets whose Brownian motions are correlated as given
+/// by a correlation matrix.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CorrelatedGbm {
+    prices: Vec<f64>,
+    drifts: Vec<f64>,
+    volatilities: Vec<f64>,
+    // lower triangular L with L L^T equal to the correlation matrix
+    cholesky: Vec<Vec<f64>>,
+}
+
+impl CorrelatedGbm {
+    pub fn new(
+        prices: &[f64],
+        drifts: &[f64],
+        volatilities: &[f64],
+        correlation: &[Vec<f64>]
+    ) -> Result<CorrelatedGbm, PoolError> {
+        let n = prices.len();
+        if drifts.len() != n || volatilities.len() != n || correlation.len() != n || correlation.iter().any(|row| row.len() != n) {
+            return Err(PoolError::InvalidParameter { name: "assets", value: n as f64 });
+        }
+        for k in 0..n {
+            check_price(prices[k])?;
+            check_volatility("volatility", volatilities[k])?;
+            if correlation[k][k] != 1.0 {
+                return Err(PoolError::InvalidParameter { name: "correlation", value: k as f64 });
+            }
+            for (j, row) in correlation.iter().enumerate() {
+                if correlation[k][j] != row[k] || row[k].abs() > 1.0 {
+                    return Err(PoolError::InvalidParameter { name: "correlation", value: row[k] });
+                }
+            }
+        }
+        Ok(CorrelatedGbm {
+            prices: prices.to_vec(),
+            drifts: drifts.to_vec(),
+            volatilities: volatilities.to_vec(),
+            cholesky: cholesky(correlation)?,
+        })
+    }
+}
+
+// [cholesky] factors the symmetric matrix [a] into L L^T, failing unless [a] is positive semi-definite.
+fn cholesky(a: &[Vec<f64>]) -> Result<Vec<Vec<f64>>, PoolError> {
+    let n = a.len();
+    let mut l = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in 0..=i {
+            let sum: f64 = (0..j).map(|k| l[i][k] * l[j][k]).sum();
+            if i == j {
+                let d = a[i][i] - sum;
+                // a tolerance lets perfectly correlated assets through
+                if d < -1e-12 {
+                    return Err(PoolError::InvalidParameter { name: "correlation", value: d });
+                }
+                l[i][j] = d.max(0.0).sqrt();
+            } else if l[j][j] > 0.0 {
+                l[i][j] = (a[i][j] - sum) / l[j][j];
+            }
+        }
+    }
+    Ok(l)
+}
+
+impl PriceProcess for CorrelatedGbm {
+    fn prices(&self) -> Vec<f64> {
+        self.prices.clone()
+    }
+
+    fn step(&mut self, dt: f64, rng: &mut dyn RngCore) -> Vec<f64> {
+        let z: Vec<f64> = (0..self.prices.len()).map(|_| standard_normal(rng)).collect();
+        for i in 0..self.prices.len() {
+            let w: f64 = (0..=i).map(|k| self.cholesky[i][k] * z[k]).sum();
+            let sigma = self.volatilities[i];
+            self.prices[i] *= ((self.drifts[i] - 0.5 * sigma * sigma) * dt + sigma * dt.sqrt() * w).exp();
+        }
+        self.prices()
+    }
+}
+
+/// How far a pool's mid price trails a reference price. Deviations are
+/// relative, `mid / reference - 1`.
+#[derive(Debug, PartialEq, Clone, Default, Serialize)]
+pub struct LagStats {
+    pub samples: usize,
+    pub total_abs: f64,
+    pub max_abs: f64,
+    pub threshold: f64,
+    pub above_threshold: usize,
+}
+
+impl LagStats {
+    /// Counts deviations larger than `threshold` in absolute value, e.g. the pool fee.
+    pub fn new(threshold: f64) -> LagStats {
+        LagStats { threshold, ..LagStats::default() }
+    }
+
+    pub fn record(&mut self, deviation: f64) {
+        let deviation = deviation.abs();
+        self.samples += 1;
+        self.total_abs += deviation;
+        self.max_abs = self.max_abs.max(deviation);
+        if deviation > self.threshold {
+            self.above_threshold += 1;
+        }
+    }
+
+    pub fn mean_abs(&self) -> f64 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        self.total_abs / (self.samples as f64)
+    }
+
+    /// The share of samples that deviated by more than the threshold.
+    pub fn frequency(&self) -> f64 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        (self.above_threshold as f64) / (self.samples as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // [log_returns] steps [process] [steps] times and returns the log return of each asset at each step.
+    fn log_returns(process: &mut dyn PriceProcess, steps: usize, dt: f64) -> Vec<Vec<f64>> {
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut before = process.prices();
+        (0..steps)
+            .map(|_| {
+                let after = process.step(dt, &mut rng);
+                let returns = before
+                    .iter()
+                    .zip(&after)
+                    .map(|(b, a)| (a / b).ln())
+                    .collect();
+                before = after;
+                returns
+            })
+            .collect()
+    }
+
+    fn mean_and_variance(xs: &[f64]) -> (f64, f64) {
+        let n = xs.len() as f64;
+        let mean = xs.iter().sum::<f64>() / n;
+        (mean, xs.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / n)
+    }
+
+    #[test]
+    fn gbm_moments() {
+        let dt = 1.0 / 365.0;
+        let mut gbm = Gbm::new(2000.0, 0.1, 0.8).unwrap();
+        let returns: Vec<f64> = log_returns(&mut gbm, 20000, dt)
+            .iter()
+            .map(|r| r[0])
+            .collect();
+
+        let (mean, variance) = mean_and_variance(&returns);
+        assert!((variance / (0.64 * dt) - 1.0).abs() < 0.03);
+        // the mean is noisy next to the volatility, so only check it is within a few standard errors
+        let standard_error = (0.64 * dt / 20000.0).sqrt();
+        assert!((mean - (0.1 - 0.32) * dt).abs() < 4.0 * standard_error);
+
+        let flat = log_returns(&mut Gbm::new(1.0, 0.5, 0.0).unwrap(), 10, 0.1);
+        assert!(flat.iter().all(|r| (r[0] - 0.05).abs() < 1e-15));
+        assert!(Gbm::new(-1.0, 0.0, 0.1).is_err());
+    }
+
+    #[test]
+    fn jumps_add_to_the_diffusion() {
+        let dt = 1.0 / 365.0;
+        // without jumps the process is the plain GBM, draw for draw
+        let mut plain = JumpDiffusion::new(2000.0, 0.1, 0.5, 0.0, -0.1, 0.05).unwrap();
+        let mut gbm = Gbm::new(2000.0, 0.1, 0.5).unwrap();
+        assert_eq!(log_returns(&mut plain, 100, dt), log_returns(&mut gbm, 100, dt));
+
+        // frequent downward jumps widen the distribution by their own variance
+        let mut jumpy = JumpDiffusion::new(2000.0, 0.0, 0.5, 50.0, -0.05, 0.02).unwrap();
+        let returns: Vec<f64> = log_returns(&mut jumpy, 20000, dt)
+            .iter()
+            .map(|r| r[0])
+            .collect();
+        let (_, variance) = mean_and_variance(&returns);
+        let expected = (0.25 + 50.0 * (0.05 * 0.05 + 0.02 * 0.02)) * dt;
+        assert!((variance / expected - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn correlated_assets() {
+        let correlation = vec![vec![1.0, 0.7, 0.0], vec![0.7, 1.0, 0.3], vec![0.0, 0.3, 1.0]];
+        let mut gbm = CorrelatedGbm::new(&[2000.0, 30000.0, 1.0], &[0.0; 3], &[0.8, 0.6, 0.01], &correlation).unwrap();
+        let returns = log_returns(&mut gbm, 20000, 1.0 / 365.0);
+
+        let column = |k: usize| -> Vec<f64> {
+            returns
+                .iter()
+                .map(|r| r[k])
+                .collect()
+        };
+        let corr = |a: &[f64], b: &[f64]| {
+            let ((ma, va), (mb, vb)) = (mean_and_variance(a), mean_and_variance(b));
+            let cov = a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| (x - ma) * (y - mb))
+                .sum::<f64>() / (a.len() as f64);
+            cov / (va * vb).sqrt()
+        };
+        assert!((corr(&column(0), &column(1)) - 0.7).abs() < 0.02);
+        assert!((corr(&column(1), &column(2)) - 0.3).abs() < 0.02);
+        assert!(corr(&column(0), &column(2)).abs() < 0.02);
+
+        let not_psd = vec![vec![1.0, 0.9, -0.9], vec![0.9, 1.0, 0.9], vec![-0.9, 0.9, 1.0]];
+        assert!(CorrelatedGbm::new(&[1.0; 3], &[0.0; 3], &[0.1; 3], &not_psd).is_err());
+    }
+}
@@ -0,0 +1,622 @@
+use crate::math::get_max_tick;
+use crate::onchain::{DumpTick, DumpToken, Slot0, V3PoolDump};
+use crate::snapshot::SnapshotError;
+use crate::token::Token;
+use crate::v2::{Pool, V2Snapshot};
+use crate::v3::UniswapV3Pool;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+// function selectors of the pool and token views the loaders call
+const SLOT0: [u8; 4] = [0x38, 0x50, 0xc7, 0xbd];
+const LIQUIDITY: [u8; 4] = [0x1a, 0x68, 0x65, 0x02];
+const FEE: [u8; 4] = [0xdd, 0xca, 0x3f, 0x43];
+const TICK_SPACING: [u8; 4] = [0xd0, 0xc9, 0x3a, 0x7c];
+const TICKS: [u8; 4] = [0xf3, 0x0d, 0xba, 0x93];
+const TICK_BITMAP: [u8; 4] = [0x53, 0x39, 0xc2, 0x96];
+const TOKEN0: [u8; 4] = [0x0d, 0xfe, 0x16, 0x81];
+const TOKEN1: [u8; 4] = [0xd2, 0x12, 0x20, 0xa7];
+const GET_RESERVES: [u8; 4] = [0x09, 0x02, 0xf1, 0xac];
+const BALANCE_OF: [u8; 4] = [0x70, 0xa0, 0x82, 0x31];
+const DECIMALS: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+
+// the longest a retry waits, however many attempts came before it
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+type Word = [u8; 32];
+
+#[derive(Debug)]
+pub enum RpcError {
+    // the node could not be reached, or kept failing after every retry
+    Transport(String),
+    Rpc {
+        code: i64,
+        message: String,
+    },
+    Parse(String),
+    /// `at` says what is wrong, e.g. the address of a pool trading other tokens
+    Invalid {
+        at: String,
+        message: String,
+    },
+    Snapshot(SnapshotError),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpcError::Transport(message) => write!(f, "cannot reach the node: {}", message),
+            RpcError::Rpc { code, message } => write!(f, "node error {}: {}", code, message),
+            RpcError::Parse(message) => write!(f, "cannot parse node response: {}", message),
+            RpcError::Invalid { at, message } => write!(f, "{}: {}", at, message),
+            RpcError::Snapshot(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<SnapshotError> for RpcError {
+    fn from(e: SnapshotError) -> RpcError {
+        RpcError::Snapshot(e)
+    }
+}
+
+fn parse_error(message: impl fmt::Display) -> RpcError {
+    RpcError::Parse(message.to_string())
+}
+
+/// A read-only `eth_call` of a contract view.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Call {
+    pub to: String,
+    pub data: String,
+}
+
+impl Call {
+    pub fn new(to: &str, selector: [u8; 4]) -> Call {
+        Call { to: to.to_lowercase(), data: format!("0x{}", hex(&selector)) }
+    }
+
+    /// Appends a signed integer argument, such as an int24 tick or an int16 bitmap
+    /// word, sign-extended to a word.
+    pub fn int(mut self, value: i64) -> Call {
+        let fill = if value < 0 { "f" } else { "0" };
+        self.data.push_str(&format!("{}{:016x}", fill.repeat(48), value as u64));
+        self
+    }
+
+    pub fn address(mut self, address: &str) -> Call {
+        self.data.push_str(&format!("{:0>64}", address.trim_start_matches("0x").to_lowercase()));
+        self
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// [words] splits ABI-encoded return data into 32-byte words.
+fn words(data: &str) -> Result<Vec<Word>, RpcError> {
+    let digits = data.strip_prefix("0x").unwrap_or(data);
+    if !digits.len().is_multiple_of(64) {
+        return Err(parse_error(format!("{} is not a list of words", data)));
+    }
+    let mut words = Vec::with_capacity(digits.len() / 64);
+    for chunk in digits.as_bytes().chunks(64) {
+        let mut word = [0u8; 32];
+        for (k, pair) in chunk.chunks(2).enumerate() {
+            let pair = std::str::from_utf8(pair).map_err(parse_error)?;
+            word[k] = u8::from_str_radix(pair, 16).map_err(|_| parse_error(format!("{:?} is not hex", data)))?;
+        }
+        words.push(word);
+    }
+    Ok(words)
+}
+
+// [unsigned] converts a uint word to the nearest f64.
+fn unsigned(word: &Word) -> f64 {
+    if word[..16].iter().all(|b| *b == 0) {
+        return u128::from_be_bytes(word[16..].try_into().unwrap()) as f64;
+    }
+    word.iter().fold(0.0, |value, b| value * 256.0 + *b as f64)
+}
+
+// [signed] converts a two's complement int word to the nearest f64.
+fn signed(word: &Word) -> f64 {
+    if word[0] & 0x80 == 0 {
+        return unsigned(word);
+    }
+    let mut magnitude = word.map(|b| !b);
+    for byte in magnitude.iter_mut().rev() {
+        let (sum, carry) = byte.overflowing_add(1);
+        *byte = sum;
+        if !carry {
+            break;
+        }
+    }
+    -unsigned(&magnitude)
+}
+
+// [small] is a word holding an integer of at most 64 bits, such as a tick, fee or decimals.
+fn small(word: &Word) -> i64 {
+    i64::from_be_bytes(word[24..].try_into().unwrap())
+}
+
+fn address(word: &Word) -> String {
+    format!("0x{}", hex(&word[12..]))
+}
+
+// [word] is word [k] of [data], or an error saying what the call returned too little of.
+fn word<'a>(data: &'a [Word], k: usize, what: &str) -> Result<&'a Word, RpcError> {
+    data.get(k).ok_or_else(|| parse_error(format!("{} returned {} words", what, data.len())))
+}
+
+/// Talks JSON-RPC to an Ethereum node over HTTP. Calls are sent in batches
+/// of at most `batch_size`, and a batch that cannot reach the node, or that
+/// the node answers with 429 or a 5xx status, is retried with exponential
+/// backoff.
+pub struct RpcClient {
+    url: String,
+    agent: ureq::Agent,
+    retries: u32,
+    backoff: Duration,
+    batch_size: usize,
+}
+
+impl RpcClient {
+    pub fn new(url: &str) -> RpcClient {
+        RpcClient {
+            url: url.to_string(),
+            agent: ureq::AgentBuilder::new().timeout(Duration::from_secs(30)).build(),
+            retries: 3,
+            backoff: Duration::from_millis(250),
+            batch_size: 100,
+        }
+    }
+
+    /// Retries a failed batch `retries` times, waiting `backoff` before the first
+    /// retry and twice as long before each next one, up to a minute.
+    pub fn with_retries(mut self, retries: u32, backoff: Duration) -> RpcClient {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> RpcClient {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    // [post] sends [body] and returns the parsed response, retrying transport failures and overloaded nodes.
+    fn post(&self, body: &Value) -> Result<Value, RpcError> {
+        let body = body.to_string();
+        let mut attempt = 0;
+        loop {
+            let failure = match self.agent.post(&self.url).set("Content-Type", "application/json").send_string(&body) {
+                Ok(response) => {
+                    let text = response.into_string().map_err(|e| RpcError::Transport(e.to_string()))?;
+                    return serde_json::from_str(&text).map_err(parse_error);
+                }
+                Err(ureq::Error::Status(status, _)) if status == 429 || status >= 500 => format!("HTTP {}", status),
+                Err(ureq::Error::Status(status, _)) => {
+                    return Err(RpcError::Transport(format!("HTTP {}", status)));
+                }
+                // the node may come back, but a wrong URL will not
+                Err(ureq::Error::Transport(e)) if matches!(
+                    e.kind(),
+                    ureq::ErrorKind::Dns | ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io
+                ) => e.to_string(),
+                Err(ureq::Error::Transport(e)) => {
+                    return Err(RpcError::Transport(e.to_string()));
+                }
+            };
+            if attempt >= self.retries {
+                return Err(RpcError::Transport(format!("{} after {} attempts", failure, attempt + 1)));
+            }
+            thread::sleep(self.delay(attempt));
+            attempt += 1;
+        }
+    }
+
+    // [delay] is how long to wait after failed [attempt], counting from 0, capped so that it cannot overflow.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(attempt)).min(MAX_BACKOFF)
+    }
+
+    /// Sends every (method, params) request and returns their results in the same order.
+    pub fn batch(&self, requests: &[(&str, Value)]) -> Result<Vec<Value>, RpcError> {
+        let mut results = Vec::with_capacity(requests.len());
+        for chunk in requests.chunks(self.batch_size) {
+            let body: Vec<Value> = chunk
+                .iter()
+                .enumerate()
+                .map(|(id, (method, params))| json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+                .collect();
+            let response = self.post(&Value::Array(body))?;
+            let Value::Array(responses) = response else {
+                return Err(rpc_error(&response).unwrap_or_else(|| parse_error("a batch was not answered with a list")));
+            };
+            // nodes may answer a batch in any order
+            let mut by_id: HashMap<u64, Value> = HashMap::new();
+            for response in responses {
+                if let Some(error) = rpc_error(&response) {
+                    return Err(error);
+                }
+                let id = response["id"].as_u64().ok_or_else(|| parse_error("a response has no id"))?;
+                by_id.insert(id, response["result"].clone());
+            }
+            for id in 0..chunk.len() {
+                let result = by_id.remove(&(id as u64)).ok_or_else(|| parse_error(format!("no response to request {}", id)))?;
+                results.push(result);
+            }
+        }
+        Ok(results)
+    }
+
+    pub fn block_number(&self) -> Result<u64, RpcError> {
+        let result = self.batch(&[("eth_blockNumber", json!([]))])?.remove(0);
+        result
+            .as_str()
+            .and_then(|text| u64::from_str_radix(text.trim_start_matches("0x"), 16).ok())
+            .ok_or_else(|| parse_error(format!("{} is not a block number", result)))
+    }
+
+    /// Runs every call against the state at `block` and returns the words each returned.
+    pub fn call(&self, calls: &[Call], block: u64) -> Result<Vec<Vec<Word>>, RpcError> {
+        let block = format!("0x{:x}", block);
+        let requests: Vec<(&str, Value)> = calls
+            .iter()
+            .map(|call| ("eth_call", json!([{ "to": call.to, "data": call.data }, block])))
+            .collect();
+        self.batch(&requests)?
+            .iter()
+            .map(|result| words(result.as_str().ok_or_else(|| parse_error(format!("{} is not call data", result)))?))
+            .collect()
+    }
+
+    // [block_or_latest] is [block], or the latest block so that every call of a load sees the same state.
+    fn block_or_latest(&self, block: Option<u64>) -> Result<u64, RpcError> {
+        block.map_or_else(|| self.block_number(), Ok)
+    }
+
+    /// Builds the V2 pair at `address` from its reserves at `block`, or at the
+    /// latest block. `token_x` and `token_y` may be given in either order.
+    pub fn load_v2(
+        &self,
+        address: &str,
+        token_x: &Token,
+        token_y: &Token,
+        fee: f64,
+        block: Option<u64>
+    ) -> Result<Pool, RpcError> {
+        let block = self.block_or_latest(block)?;
+        let calls = [Call::new(address, TOKEN0), Call::new(address, TOKEN1), Call::new(address, GET_RESERVES)];
+        let results = self.call(&calls, block)?;
+        let (token0, token1) = (address_of(&results[0], "token0")?, address_of(&results[1], "token1")?);
+        let (reserve0, reserve1) = (unsigned(word(&results[2], 0, "getReserves")?), unsigned(word(&results[2], 1, "getReserves")?));
+
+        let (x, y) = if token0.eq_ignore_ascii_case(&token_x.address) && token1.eq_ignore_ascii_case(&token_y.address) {
+            (reserve0, reserve1)
+        } else if token0.eq_ignore_ascii_case(&token_y.address) && token1.eq_ignore_ascii_case(&token_x.address) {
+            (reserve1, reserve0)
+        } else {
+            return Err(RpcError::Invalid {
+                at: address.to_string(),
+                message: format!("trades {} and {}, not {} and {}", token0, token1, token_x.symbol, token_y.symbol),
+            });
+        };
+        let snapshot = V2Snapshot { token_x: token_x.id, token_y: token_y.id, fee, x, y, version: 0 };
+        Ok(Pool::from_snapshot(&snapshot).map_err(SnapshotError::from)?)
+    }
+
+    /// Reads the V3 pool at `address` at `block`, or the latest block: slot0,
+    /// liquidity, fee, tokens and balances, then every initialized tick.
+    pub fn dump_v3(&self, address: &str, block: Option<u64>) -> Result<V3PoolDump, RpcError> {
+        let block = self.block_or_latest(block)?;
+        let calls = [SLOT0, LIQUIDITY, FEE, TICK_SPACING, TOKEN0, TOKEN1].map(|selector| Call::new(address, selector));
+        let results = self.call(&calls, block)?;
+        let slot0 = Slot0 {
+            sqrt_price_x96: unsigned(word(&results[0], 0, "slot0")?),
+            tick: small(word(&results[0], 1, "slot0")?),
+        };
+        let liquidity = unsigned(word(&results[1], 0, "liquidity")?);
+        let fee = small(word(&results[2], 0, "fee")?);
+        let spacing = small(word(&results[3], 0, "tickSpacing")?);
+        if spacing <= 0 {
+            return Err(RpcError::Invalid { at: address.to_string(), message: format!("tick spacing {}", spacing) });
+        }
+        let tokens = [address_of(&results[4], "token0")?, address_of(&results[5], "token1")?];
+
+        // one bitmap word covers 256 spaced ticks
+        let last_word = (get_max_tick() as i64 / spacing) >> 8;
+        let positions: Vec<i64> = (-last_word - 1..=last_word).collect();
+        let mut calls: Vec<Call> = tokens
+            .iter()
+            .flat_map(|token| [Call::new(token, BALANCE_OF).address(address), Call::new(token, DECIMALS)])
+            .collect();
+        calls.extend(positions.iter().map(|position| Call::new(address, TICK_BITMAP).int(*position)));
+        let results = self.call(&calls, block)?;
+        let mut dumped_tokens = Vec::new();
+        let mut balances = Vec::new();
+        for (k, token) in tokens.iter().enumerate() {
+            balances.push(unsigned(word(&results[2 * k], 0, "balanceOf")?));
+            dumped_tokens.push(DumpToken {
+                address: token.clone(),
+                symbol: None,
+                decimals: Some(small(word(&results[2 * k + 1], 0, "decimals")?)),
+            });
+        }
+
+        let mut initialized = Vec::new();
+        for (position, bitmap) in positions.iter().zip(&results[4..]) {
+            let bitmap = word(bitmap, 0, "tickBitmap")?;
+            for bit in 0..256 {
+                if bitmap[31 - bit / 8] & (1 << (bit % 8)) != 0 {
+                    initialized.push((position * 256 + bit as i64) * spacing);
+                }
+            }
+        }
+        let calls: Vec<Call> = initialized.iter().map(|tick| Call::new(address, TICKS).int(*tick)).collect();
+        let ticks = initialized
+            .iter()
+            .zip(self.call(&calls, block)?)
+            .map(|(tick, info)| {
+                Ok(DumpTick {
+                    tick: *tick,
+                    liquidity_gross: unsigned(word(&info, 0, "ticks")?),
+                    liquidity_net: signed(word(&info, 1, "ticks")?),
+                })
+            })
+            .collect::<Result<Vec<_>, RpcError>>()?;
+
+        let [token0, token1]: [DumpToken; 2] = dumped_tokens.try_into().unwrap();
+        Ok(V3PoolDump {
+            token0: Some(token0),
+            token1: Some(token1),
+            slot0,
+            liquidity,
+            fee,
+            tick_spacing: Some(spacing),
+            ticks,
+            balance0: Some(balances[0]),
+            balance1: Some(balances[1]),
+            block_number: Some(block),
+        })
+    }
+
+    /// Builds the V3 pool at `address` as it was at `block`, or at the latest
+    /// block. `token_0` and `token_1` must be the pool's tokens in its order.
+    pub fn load_v3(
+        &self,
+        address: &str,
+        token_0: &Token,
+        token_1: &Token,
+        block: Option<u64>
+    ) -> Result<UniswapV3Pool, RpcError> {
+        Ok(self.dump_v3(address, block)?.to_pool(token_0, token_1)?)
+    }
+}
+
+fn address_of(data: &[Word], what: &str) -> Result<String, RpcError> {
+    Ok(address(word(data, 0, what)?))
+}
+
+// [rpc_error] is the error a JSON-RPC response carries, if any.
+fn rpc_error(response: &Value) -> Option<RpcError> {
+    let error = response.get("error")?;
+    Some(RpcError::Rpc {
+        code: error["code"].as_i64().unwrap_or(0),
+        message: error["message"].as_str().unwrap_or("").to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amm::Amm;
+    use crate::fixtures::{usdc_weth, USDC_WETH};
+    use crate::ledger::Holdings;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tiny_http::{Response, Server};
+
+    const V3_POOL: &str = "0x8ad599c3a0ff1de082011efddc58f1908eb6e6d8";
+    const V2_PAIR: &str = "0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc";
+    const USDC: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+    const WETH: &str = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+    const BLOCK: u64 = 19000000;
+
+    type Results = HashMap<(String, String), String>;
+
+    /// A JSON-RPC node on a local port answering `eth_call`s from a table of
+    /// results. It fails its first `failures` requests with 503, answers
+    /// batches back to front, and counts the requests it is sent.
+    struct MockNode {
+        server: Arc<Server>,
+        url: String,
+        requests: Arc<AtomicUsize>,
+    }
+
+    impl MockNode {
+        fn start(results: Results, failures: usize) -> MockNode {
+            let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+            let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+            let requests = Arc::new(AtomicUsize::new(0));
+            let (incoming, count) = (server.clone(), requests.clone());
+            thread::spawn(move || {
+                for mut request in incoming.incoming_requests() {
+                    if count.fetch_add(1, Ordering::SeqCst) < failures {
+                        request.respond(Response::from_string("busy").with_status_code(503)).ok();
+                        continue;
+                    }
+                    let mut body = String::new();
+                    request.as_reader().read_to_string(&mut body).unwrap();
+                    let answer = match serde_json::from_str(&body).unwrap() {
+                        Value::Array(batch) => Value::Array(batch.iter().rev().map(|r| answer(&results, r)).collect()),
+                        single => answer(&results, &single),
+                    };
+                    request.respond(Response::from_string(answer.to_string())).ok();
+                }
+            });
+            MockNode { server, url, requests }
+        }
+
+        fn requests(&self) -> usize {
+            self.requests.load(Ordering::SeqCst)
+        }
+    }
+
+    impl Drop for MockNode {
+        fn drop(&mut self) {
+            self.server.unblock();
+        }
+    }
+
+    fn answer(results: &Results, request: &Value) -> Value {
+        let id = request["id"].clone();
+        let result = match request["method"].as_str() {
+            Some("eth_blockNumber") => Some(format!("0x{:x}", BLOCK)),
+            Some("eth_call") if request["params"][1] == format!("0x{:x}", BLOCK) => {
+                let call = &request["params"][0];
+                let key = (call["to"].as_str().unwrap().to_string(), call["data"].as_str().unwrap().to_string());
+                results.get(&key).cloned()
+            }
+            _ => None,
+        };
+        match result {
+            Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            None => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32000, "message": "execution reverted" } }),
+        }
+    }
+
+    fn uint(value: u128) -> String {
+        format!("{:064x}", value)
+    }
+
+    fn int(value: i128) -> String {
+        let fill = if value < 0 { "f" } else { "0" };
+        format!("{}{:032x}", fill.repeat(32), value as u128)
+    }
+
+    fn returns(words: &[String]) -> String {
+        format!("0x{}", words.concat())
+    }
+
+    fn add(results: &mut Results, call: Call, words: &[String]) {
+        results.insert((call.to, call.data), returns(words));
+    }
+
+    // [chain] is a node's view of the fixture USDC/WETH pool, holding 50M USDC and 25k WETH, and of a USDC/WETH pair.
+    fn chain() -> Results {
+        let dump: Value = serde_json::from_str(USDC_WETH).unwrap();
+        let number = |value: &Value| value.as_str().unwrap().parse::<i128>().unwrap();
+        let zero = uint(0);
+        let mut results = Results::new();
+
+        let slot0 = [uint(number(&dump["slot0"]["sqrtPriceX96"]) as u128), int(200311)];
+        add(&mut results, Call::new(V3_POOL, SLOT0), &[&slot0[..], &vec![zero.clone(); 5]].concat());
+        add(&mut results, Call::new(V3_POOL, LIQUIDITY), &[uint(number(&dump["liquidity"]) as u128)]);
+        add(&mut results, Call::new(V3_POOL, FEE), &[uint(3000)]);
+        add(&mut results, Call::new(V3_POOL, TICK_SPACING), &[int(60)]);
+        add(&mut results, Call::new(V3_POOL, TOKEN0), &[format!("{:0>64}", &USDC[2..])]);
+        add(&mut results, Call::new(V3_POOL, TOKEN1), &[format!("{:0>64}", &WETH[2..])]);
+        add(&mut results, Call::new(USDC, BALANCE_OF).address(V3_POOL), &[uint(50_000_000_000_000)]);
+        add(&mut results, Call::new(WETH, BALANCE_OF).address(V3_POOL), &[uint(25_000 * 10u128.pow(18))]);
+        add(&mut results, Call::new(USDC, DECIMALS), &[uint(6)]);
+        add(&mut results, Call::new(WETH, DECIMALS), &[uint(18)]);
+
+        let mut bitmaps: HashMap<i64, Word> = (-58..=57).map(|position| (position, [0u8; 32])).collect();
+        for tick in dump["ticks"].as_array().unwrap() {
+            let gross = number(&tick["liquidityGross"]);
+            if gross == 0 {
+                continue;
+            }
+            let index = number(&tick["tickIdx"]) as i64;
+            let compressed = index / 60;
+            let bit = (compressed & 255) as usize;
+            bitmaps.get_mut(&(compressed >> 8)).unwrap()[31 - bit / 8] |= 1 << (bit % 8);
+            let info = [uint(gross as u128), int(number(&tick["liquidityNet"]))];
+            let rest = [vec![zero.clone(); 5], vec![uint(1)]].concat();
+            add(&mut results, Call::new(V3_POOL, TICKS).int(index), &[&info[..], &rest].concat());
+        }
+        for (position, bitmap) in bitmaps {
+            add(&mut results, Call::new(V3_POOL, TICK_BITMAP).int(position), &[hex(&bitmap)]);
+        }
+
+        add(&mut results, Call::new(V2_PAIR, TOKEN0), &[format!("{:0>64}", &USDC[2..])]);
+        add(&mut results, Call::new(V2_PAIR, TOKEN1), &[format!("{:0>64}", &WETH[2..])]);
+        add(&mut results, Call::new(V2_PAIR, GET_RESERVES), &[uint(20_200_000_000_000), uint(10_000 * 10u128.pow(18)), uint(1700000000)]);
+        results
+    }
+
+    #[test]
+    fn words_decode_signed_and_unsigned() {
+        let data = returns(&[int(-5), uint(7), int(-(1i128 << 100))]);
+        let decoded = words(&data).unwrap();
+        assert_eq!((signed(&decoded[0]), small(&decoded[0])), (-5.0, -5));
+        assert_eq!(unsigned(&decoded[1]), 7.0);
+        assert_eq!(signed(&decoded[2]), -(2f64.powi(100)));
+        assert_eq!(unsigned(&[0xff; 32]), 2f64.powi(256));
+        assert_eq!(Call::new(V3_POOL, TICKS).int(-1).data, format!("0xf30dba93{}", "f".repeat(64)));
+        assert!(words("0x1234").is_err());
+    }
+
+    #[test]
+    fn pools_load_at_a_block_in_batches() {
+        let node = MockNode::start(chain(), 0);
+        let client = RpcClient::new(&node.url).with_batch_size(50);
+        let (usdc, weth) = usdc_weth();
+
+        let pool = client.load_v3(V3_POOL, &usdc, &weth, Some(BLOCK)).unwrap();
+        let imported = V3PoolDump::from_json(USDC_WETH).unwrap().to_pool(&usdc, &weth).unwrap();
+        assert_eq!(pool.ticks(), imported.ticks());
+        assert_eq!((pool.sqrt_price_x96(), pool.liquidity(), pool.fee()), (imported.sqrt_price_x96(), imported.liquidity(), 0.003));
+        assert_eq!(pool.holdings(), vec![(usdc.id, 5e13), (weth.id, 2.5e22)]);
+        // pool views, then 4 token calls and 116 bitmap words in three batches, then the ticks
+        assert_eq!(node.requests(), 5);
+
+        let dump = client.dump_v3(V3_POOL, None).unwrap();
+        assert_eq!(dump.block_number, Some(BLOCK));
+        assert_eq!(dump.token1.as_ref().unwrap().decimals, Some(18));
+        // what `fetch` writes is what `v3_chain` pools import
+        assert_eq!(V3PoolDump::from_json(&serde_json::to_string(&dump).unwrap()).unwrap(), dump);
+
+        // tokens given in the other order than the pair's
+        let pair = client.load_v2(V2_PAIR, &weth, &usdc, 0.003, None).unwrap();
+        assert_eq!(pair.reserves(), (1e22, 2.02e13));
+        assert_eq!(pair.token_x(), weth.id);
+    }
+
+    #[test]
+    fn failures_are_retried_or_reported() {
+        let node = MockNode::start(chain(), 2);
+        let client = RpcClient::new(&node.url).with_retries(2, Duration::from_millis(1));
+        assert_eq!(client.block_number().unwrap(), BLOCK);
+        assert_eq!(node.requests(), 3);
+
+        let node = MockNode::start(chain(), 5);
+        let client = RpcClient::new(&node.url).with_retries(1, Duration::from_millis(1));
+        assert_eq!(client.block_number().unwrap_err().to_string(), "cannot reach the node: HTTP 503 after 2 attempts");
+
+        assert!(RpcClient::new("localhost:8545").block_number().unwrap_err().to_string().contains("Bad URL"));
+
+        let client = RpcClient::new(&node.url).with_retries(u32::MAX, Duration::from_millis(250));
+        assert_eq!(client.delay(2), Duration::from_secs(1));
+        assert_eq!(client.delay(40), MAX_BACKOFF);
+
+        let (usdc, weth) = usdc_weth();
+        let node = MockNode::start(chain(), 0);
+        let client = RpcClient::new(&node.url);
+        assert_eq!(
+            client.load_v2(V3_POOL, &usdc, &weth, 0.003, Some(BLOCK)).err().unwrap().to_string(),
+            "node error -32000: execution reverted"
+        );
+        let error = client.load_v3(V3_POOL, &weth, &usdc, Some(BLOCK)).err().unwrap().to_string();
+        assert!(error.starts_with("token0: is 0xa0b8"), "{}", error);
+        assert!(client.load_v2(V2_PAIR, &usdc, &usdc, 0.003, Some(BLOCK)).err().unwrap().to_string().contains("not USDC and USDC"));
+    }
+}
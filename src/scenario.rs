@@ -0,0 +1,1061 @@
+use crate::agent::{Agent, AgentStats, Arbitrageur, InformedTrader, MarketSim, NoiseTrader, PassiveLp, RangeLp};
+use crate::amount::to_raw_price;
+use crate::error::PoolError;
+use crate::events::{PoolEvent, PoolId, Publisher};
+use crate::gas::GasModel;
+use crate::journal::{state_hash, Journal, JournalRecord};
+use crate::ledger::{check_conservation, totals, Account, Holdings, LedgerError};
+use crate::market::{Action, Market, MarketPool};
+use crate::math::price_to_sqrtp;
+use crate::onchain::V3PoolDump;
+use crate::price::{Gbm, JumpDiffusion, LagStats, PriceProcess};
+use crate::searcher::{PoolPair, SearchMetrics, Searcher, Valuation};
+use crate::sink::OpportunitySink;
+use crate::solidly::SolidlyStablePool;
+use crate::stableswap::StableSwapPool;
+use crate::token::{Token, TokenId, TokenRegistry};
+use crate::v2::Pool;
+use crate::v3::UniswapV3Pool;
+use crate::weighted::WeightedPool;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+
+fn default_block_time() -> u64 {
+    12
+}
+
+fn default_slippage_tolerance() -> f64 {
+    0.005
+}
+
+fn default_native_price() -> f64 {
+    1.0
+}
+
+/// A market to simulate: tokens, pools, opening positions, agents, an
+/// optional reference price and an optional arbitrage search, plus the seed
+/// and length of the run. Amounts and prices are in whole tokens, except
+/// V3 ticks and liquidity, gas prices (wei per gas) and `native_price`,
+/// which are raw.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub seed: u64,
+    pub blocks: u64,
+    #[serde(default = "default_block_time")]
+    pub block_time: u64,
+    pub tokens: Vec<TokenConfig>,
+    pub pools: Vec<PoolConfig>,
+    #[serde(default)]
+    pub positions: Vec<PositionConfig>,
+    #[serde(default)]
+    pub agents: Vec<AgentConfig>,
+    #[serde(default)]
+    pub reference: Option<ReferenceConfig>,
+    #[serde(default)]
+    pub search: Option<SearchConfig>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenConfig {
+    pub symbol: String,
+    pub decimals: u8,
+    pub address: String,
+}
+
+/// A pool and its opening state. Tokens are given by symbol.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PoolConfig {
+    V2 {
+        name: String,
+        tokens: [String; 2],
+        reserves: [f64; 2],
+        fee: f64,
+    },
+    // starts without liquidity at [price] of the second token per first token
+    V3 {
+        name: String,
+        tokens: [String; 2],
+        price: f64,
+        fee: f64,
+    },
+    // imports the state of a deployed pool from a JSON dump of it, see [`V3PoolDump`]; [state] is relative to the file naming it
+    V3Chain {
+        name: String,
+        tokens: [String; 2],
+        state: String,
+    },
+    StableSwap {
+        name: String,
+        tokens: Vec<String>,
+        balances: Vec<f64>,
+        amp: f64,
+        fee: f64,
+        #[serde(default)]
+        admin_fee: f64,
+    },
+    Solidly {
+        name: String,
+        tokens: [String; 2],
+        reserves: [f64; 2],
+        fee: f64,
+    },
+    Weighted {
+        name: String,
+        tokens: Vec<String>,
+        weights: Vec<f64>,
+        balances: Vec<f64>,
+        fee: f64,
+    },
+}
+
+impl PoolConfig {
+    pub fn name(&self) -> &str {
+        match self {
+            PoolConfig::V2 { name, .. } => name,
+            PoolConfig::V3 { name, .. } => name,
+            PoolConfig::V3Chain { name, .. } => name,
+            PoolConfig::StableSwap { name, .. } => name,
+            PoolConfig::Solidly { name, .. } => name,
+            PoolConfig::Weighted { name, .. } => name,
+        }
+    }
+
+    /// The type of the pool as written in scenario files.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PoolConfig::V2 { .. } => "v2",
+            PoolConfig::V3 { .. } => "v3",
+            PoolConfig::V3Chain { .. } => "v3_chain",
+            PoolConfig::StableSwap { .. } => "stable_swap",
+            PoolConfig::Solidly { .. } => "solidly",
+            PoolConfig::Weighted { .. } => "weighted",
+        }
+    }
+}
+
+/// Liquidity opened on the first block by a passive LP funded with
+/// `balances`: a V2 `deposit` of both tokens, or V3 `liquidity` over `range`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PositionConfig {
+    pub pool: String,
+    pub balances: BTreeMap<String, f64>,
+    #[serde(default)]
+    pub deposit: Option<[f64; 2]>,
+    #[serde(default)]
+    pub range: Option<[i32; 2]>,
+    #[serde(default)]
+    pub liquidity: Option<f64>,
+}
+
+/// One of the built-in agents of [`crate::agent`] and its opening balances.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum AgentConfig {
+    NoiseTrader {
+        pool: String,
+        tokens: [String; 2],
+        probability: f64,
+        max_fraction: f64,
+        balances: BTreeMap<String, f64>,
+    },
+    InformedTrader {
+        pool: String,
+        base: String,
+        quote: String,
+        threshold: f64,
+        balances: BTreeMap<String, f64>,
+    },
+    RangeLp {
+        pool: String,
+        half_width: i32,
+        liquidity: f64,
+        balances: BTreeMap<String, f64>,
+    },
+    Arbitrageur {
+        first: String,
+        second: String,
+        token_in: String,
+        token_mid: String,
+        max_amount_in: f64,
+        balances: BTreeMap<String, f64>,
+    },
+}
+
+impl AgentConfig {
+    pub fn balances(&self) -> &BTreeMap<String, f64> {
+        match self {
+            AgentConfig::NoiseTrader { balances, .. } => balances,
+            AgentConfig::InformedTrader { balances, .. } => balances,
+            AgentConfig::RangeLp { balances, .. } => balances,
+            AgentConfig::Arbitrageur { balances, .. } => balances,
+        }
+    }
+}
+
+/// A price process driving the reference price of `quote` per `base`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReferenceConfig {
+    pub base: String,
+    pub quote: String,
+    pub process: ProcessConfig,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ProcessConfig {
+    Constant {
+        price: f64,
+    },
+    Gbm {
+        price: f64,
+        drift: f64,
+        volatility: f64,
+    },
+    JumpDiffusion {
+        price: f64,
+        drift: f64,
+        volatility: f64,
+        intensity: f64,
+        jump_mean: f64,
+        jump_volatility: f64,
+    },
+}
+
+/// A two-pool arbitrage search run after every block. Profit is valued in
+/// `numeraire` through the mid price of the pool named `valuation`, or at
+/// `reference_price` numeraire per `token_in` if no pool is named.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SearchConfig {
+    pub first: String,
+    pub second: String,
+    pub token_in: String,
+    pub token_mid: String,
+    pub max_amount_in: f64,
+    pub numeraire: String,
+    #[serde(default)]
+    pub valuation: Option<String>,
+    #[serde(default)]
+    pub reference_price: Option<f64>,
+    #[serde(default)]
+    pub base_fee: f64,
+    #[serde(default)]
+    pub priority_fee: f64,
+    #[serde(default = "default_native_price")]
+    pub native_price: f64,
+    #[serde(default = "default_slippage_tolerance")]
+    pub slippage_tolerance: f64,
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(io::Error),
+    Parse(String),
+    /// `at` says which part of the scenario is wrong, e.g. `pools[1] (v2-2)`
+    Invalid {
+        at: String,
+        message: String,
+    },
+    Ledger(LedgerError),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "{}", e),
+            ScenarioError::Parse(message) => write!(f, "cannot parse scenario: {}", message),
+            ScenarioError::Invalid { at, message } => write!(f, "{}: {}", at, message),
+            ScenarioError::Ledger(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<io::Error> for ScenarioError {
+    fn from(e: io::Error) -> ScenarioError {
+        ScenarioError::Io(e)
+    }
+}
+
+fn invalid(at: &str, message: impl fmt::Display) -> ScenarioError {
+    ScenarioError::Invalid { at: at.to_string(), message: message.to_string() }
+}
+
+// [check] fails with [message] at [at] unless [ok].
+fn check(ok: bool, at: &str, message: &str) -> Result<(), ScenarioError> {
+    if ok { Ok(()) } else { Err(invalid(at, message)) }
+}
+
+impl Scenario {
+    pub fn from_toml(text: &str) -> Result<Scenario, ScenarioError> {
+        toml::from_str(text).map_err(|e| ScenarioError::Parse(e.to_string()))
+    }
+
+    pub fn from_json(text: &str) -> Result<Scenario, ScenarioError> {
+        serde_json::from_str(text).map_err(|e| ScenarioError::Parse(e.to_string()))
+    }
+
+    /// Reads a `.toml` or `.json` scenario file and validates it.
+    pub fn load(path: &Path) -> Result<Scenario, ScenarioError> {
+        let mut scenario: Scenario = read_file(path)?;
+        resolve_paths(&mut scenario.pools, path);
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// Reports the first thing in the scenario that cannot be built.
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        self.build().map(|_| ())
+    }
+
+    /// Sets up the market, agents and searcher described by the scenario, ready to run.
+    pub fn build(&self) -> Result<ScenarioRun, ScenarioError> {
+        check(self.blocks > 0, "blocks", "a run needs at least one block")?;
+        check(self.block_time > 0, "block_time", "blocks need a positive block time")?;
+
+        let registry = build_tokens(&self.tokens)?;
+        let tokens = Tokens(&registry);
+        let mut market = Market::new();
+        let (names, holdings) = build_pools(&mut market, &tokens, &self.pools)?;
+        let pools = Pools(&names);
+
+        let search = match &self.search {
+            Some(search) => {
+                let events = market.bus().subscribe();
+                Some((build_searcher(search, &market, &tokens, &pools)?, events))
+            }
+            None => None,
+        };
+
+        let mut sim = MarketSim::new(market, self.seed, self.block_time);
+        let mut reference = None;
+        if let Some(config) = &self.reference {
+            let (base, quote) = (tokens.get(&config.base, "reference")?, tokens.get(&config.quote, "reference")?);
+            check(base.id != quote.id, "reference", "base and quote must differ")?;
+            let process = build_process(&config.process, base, quote)?;
+            let (base, quote) = (base.id, quote.id);
+            sim.add_price_feed(process, vec![(base, quote)]).map_err(|e| invalid("reference", e))?;
+            let mut lag_pools: Vec<PoolId> = names
+                .values()
+                .copied()
+                .filter(|id| {
+                    let pool_tokens = sim.market().amm(*id).unwrap().tokens();
+                    pool_tokens.contains(&base) && pool_tokens.contains(&quote)
+                })
+                .collect();
+            lag_pools.sort();
+            reference = Some((base, quote, lag_pools));
+        }
+
+        let mut agents = Vec::new();
+        for (k, position) in self.positions.iter().enumerate() {
+            let at = format!("positions[{}] ({})", k, position.pool);
+            let action = build_position(position, sim.market(), &pools, &tokens, &at)?;
+            let account = fund(agents.len() + 1, &position.balances, &tokens, &at)?;
+            let name = format!("LP in {}", position.pool);
+            agents.push((name, sim.add_agent(Box::new(PassiveLp::new(action)), account)));
+        }
+        for (k, agent) in self.agents.iter().enumerate() {
+            let at = format!("agents[{}]", k);
+            let built = build_agent(agent, sim.market(), &pools, &tokens, &at)?;
+            let account = fund(agents.len() + 1, agent.balances(), &tokens, &at)?;
+            let name = format!("{} {}", built.name(), k);
+            agents.push((name, sim.add_agent(built, account)));
+        }
+
+        Ok(ScenarioRun {
+            registry,
+            sim,
+            scenario: self.clone(),
+            blocks: self.blocks,
+            agents,
+            holdings,
+            search,
+            reference,
+        })
+    }
+}
+
+/// Tokens and pools with their opening positions, but no agents: what the
+/// one-shot commands quote, search and inspect. Positions are opened as the
+/// file is built rather than on the first block.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MarketFile {
+    pub tokens: Vec<TokenConfig>,
+    pub pools: Vec<PoolConfig>,
+    #[serde(default)]
+    pub positions: Vec<PositionConfig>,
+}
+
+/// A built [`MarketFile`].
+pub struct LoadedMarket {
+    pub registry: TokenRegistry,
+    pub market: Market,
+    configs: Vec<PoolConfig>,
+    names: HashMap<String, PoolId>,
+    holdings: Vec<Arc<dyn Holdings>>,
+}
+
+impl LoadedMarket {
+    pub fn pool_id(&self, name: &str) -> Option<PoolId> {
+        self.names.get(name).copied()
+    }
+
+    pub fn config(&self, id: PoolId) -> Option<&PoolConfig> {
+        self.configs.get(id.0)
+    }
+
+    /// The raw balance of each token in pool `id`.
+    pub fn holdings(&self, id: PoolId) -> Vec<(TokenId, f64)> {
+        self.holdings.get(id.0).map_or(Vec::new(), |pool| pool.holdings())
+    }
+
+    /// Looks a token up by symbol.
+    pub fn token(&self, symbol: &str) -> Option<&Token> {
+        self.registry.by_symbol(symbol).and_then(|id| self.registry.get(id))
+    }
+}
+
+impl MarketFile {
+    /// Reads a `.toml` or `.json` file of tokens, pools and positions.
+    pub fn load(path: &Path) -> Result<MarketFile, ScenarioError> {
+        let mut file: MarketFile = read_file(path)?;
+        resolve_paths(&mut file.pools, path);
+        Ok(file)
+    }
+
+    pub fn build(&self) -> Result<LoadedMarket, ScenarioError> {
+        let registry = build_tokens(&self.tokens)?;
+        let tokens = Tokens(&registry);
+        let mut market = Market::new();
+        let (names, holdings) = build_pools(&mut market, &tokens, &self.pools)?;
+        let pools = Pools(&names);
+        for (k, position) in self.positions.iter().enumerate() {
+            let at = format!("positions[{}] ({})", k, position.pool);
+            let action = build_position(position, &market, &pools, &tokens, &at)?;
+            let mut account = fund(k + 1, &position.balances, &tokens, &at)?;
+            market.execute(&mut account, &action).map_err(|e| invalid(&at, e))?;
+        }
+        Ok(LoadedMarket {
+            registry,
+            market,
+            configs: self.pools.clone(),
+            names,
+            holdings,
+        })
+    }
+}
+
+// [read_file] parses [path] as TOML or JSON, going by its extension.
+fn read_file<T: DeserializeOwned>(path: &Path) -> Result<T, ScenarioError> {
+    let parse = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => |text: &str| toml::from_str(text).map_err(|e| ScenarioError::Parse(e.to_string())),
+        Some("json") => |text: &str| serde_json::from_str(text).map_err(|e| ScenarioError::Parse(e.to_string())),
+        _ => {
+            return Err(invalid(&path.display().to_string(), "files end in .toml or .json"));
+        }
+    };
+    parse(&fs::read_to_string(path)?)
+}
+
+// [resolve_paths] makes the files named by [pools] relative to the directory of [path], the file naming them.
+fn resolve_paths(pools: &mut [PoolConfig], path: &Path) {
+    let Some(dir) = path.parent() else {
+        return;
+    };
+    for pool in pools {
+        if let PoolConfig::V3Chain { state, .. } = pool {
+            *state = dir.join(&*state).to_string_lossy().into_owned();
+        }
+    }
+}
+
+fn build_tokens(configs: &[TokenConfig]) -> Result<TokenRegistry, ScenarioError> {
+    let mut registry = TokenRegistry::new();
+    for (k, token) in configs.iter().enumerate() {
+        let at = format!("tokens[{}] ({})", k, token.symbol);
+        check(registry.by_symbol(&token.symbol).is_none(), &at, "symbol is already taken")?;
+        check(registry.by_address(&token.address).is_none(), &at, "address is already taken")?;
+        registry.register(&token.symbol, token.decimals, &token.address);
+    }
+    Ok(registry)
+}
+
+// pool ids by name, and what each pool holds
+type BuiltPools = (HashMap<String, PoolId>, Vec<Arc<dyn Holdings>>);
+
+// [build_pools] adds every pool to [market] and returns the ids by name and what each pool holds.
+fn build_pools(
+    market: &mut Market,
+    tokens: &Tokens,
+    configs: &[PoolConfig]
+) -> Result<BuiltPools, ScenarioError> {
+    let mut names = HashMap::new();
+    let mut holdings = Vec::new();
+    for (k, pool) in configs.iter().enumerate() {
+        let at = format!("pools[{}] ({})", k, pool.name());
+        check(!names.contains_key(pool.name()), &at, "name is already taken")?;
+        let (id, pool_holdings) = add_pool(market, tokens, pool, &at)?;
+        names.insert(pool.name().to_string(), id);
+        holdings.push(pool_holdings);
+    }
+    Ok((names, holdings))
+}
+
+// [Tokens] looks tokens up by symbol, with errors that say where the symbol came from.
+struct Tokens<'a>(&'a TokenRegistry);
+
+impl<'a> Tokens<'a> {
+    fn get(&self, symbol: &str, at: &str) -> Result<&'a Token, ScenarioError> {
+        self.0
+            .by_symbol(symbol)
+            .and_then(|id| self.0.get(id))
+            .ok_or_else(|| invalid(at, format!("unknown token {:?}", symbol)))
+    }
+
+    fn id(&self, symbol: &str, at: &str) -> Result<TokenId, ScenarioError> {
+        Ok(self.get(symbol, at)?.id)
+    }
+
+    // [raw] converts [amount] whole tokens of [symbol] to raw units, failing on negative or non-finite amounts.
+    fn raw(&self, symbol: &str, amount: f64, at: &str) -> Result<f64, ScenarioError> {
+        check(amount >= 0.0 && amount.is_finite(), at, "amounts must be finite and not negative")?;
+        Ok(self.get(symbol, at)?.amount(amount).raw_f64())
+    }
+}
+
+struct Pools<'a>(&'a HashMap<String, PoolId>);
+
+impl Pools<'_> {
+    fn id(&self, name: &str, at: &str) -> Result<PoolId, ScenarioError> {
+        self.0.get(name).copied().ok_or_else(|| invalid(at, format!("unknown pool {:?}", name)))
+    }
+}
+
+fn check_fee(fee: f64, at: &str) -> Result<(), ScenarioError> {
+    check((0.0..1.0).contains(&fee), at, "fee must be at least 0 and below 1")
+}
+
+// [add_pool] adds [pool] to [market] and returns its id and what it holds, for conservation checks.
+fn add_pool(
+    market: &mut Market,
+    tokens: &Tokens,
+    pool: &PoolConfig,
+    at: &str
+) -> Result<(PoolId, Arc<dyn Holdings>), ScenarioError> {
+    let events = Publisher::new(market.bus(), market.next_id());
+    let added: (PoolId, Arc<dyn Holdings>) = match pool {
+        PoolConfig::V2 { name, tokens: [x, y], reserves, fee } => {
+            check_fee(*fee, at)?;
+            check(x != y, at, "a pool needs two different tokens")?;
+            let (rx, ry) = (tokens.raw(x, reserves[0], at)?, tokens.raw(y, reserves[1], at)?);
+            let id = market.add_v2(name, Pool::new(tokens.id(x, at)?, tokens.id(y, at)?, rx, ry, *fee));
+            let Ok(MarketPool::V2(pool)) = market.pool(id) else {
+                unreachable!("added as V2");
+            };
+            (id, pool.clone())
+        }
+        PoolConfig::V3 { name, tokens: [t0, t1], price, fee } => {
+            check_fee(*fee, at)?;
+            check(t0 != t1, at, "a pool needs two different tokens")?;
+            check(*price > 0.0 && price.is_finite(), at, "price must be positive")?;
+            let (t0, t1) = (tokens.get(t0, at)?, tokens.get(t1, at)?);
+            let sqrt_price_x96 = price_to_sqrtp(*price, t0.decimals, t1.decimals);
+            let id = market.add_v3(name, UniswapV3Pool::new(t0, t1, sqrt_price_x96, *fee));
+            let Ok(MarketPool::V3(pool)) = market.pool(id) else {
+                unreachable!("added as V3");
+            };
+            (id, pool.clone())
+        }
+        PoolConfig::V3Chain { name, tokens: [t0, t1], state } => {
+            check(t0 != t1, at, "a pool needs two different tokens")?;
+            let (t0, t1) = (tokens.get(t0, at)?, tokens.get(t1, at)?);
+            let pool = V3PoolDump::load(Path::new(state))
+                .and_then(|dump| dump.to_pool(t0, t1))
+                .map_err(|e| invalid(at, format!("{}: {}", state, e)))?;
+            let id = market.add_v3(name, pool);
+            let Ok(MarketPool::V3(pool)) = market.pool(id) else {
+                unreachable!("added as V3");
+            };
+            (id, pool.clone())
+        }
+        PoolConfig::StableSwap { name, tokens: symbols, balances, amp, fee, admin_fee } => {
+            check_fee(*fee, at)?;
+            check_fee(*admin_fee, at)?;
+            check(
+                symbols.len() >= 2 && symbols.len() == balances.len(),
+                at,
+                "give a balance for each of at least two tokens"
+            )?;
+            check(*amp > 0.0, at, "amp must be positive")?;
+            let coins = symbols
+                .iter()
+                .map(|s| tokens.get(s, at))
+                .collect::<Result<Vec<_>, _>>()?;
+            let raw = raw_amounts(tokens, symbols, balances, at)?;
+            let pool = StableSwapPool::new(&coins, &raw, *amp, *fee, *admin_fee).map_err(|e| invalid(at, e))?;
+            let pool = Arc::new(pool.with_events(events));
+            (market.add_pool(name, pool.clone()), pool)
+        }
+        PoolConfig::Solidly { name, tokens: [t0, t1], reserves, fee } => {
+            check_fee(*fee, at)?;
+            check(t0 != t1, at, "a pool needs two different tokens")?;
+            let (r0, r1) = (tokens.raw(t0, reserves[0], at)?, tokens.raw(t1, reserves[1], at)?);
+            let pool = SolidlyStablePool::new(tokens.get(t0, at)?, tokens.get(t1, at)?, r0, r1, *fee)
+                .map_err(|e| invalid(at, e))?;
+            let pool = Arc::new(pool.with_events(events));
+            (market.add_pool(name, pool.clone()), pool)
+        }
+        PoolConfig::Weighted { name, tokens: symbols, weights, balances, fee } => {
+            check(symbols.len() == balances.len(), at, "give a balance for each token")?;
+            let ids = symbols
+                .iter()
+                .map(|s| tokens.id(s, at))
+                .collect::<Result<Vec<_>, _>>()?;
+            let raw = raw_amounts(tokens, symbols, balances, at)?;
+            let pool = WeightedPool::new(&ids, weights, *fee).map_err(|e| invalid(at, e))?;
+            // the opening balances come from an account that exists only to make the first join
+            let genesis: Vec<(TokenId, f64)> = ids.iter().copied().zip(raw.iter().copied()).collect();
+            pool.join(&Account::new(0, &genesis), &raw, 0.0).map_err(|e| invalid(at, e))?;
+            let pool = Arc::new(pool.with_events(events));
+            (market.add_pool(name, pool.clone()), pool)
+        }
+    };
+    Ok(added)
+}
+
+fn raw_amounts(tokens: &Tokens, symbols: &[String], amounts: &[f64], at: &str) -> Result<Vec<f64>, ScenarioError> {
+    symbols
+        .iter()
+        .zip(amounts)
+        .map(|(s, a)| tokens.raw(s, *a, at))
+        .collect()
+}
+
+// [build_process] turns a price given in whole tokens into a process over raw prices.
+fn build_process(config: &ProcessConfig, base: &Token, quote: &Token) -> Result<Box<dyn PriceProcess>, ScenarioError> {
+    let raw = |price: f64| to_raw_price(price, base.decimals, quote.decimals);
+    let process: Result<Box<dyn PriceProcess>, PoolError> = match config {
+        ProcessConfig::Constant { price } => Gbm::new(raw(*price), 0.0, 0.0).map(|p| Box::new(p) as _),
+        ProcessConfig::Gbm { price, drift, volatility } =>
+            Gbm::new(raw(*price), *drift, *volatility).map(|p| Box::new(p) as _),
+        ProcessConfig::JumpDiffusion { price, drift, volatility, intensity, jump_mean, jump_volatility } =>
+            JumpDiffusion::new(raw(*price), *drift, *volatility, *intensity, *jump_mean, *jump_volatility).map(|p|
+                Box::new(p) as _
+            ),
+    };
+    process.map_err(|e| invalid("reference", e))
+}
+
+fn build_searcher(
+    config: &SearchConfig,
+    market: &Market,
+    tokens: &Tokens,
+    pools: &Pools
+) -> Result<Searcher, ScenarioError> {
+    let at = "search";
+    let (first, second) = (pools.id(&config.first, at)?, pools.id(&config.second, at)?);
+    let (token_in, token_mid) = (tokens.id(&config.token_in, at)?, tokens.id(&config.token_mid, at)?);
+    check_route(market, (first, second), (token_in, token_mid), at)?;
+    let numeraire = tokens.get(&config.numeraire, at)?;
+    let valuation = match (&config.valuation, config.reference_price) {
+        (Some(pool), None) => Valuation::PoolMid(pools.id(pool, at)?),
+        (None, Some(price)) => {
+            let profit_token = tokens.get(&config.token_in, at)?;
+            Valuation::Reference(to_raw_price(price, profit_token.decimals, numeraire.decimals))
+        }
+        _ => {
+            return Err(invalid(at, "give either a valuation pool or a reference_price"));
+        }
+    };
+    check(
+        (0.0..1.0).contains(&config.slippage_tolerance),
+        at,
+        "slippage_tolerance must be at least 0 and below 1"
+    )?;
+    check(
+        config.base_fee >= 0.0 && config.priority_fee >= 0.0,
+        at,
+        "base_fee and priority_fee must not be negative"
+    )?;
+
+    let gas = GasModel::new(config.base_fee, config.priority_fee);
+    let mut searcher = Searcher::new(numeraire.id, valuation, gas, config.native_price, config.slippage_tolerance);
+    // the searcher numbers pools in the order they are added, which must match the market's ids
+    for k in 0..market.len() {
+        let id = PoolId(k);
+        searcher.add_pool(market.name(id).unwrap(), market.pool(id).unwrap().shared());
+    }
+    searcher.add_pair(PoolPair {
+        first,
+        second,
+        token_in,
+        token_mid,
+        max_amount_in: tokens.raw(&config.token_in, config.max_amount_in, at)?,
+    });
+    Ok(searcher)
+}
+
+// [check_route] fails at [at] unless the two pools of [route] differ and both trade the two tokens of [pair].
+fn check_route(
+    market: &Market,
+    route: (PoolId, PoolId),
+    pair: (TokenId, TokenId),
+    at: &str
+) -> Result<(), ScenarioError> {
+    check(route.0 != route.1, at, "first and second must be different pools")?;
+    check(pair.0 != pair.1, at, "token_in and token_mid must be different tokens")?;
+    for id in [route.0, route.1] {
+        let traded = market.pool(id).map_err(|e| invalid(at, e))?.amm().tokens();
+        check(
+            traded.contains(&pair.0) && traded.contains(&pair.1),
+            at,
+            "both pools must trade token_in and token_mid"
+        )?;
+    }
+    Ok(())
+}
+
+fn build_position(
+    config: &PositionConfig,
+    market: &Market,
+    pools: &Pools,
+    tokens: &Tokens,
+    at: &str
+) -> Result<Action, ScenarioError> {
+    let pool = pools.id(&config.pool, at)?;
+    match (market.pool(pool).unwrap(), config.deposit, config.range, config.liquidity) {
+        (MarketPool::V2(v2), Some([amount_x, amount_y]), None, None) => {
+            let symbol = |id| tokens.0.symbol(id).to_string();
+            Ok(Action::Deposit {
+                pool,
+                amount_x: tokens.raw(&symbol(v2.token_x()), amount_x, at)?,
+                amount_y: tokens.raw(&symbol(v2.token_y()), amount_y, at)?,
+            })
+        }
+        (MarketPool::V3(_), None, Some([lower_tick, upper_tick]), Some(liquidity)) => {
+            check(lower_tick < upper_tick, at, "the range must run from a lower to a higher tick")?;
+            check(liquidity > 0.0, at, "liquidity must be positive")?;
+            Ok(Action::Mint { pool, lower_tick, upper_tick, liquidity_delta: liquidity })
+        }
+        (MarketPool::V2(_), ..) => Err(invalid(at, "a V2 position is a deposit of both tokens and nothing else")),
+        (MarketPool::V3(_), ..) => Err(invalid(at, "a V3 position is a range and a liquidity and nothing else")),
+        (MarketPool::Other(_), ..) => Err(invalid(at, "positions can only be opened in V2 and V3 pools")),
+    }
+}
+
+fn build_agent(
+    config: &AgentConfig,
+    market: &Market,
+    pools: &Pools,
+    tokens: &Tokens,
+    at: &str
+) -> Result<Box<dyn Agent>, ScenarioError> {
+    let agent: Box<dyn Agent> = match config {
+        AgentConfig::NoiseTrader { pool, tokens: [a, b], probability, max_fraction, .. } => {
+            check((0.0..=1.0).contains(probability), at, "probability must be between 0 and 1")?;
+            check(*max_fraction > 0.0 && *max_fraction <= 1.0, at, "max_fraction must be above 0 and at most 1")?;
+            let pair = (tokens.id(a, at)?, tokens.id(b, at)?);
+            Box::new(NoiseTrader::new(pools.id(pool, at)?, pair, *probability, *max_fraction))
+        }
+        AgentConfig::InformedTrader { pool, base, quote, threshold, .. } => {
+            check(*threshold >= 0.0, at, "threshold must not be negative")?;
+            Box::new(InformedTrader::new(pools.id(pool, at)?, tokens.id(base, at)?, tokens.id(quote, at)?, *threshold))
+        }
+        AgentConfig::RangeLp { pool, half_width, liquidity, .. } => {
+            let id = pools.id(pool, at)?;
+            check(matches!(market.pool(id), Ok(MarketPool::V3(_))), at, "range LPs need a V3 pool")?;
+            check(*half_width > 0 && *liquidity > 0.0, at, "half_width and liquidity must be positive")?;
+            Box::new(RangeLp::new(id, *half_width, *liquidity))
+        }
+        AgentConfig::Arbitrageur { first, second, token_in, token_mid, max_amount_in, .. } => {
+            let route = (pools.id(first, at)?, pools.id(second, at)?);
+            let pair = (tokens.id(token_in, at)?, tokens.id(token_mid, at)?);
+            check_route(market, route, pair, at)?;
+            Box::new(Arbitrageur::new(route.0, route.1, pair.0, pair.1, tokens.raw(token_in, *max_amount_in, at)?))
+        }
+    };
+    Ok(agent)
+}
+
+// [fund] opens account [id] holding [balances], given in whole tokens.
+fn fund(id: usize, balances: &BTreeMap<String, f64>, tokens: &Tokens, at: &str) -> Result<Account, ScenarioError> {
+    let raw = balances
+        .iter()
+        .map(|(symbol, amount)| Ok((tokens.id(symbol, at)?, tokens.raw(symbol, *amount, at)?)))
+        .collect::<Result<Vec<_>, ScenarioError>>()?;
+    Ok(Account::new(id as i32, &raw))
+}
+
+/// What a finished scenario run did.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ScenarioReport {
+    pub blocks: u64,
+    pub agents: Vec<(String, AgentStats)>,
+    // how far each pool trading the reference pair trailed the reference price, against the pool fee
+    pub lags: Vec<(String, LagStats)>,
+    pub search: Option<SearchMetrics>,
+}
+
+/// A built scenario, ready to run.
+pub struct ScenarioRun {
+    pub registry: TokenRegistry,
+    pub sim: MarketSim,
+    // what the run was built from, for its journal
+    scenario: Scenario,
+    blocks: u64,
+    agents: Vec<(String, usize)>,
+    holdings: Vec<Arc<dyn Holdings>>,
+    search: Option<(Searcher, Receiver<PoolEvent>)>,
+    // the reference pair and the pools that trade it
+    reference: Option<(TokenId, TokenId, Vec<PoolId>)>,
+}
+
+impl ScenarioRun {
+    /// Journals the run to `writer`: the scenario first, then every price move and
+    /// action with the state it left.
+    pub fn record(&mut self, writer: Box<dyn Write>) {
+        let mut journal = Journal::new(writer, self.holdings.clone());
+        journal.write(&JournalRecord::Start { scenario: Box::new(self.scenario.clone()) });
+        self.sim.set_journal(journal);
+    }
+
+    /// The name agent `agent` of the simulation is reported under.
+    pub fn agent_name(&self, agent: usize) -> Option<&str> {
+        self.agents
+            .iter()
+            .find(|(_, k)| *k == agent)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Digests the current state of the run, as its journal records it.
+    pub fn state_hash(&self) -> u64 {
+        let accounts: Vec<&Account> = (0..self.sim.agents()).map(|k| self.sim.account(k)).collect();
+        state_hash(self.sim.market(), &self.holdings, &accounts)
+    }
+
+    /// Plays the scenario, searching after every block and publishing what it finds
+    /// to `sink`, then checks that no tokens were created or destroyed.
+    pub fn run(&mut self, sink: &mut dyn OpportunitySink) -> Result<ScenarioReport, ScenarioError> {
+        let supply = totals(&self.holders());
+        let mut lags: Vec<(PoolId, LagStats)> = match &self.reference {
+            Some((_, _, pools)) => pools
+                .iter()
+                .map(|id| (*id, LagStats::new(self.sim.market().amm(*id).unwrap().fee())))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let (search, reference) = (&mut self.search, &self.reference);
+        let mut failure = None;
+        self.sim.run_blocks(self.blocks, |_, market| {
+            if let (Some((searcher, events)), None) = (search.as_mut(), &failure) {
+                if let Err(e) = searcher.handle(&events.try_iter().collect::<Vec<_>>(), sink) {
+                    failure = Some(e);
+                }
+            }
+            if let Some((base, quote, _)) = reference {
+                for (id, lag) in lags.iter_mut() {
+                    if let Some(deviation) = market.deviation(*id, *base, *quote) {
+                        lag.record(deviation);
+                    }
+                }
+            }
+        });
+        if let Some(e) = failure {
+            return Err(ScenarioError::Io(e));
+        }
+        if let Some(journal) = self.sim.take_journal() {
+            journal.finish()?;
+        }
+        check_conservation(&supply, &self.holders()).map_err(ScenarioError::Ledger)?;
+
+        Ok(ScenarioReport {
+            blocks: self.blocks,
+            agents: self.agents
+                .iter()
+                .map(|(name, k)| (name.clone(), self.sim.stats(*k).clone()))
+                .collect(),
+            lags: lags
+                .into_iter()
+                .map(|(id, lag)| (self.sim.market().name(id).unwrap().to_string(), lag))
+                .collect(),
+            search: self.search.as_ref().map(|(searcher, _)| searcher.metrics().clone()),
+        })
+    }
+
+    // [holders] lists every agent account and every pool.
+    fn holders(&self) -> Vec<&dyn Holdings> {
+        let mut holders: Vec<&dyn Holdings> = self.agents
+            .iter()
+            .map(|(_, k)| self.sim.account(*k) as &dyn Holdings)
+            .collect();
+        holders.extend(self.holdings.iter().map(|pool| &**pool));
+        holders
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::JsonLinesSink;
+
+    const V3_SCENARIO: &str = include_str!("../scenarios/v3.toml");
+
+    // [every_pool_type] is a small JSON scenario with one pool of each type, each traded by a noise trader.
+    fn every_pool_type() -> String {
+        let pools = ["v2", "v3", "curve", "solidly", "balancer"];
+        let agents: Vec<String> = pools
+            .iter()
+            .map(|pool| {
+                format!(
+                    r#"{{"type": "noise_trader", "pool": "{}", "tokens": ["USDC", "DAI"], "probability": 1.0, "max_fraction": 0.01, "balances": {{"USDC": 1000.0, "DAI": 1000.0}}}}"#,
+                    pool
+                )
+            })
+            .collect();
+        format!(
+            r#"{{
+                "seed": 9,
+                "blocks": 10,
+                "tokens": [
+                    {{"symbol": "USDC", "decimals": 6, "address": "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"}},
+                    {{"symbol": "DAI", "decimals": 18, "address": "0x6B175474E89094C44Da98b954EedeAC495271d0F"}}
+                ],
+                "pools": [
+                    {{"type": "v2", "name": "v2", "tokens": ["USDC", "DAI"], "reserves": [100000.0, 100000.0], "fee": 0.003}},
+                    {{"type": "v3", "name": "v3", "tokens": ["USDC", "DAI"], "price": 1.0, "fee": 0.0005}},
+                    {{"type": "stable_swap", "name": "curve", "tokens": ["USDC", "DAI"], "balances": [100000.0, 100000.0], "amp": 100.0, "fee": 0.0004}},
+                    {{"type": "solidly", "name": "solidly", "tokens": ["USDC", "DAI"], "reserves": [100000.0, 100000.0], "fee": 0.0001}},
+                    {{"type": "weighted", "name": "balancer", "tokens": ["USDC", "DAI"], "weights": [0.5, 0.5], "balances": [100000.0, 100000.0], "fee": 0.001}}
+                ],
+                "positions": [
+                    {{"pool": "v3", "range": [276200, 276400], "liquidity": 1e18, "balances": {{"USDC": 100000.0, "DAI": 100000.0}}}}
+                ],
+                "agents": [{}],
+                "reference": {{"base": "USDC", "quote": "DAI", "process": {{"type": "constant", "price": 1.0}}}}
+            }}"#,
+            agents.join(",")
+        )
+    }
+
+    #[test]
+    fn scenarios_build_and_run() {
+        let scenario = Scenario::from_toml(V3_SCENARIO).unwrap();
+        let json = serde_json::to_string(&scenario).unwrap();
+        assert_eq!(Scenario::from_json(&json).unwrap(), scenario);
+
+        let mut sink = JsonLinesSink::new(io::sink());
+        let report = scenario.build().unwrap().run(&mut sink).unwrap();
+        assert_eq!(report.agents.len(), 5);
+        assert_eq!(report.agents[0].0, "LP in v3-1");
+        assert_eq!(report.lags.len(), 2);
+        assert_eq!(report.lags[1].1.samples, 20);
+        assert!(report.search.unwrap().events > 0);
+
+        let mixed = Scenario::from_json(&every_pool_type()).unwrap();
+        let report = mixed.build().unwrap().run(&mut sink).unwrap();
+        assert_eq!(report.lags.len(), 5);
+        assert!(report.agents.iter().all(|(_, stats)| stats.failures == 0));
+        assert!(report.agents[1..].iter().all(|(_, stats)| stats.actions == 10));
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let error = |edit: &dyn Fn(&mut Scenario)| {
+            let mut scenario = Scenario::from_toml(V3_SCENARIO).unwrap();
+            edit(&mut scenario);
+            scenario.validate().unwrap_err().to_string()
+        };
+
+        assert_eq!(
+            error(&|s| {
+                if let PoolConfig::V3 { tokens, .. } = &mut s.pools[1] {
+                    tokens[1] = "USDT".to_string();
+                }
+            }),
+            "pools[1] (v3-2): unknown token \"USDT\""
+        );
+        assert_eq!(error(&|s| s.positions[0].pool = "v4".to_string()), "positions[0] (v4): unknown pool \"v4\"");
+        assert_eq!(
+            error(&|s| s.positions[1].deposit = Some([1.0, 1.0])),
+            "positions[1] (v3-2): a V3 position is a range and a liquidity and nothing else"
+        );
+        assert_eq!(
+            error(&|s| s.pools.push(s.pools[0].clone())),
+            "pools[2] (v3-1): name is already taken"
+        );
+        assert_eq!(error(&|s| s.blocks = 0), "blocks: a run needs at least one block");
+        assert_eq!(
+            error(&|s| s.search.as_mut().unwrap().reference_price = Some(5000.0)),
+            "search: give either a valuation pool or a reference_price"
+        );
+        assert_eq!(
+            error(&|s| s.search.as_mut().unwrap().base_fee = -1.0),
+            "search: base_fee and priority_fee must not be negative"
+        );
+        assert_eq!(
+            error(&|s| {
+                s.tokens.push(TokenConfig {
+                    symbol: "USDC".to_string(),
+                    decimals: 6,
+                    address: "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48".to_string(),
+                });
+                s.search.as_mut().unwrap().token_mid = "USDC".to_string();
+            }),
+            "search: both pools must trade token_in and token_mid"
+        );
+        assert_eq!(
+            error(&|s| {
+                s.agents.push(AgentConfig::Arbitrageur {
+                    first: "v3-1".to_string(),
+                    second: "v3-1".to_string(),
+                    token_in: "WETH".to_string(),
+                    token_mid: "DAI".to_string(),
+                    max_amount_in: 1.0,
+                    balances: BTreeMap::new(),
+                });
+            }),
+            "agents[3]: first and second must be different pools"
+        );
+
+        let typo = V3_SCENARIO.replace("half_width", "half_wdth");
+        assert!(Scenario::from_toml(&typo).unwrap_err().to_string().contains("unknown field `half_wdth`"));
+        assert_eq!(
+            Scenario::load(Path::new("scenarios/v3.yaml")).unwrap_err().to_string(),
+            "scenarios/v3.yaml: files end in .toml or .json"
+        );
+    }
+
+    #[test]
+    fn pool_files_import_chain_state() {
+        let loaded = MarketFile::load(Path::new("scenarios/chain.toml")).unwrap().build().unwrap();
+        let id = loaded.pool_id("univ3").unwrap();
+        let Ok(MarketPool::V3(pool)) = loaded.market.pool(id) else {
+            panic!("imported as V3");
+        };
+        assert_eq!(pool.ticks().len(), 12);
+        assert_eq!(pool.token_0(), loaded.token("USDC").unwrap().id);
+
+        let mut file = MarketFile::load(Path::new("scenarios/chain.toml")).unwrap();
+        if let PoolConfig::V3Chain { tokens, .. } = &mut file.pools[0] {
+            tokens.swap(0, 1);
+        }
+        let error = file.build().err().unwrap().to_string();
+        assert!(error.starts_with("pools[0] (univ3): scenarios/usdc-weth-3000.json: token0: is 0xa0b8"), "{}", error);
+    }
+}
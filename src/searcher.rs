@@ -0,0 +1,263 @@
+use crate::amm::Amm;
+use crate::arb::find_two_pool_net_arb;
+use crate::error::PoolError;
+use crate::events::{PoolEvent, PoolId};
+use crate::gas::GasModel;
+use crate::opportunity::{NamedPool, Opportunity, PriceSource};
+use crate::sink::OpportunitySink;
+use crate::token::TokenId;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub type SharedPool = Arc<dyn Amm + Send + Sync>;
+
+/// Two watched pools searched against each other, starting and ending in
+/// `token_in`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PoolPair {
+    pub first: PoolId,
+    pub second: PoolId,
+    pub token_in: TokenId,
+    pub token_mid: TokenId,
+    pub max_amount_in: f64,
+}
+
+impl PoolPair {
+    pub fn touches(&self, pool: PoolId) -> bool {
+        self.first == pool || self.second == pool
+    }
+}
+
+/// How profits are valued in the numeraire; see [`PriceSource`].
+#[derive(Debug, PartialEq, Clone)]
+pub enum Valuation {
+    PoolMid(PoolId),
+    Reference(f64),
+}
+
+#[derive(Debug, PartialEq, Clone, Default, Serialize)]
+pub struct LatencyStats {
+    pub count: usize,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    fn record(&mut self, latency: Duration) {
+        self.count += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        self.total / (self.count as u32)
+    }
+}
+
+/// Counters kept by a [`Searcher`]. Latencies run from the oldest pool
+/// update behind an evaluation to the end of that evaluation, and to the
+/// publication of any opportunity it found.
+#[derive(Debug, PartialEq, Clone, Default, Serialize)]
+pub struct SearchMetrics {
+    pub events: usize,
+    pub evaluations: usize,
+    pub failures: usize,
+    pub opportunities: usize,
+    pub to_evaluation: LatencyStats,
+    pub to_opportunity: LatencyStats,
+}
+
+/// Searches pool pairs again only when one of their pools reports a change.
+pub struct Searcher {
+    pools: Vec<(String, SharedPool)>,
+    pairs: Vec<PoolPair>,
+    numeraire: TokenId,
+    valuation: Valuation,
+    gas: GasModel,
+    native_price: f64,
+    slippage_tolerance: f64,
+    metrics: SearchMetrics,
+}
+
+impl Searcher {
+    pub fn new(
+        numeraire: TokenId,
+        valuation: Valuation,
+        gas: GasModel,
+        native_price: f64,
+        slippage_tolerance: f64
+    ) -> Searcher {
+        Searcher {
+            pools: Vec::new(),
+            pairs: Vec::new(),
+            numeraire,
+            valuation,
+            gas,
+            native_price,
+            slippage_tolerance,
+            metrics: SearchMetrics::default(),
+        }
+    }
+
+    /// Starts watching `pool` and returns the id its events must carry.
+    pub fn add_pool(&mut self, name: &str, pool: SharedPool) -> PoolId {
+        self.pools.push((name.to_string(), pool));
+        PoolId(self.pools.len() - 1)
+    }
+
+    pub fn add_pair(&mut self, pair: PoolPair) {
+        self.pairs.push(pair);
+    }
+
+    pub fn metrics(&self) -> &SearchMetrics {
+        &self.metrics
+    }
+
+    /// Searches each pair touched by `events` once and publishes what it finds to
+    /// `sink`. Failed searches are counted and skipped.
+    pub fn handle(&mut self, events: &[PoolEvent], sink: &mut dyn OpportunitySink) -> io::Result<usize> {
+        self.metrics.events += events.len();
+
+        // oldest update per changed pool
+        let mut changed: BTreeMap<PoolId, Instant> = BTreeMap::new();
+        for event in events {
+            let oldest = changed.entry(event.pool).or_insert(event.emitted_at);
+            *oldest = (*oldest).min(event.emitted_at);
+        }
+
+        let mut published = 0;
+        for k in 0..self.pairs.len() {
+            let pair = &self.pairs[k];
+            let oldest = changed
+                .iter()
+                .filter(|(pool, _)| pair.touches(**pool))
+                .map(|(_, emitted_at)| *emitted_at)
+                .min();
+            let Some(oldest) = oldest else {
+                continue;
+            };
+
+            let opportunity = self.evaluate(pair);
+            self.metrics.evaluations += 1;
+            self.metrics.to_evaluation.record(oldest.elapsed());
+            match opportunity {
+                Ok(Some(opportunity)) => {
+                    sink.publish(&opportunity)?;
+                    self.metrics.opportunities += 1;
+                    self.metrics.to_opportunity.record(oldest.elapsed());
+                    published += 1;
+                }
+                Ok(None) => {}
+                Err(_) => self.metrics.failures += 1,
+            }
+        }
+        Ok(published)
+    }
+
+    /// Handles events until every sender has gone, taking whatever has queued up
+    /// since the last search as one batch.
+    pub fn run(&mut self, events: &Receiver<PoolEvent>, sink: &mut dyn OpportunitySink) -> io::Result<()> {
+        while let Ok(first) = events.recv() {
+            let mut batch = vec![first];
+            batch.extend(events.try_iter());
+            self.handle(&batch, sink)?;
+        }
+        Ok(())
+    }
+
+    // [evaluate] searches [pair] against snapshots, so each leg is quoted at the version it reports.
+    fn evaluate(&self, pair: &PoolPair) -> Result<Option<Opportunity>, PoolError> {
+        let (first_name, first) = &self.pools[pair.first.0];
+        let (second_name, second) = &self.pools[pair.second.0];
+        let (first, second) = (first.snapshot(), second.snapshot());
+        let best = find_two_pool_net_arb(
+            &*first,
+            &*second,
+            pair.token_in,
+            pair.token_mid,
+            pair.max_amount_in,
+            &self.gas,
+            self.native_price
+        )?;
+        let Some(best) = best else {
+            return Ok(None);
+        };
+        let mid;
+        let source = match &self.valuation {
+            Valuation::PoolMid(pool) => {
+                mid = self.pools[pool.0].1.snapshot();
+                PriceSource::PoolMid(&*mid)
+            }
+            Valuation::Reference(price) => PriceSource::Reference(*price),
+        };
+        Opportunity::from_arb(
+            &best,
+            &NamedPool { name: first_name, pool: &*first },
+            &NamedPool { name: second_name, pool: &*second },
+            self.numeraire,
+            &source,
+            self.slippage_tolerance
+        ).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{EventBus, Publisher};
+    use crate::sink::ChannelSink;
+    use crate::v2::{add, sync, Pool};
+
+    const WETH: TokenId = TokenId(0);
+    const DAI: TokenId = TokenId(1);
+
+    #[test]
+    fn only_touched_pairs_are_searched() {
+        let bus = EventBus::new();
+        let events = bus.subscribe();
+        let cheap = Arc::new(Pool::new(WETH, DAI, 100.0, 200000.0, 0.003).with_events(Publisher::new(&bus, PoolId(0))));
+        let dear = Arc::new(Pool::new(WETH, DAI, 100.0, 200000.0, 0.003).with_events(Publisher::new(&bus, PoolId(1))));
+        let other = Arc::new(Pool::new(WETH, DAI, 100.0, 200000.0, 0.003).with_events(Publisher::new(&bus, PoolId(2))));
+
+        let mut searcher = Searcher::new(WETH, Valuation::Reference(1.0), GasModel::free(), 1.0, 0.005);
+        searcher.add_pool("cheap", cheap.clone());
+        searcher.add_pool("dear", dear.clone());
+        searcher.add_pool("other", other.clone());
+        searcher.add_pair(PoolPair {
+            first: PoolId(0),
+            second: PoolId(1),
+            token_in: WETH,
+            token_mid: DAI,
+            max_amount_in: 10.0,
+        });
+        let (mut sink, opportunities) = ChannelSink::new();
+
+        // a change to a pool outside every pair costs nothing
+        add(&other, 1.0, 2000.0).unwrap();
+        searcher.handle(&events.try_iter().collect::<Vec<_>>(), &mut sink).unwrap();
+        assert_eq!(searcher.metrics().evaluations, 0);
+
+        // two updates to the pair are searched once, against the newest state
+        add(&dear, 1.0, 2000.0).unwrap();
+        sync(&dear, 100.0, 220000.0);
+        searcher.handle(&events.try_iter().collect::<Vec<_>>(), &mut sink).unwrap();
+        let metrics = searcher.metrics();
+        assert_eq!((metrics.events, metrics.evaluations, metrics.opportunities), (3, 1, 1));
+        assert!(metrics.to_opportunity.max >= metrics.to_opportunity.mean());
+
+        let opportunity = opportunities.try_recv().unwrap();
+        // each leg names the version of its own pool
+        let legs: Vec<(&str, u64)> = opportunity.route
+            .iter()
+            .map(|l| (l.pool.as_str(), l.pool_version))
+            .collect();
+        assert_eq!(legs, vec![("dear", 2), ("cheap", 0)]);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::opportunity::Opportunity;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Stdout, Write};
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// Destination for opportunities found by a searcher: executors,
+/// dashboards, logs.
+pub trait OpportunitySink {
+    fn publish(&mut self, opportunity: &Opportunity) -> io::Result<()>;
+}
+
+/// Renders `opportunity` as a single line of JSON, without the trailing newline.
+pub fn to_json_line(opportunity: &Opportunity) -> io::Result<String> {
+    serde_json::to_string(opportunity).map_err(io::Error::from)
+}
+
+/// Writes one JSON object per line to any writer, flushing after each one so
+/// that readers tailing the output see complete lines.
+pub struct JsonLinesSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> JsonLinesSink<W> {
+        JsonLinesSink { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl JsonLinesSink<Stdout> {
+    pub fn stdout() -> JsonLinesSink<Stdout> {
+        JsonLinesSink::new(io::stdout())
+    }
+}
+
+impl JsonLinesSink<BufWriter<File>> {
+    /// Appends to the file at `path`, creating it if needed.
+    pub fn file<P: AsRef<Path>>(path: P) -> io::Result<JsonLinesSink<BufWriter<File>>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(JsonLinesSink::new(BufWriter::new(file)))
+    }
+}
+
+impl<W: Write> OpportunitySink for JsonLinesSink<W> {
+    fn publish(&mut self, opportunity: &Opportunity) -> io::Result<()> {
+        writeln!(self.writer, "{}", to_json_line(opportunity)?)?;
+        self.writer.flush()
+    }
+}
+
+/// Hands opportunities to another thread in the same process.
+pub struct ChannelSink {
+    sender: Sender<Opportunity>,
+}
+
+impl ChannelSink {
+    pub fn new() -> (ChannelSink, Receiver<Opportunity>) {
+        let (sender, receiver) = channel();
+        (ChannelSink { sender }, receiver)
+    }
+}
+
+impl OpportunitySink for ChannelSink {
+    fn publish(&mut self, opportunity: &Opportunity) -> io::Result<()> {
+        self.sender
+            .send(opportunity.clone())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "opportunity receiver was dropped"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opportunity::Leg;
+    use crate::token::TokenId;
+    use std::fs;
+
+    fn opportunity() -> Opportunity {
+        Opportunity {
+            route: vec![Leg {
+                pool: "cp".to_string(),
+                token_in: TokenId(0),
+                token_out: TokenId(1),
+                amount_in: 1.0,
+                amount_out: 2.0,
+                min_amount_out: 1.5,
+                pool_version: 3,
+            }],
+            token_in: TokenId(0),
+            amount_in: 1.0,
+            profit_token: TokenId(0),
+            gross_profit: 0.5,
+            gas_cost: 0.25,
+            net_profit: 0.25,
+            numeraire: TokenId(1),
+            net_value: 0.5,
+            slippage_tolerance: 0.25,
+        }
+    }
+
+    #[test]
+    fn json_lines() {
+        let mut sink = JsonLinesSink::new(Vec::new());
+        sink.publish(&opportunity()).unwrap();
+        sink.publish(&opportunity()).unwrap();
+
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("{\"route\":[{\"pool\":\"cp\",\"token_in\":0,\"token_out\":1,"));
+        assert!(lines[0].ends_with("\"slippage_tolerance\":0.25}"));
+
+        let path = std::env::temp_dir().join(format!("rusty-arb-sink-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        JsonLinesSink::file(&path).unwrap().publish(&opportunity()).unwrap();
+        JsonLinesSink::file(&path).unwrap().publish(&opportunity()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), output);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn channel() {
+        let (mut sink, receiver) = ChannelSink::new();
+        sink.publish(&opportunity()).unwrap();
+        assert_eq!(receiver.recv().unwrap(), opportunity());
+
+        drop(receiver);
+        assert_eq!(sink.publish(&opportunity()).unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,257 @@
+use crate::error::PoolError;
+use crate::market::MarketPool;
+use crate::v2::{Pool, V2Snapshot};
+use crate::v3::{UniswapV3Pool, V3Snapshot};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// starts every binary snapshot, followed by the format version
+const MAGIC: &[u8; 4] = b"RARB";
+const BINARY_VERSION: u8 = 1;
+
+/// The saved state of a V2 or V3 pool, for checkpointing a run or keeping a
+/// test fixture.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolSnapshot {
+    V2(V2Snapshot),
+    V3(V3Snapshot),
+}
+
+impl PoolSnapshot {
+    /// Saves `pool`, or returns None for pool types that cannot be saved.
+    pub fn of(pool: &MarketPool) -> Option<PoolSnapshot> {
+        match pool {
+            MarketPool::V2(pool) => Some(PoolSnapshot::V2(pool.to_snapshot())),
+            MarketPool::V3(pool) => Some(PoolSnapshot::V3(pool.to_snapshot())),
+            MarketPool::Other(_) => None,
+        }
+    }
+
+    pub fn restore(&self) -> Result<MarketPool, PoolError> {
+        Ok(match self {
+            PoolSnapshot::V2(snapshot) => MarketPool::V2(Arc::new(Pool::from_snapshot(snapshot)?)),
+            PoolSnapshot::V3(snapshot) => MarketPool::V3(Arc::new(UniswapV3Pool::from_snapshot(snapshot)?)),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SnapshotFormat {
+    Json,
+    // bincode behind a short header, several times smaller than JSON for tick-heavy pools
+    Binary,
+}
+
+impl SnapshotFormat {
+    /// Picks the format from the extension of `path`: .json or .bin.
+    pub fn from_path(path: &Path) -> Option<SnapshotFormat> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Some(SnapshotFormat::Json),
+            Some("bin") => Some(SnapshotFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Parse(String),
+    Format(String),
+    /// `at` says which part of an imported state is wrong, e.g. `slot0`
+    Invalid {
+        at: String,
+        message: String,
+    },
+    Pool(PoolError),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::Parse(message) => write!(f, "cannot parse snapshot: {}", message),
+            SnapshotError::Format(message) => write!(f, "{}", message),
+            SnapshotError::Invalid { at, message } => write!(f, "{}: {}", at, message),
+            SnapshotError::Pool(e) => write!(f, "invalid pool state: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> SnapshotError {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<PoolError> for SnapshotError {
+    fn from(e: PoolError) -> SnapshotError {
+        SnapshotError::Pool(e)
+    }
+}
+
+pub fn encode<T: Serialize>(value: &T, format: SnapshotFormat) -> Result<Vec<u8>, SnapshotError> {
+    match format {
+        SnapshotFormat::Json =>
+            serde_json::to_vec_pretty(value).map_err(|e| SnapshotError::Parse(e.to_string())),
+        SnapshotFormat::Binary => {
+            let mut bytes = MAGIC.to_vec();
+            bytes.push(BINARY_VERSION);
+            bincode::serialize_into(&mut bytes, value).map_err(|e| SnapshotError::Parse(e.to_string()))?;
+            Ok(bytes)
+        }
+    }
+}
+
+pub fn decode<T: DeserializeOwned>(bytes: &[u8], format: SnapshotFormat) -> Result<T, SnapshotError> {
+    match format {
+        SnapshotFormat::Json =>
+            serde_json::from_slice(bytes).map_err(|e| SnapshotError::Parse(e.to_string())),
+        SnapshotFormat::Binary => {
+            let body = match bytes.strip_prefix(MAGIC.as_slice()) {
+                Some([BINARY_VERSION, body @ ..]) => body,
+                Some(_) => {
+                    return Err(SnapshotError::Format("unsupported binary snapshot version".to_string()));
+                }
+                None => {
+                    return Err(SnapshotError::Format("not a binary snapshot".to_string()));
+                }
+            };
+            bincode::deserialize(body).map_err(|e| SnapshotError::Parse(e.to_string()))
+        }
+    }
+}
+
+fn format_of(path: &Path) -> Result<SnapshotFormat, SnapshotError> {
+    SnapshotFormat::from_path(path).ok_or_else(|| {
+        SnapshotError::Format(format!("{}: snapshots end in .json or .bin", path.display()))
+    })
+}
+
+/// Writes `value` to `path` in the format its extension names.
+pub fn save<T: Serialize>(path: &Path, value: &T) -> Result<(), SnapshotError> {
+    let bytes = encode(value, format_of(path)?)?;
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+pub fn load<T: DeserializeOwned>(path: &Path) -> Result<T, SnapshotError> {
+    let format = format_of(path)?;
+    decode(&fs::read(path)?, format)
+}
+
+/// Rebuilds the pool saved at `path`.
+pub fn load_pool(path: &Path) -> Result<MarketPool, SnapshotError> {
+    Ok(load::<PoolSnapshot>(path)?.restore()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amm::Amm;
+    use crate::fixtures::usdc_weth;
+    use crate::ledger::{Account, Holdings};
+    use crate::math::price_to_sqrtp;
+    use crate::v3::v3_swap;
+
+    // [v3_pool] is a pool with overlapping positions of two owners whose price has moved off its starting tick.
+    fn v3_pool() -> UniswapV3Pool {
+        let (usdc, weth) = usdc_weth();
+        let sqrtp = price_to_sqrtp(2000.0, 18, 6);
+        let pool = UniswapV3Pool::new(&weth, &usdc, sqrtp, 0.003);
+        let (weth, usdc) = (weth.id, usdc.id);
+        let mut alice = Account::new(1, &[(weth, 1e24), (usdc, 1e15)]);
+        let bob = Account::new(2, &[(weth, 1e24), (usdc, 1e15)]);
+        let tick = pool.tick();
+        pool.mint(&alice, tick - 6000, tick + 6000, 1e18).unwrap();
+        pool.mint(&bob, tick - 600, tick + 1200, 5e17).unwrap();
+        v3_swap(&mut alice, &pool, weth, 5e19, 0.0).unwrap();
+        pool
+    }
+
+    #[test]
+    fn pools_round_trip_through_both_formats() {
+        let v3 = v3_pool();
+        let v2 = Pool::new(v3.token_0(), v3.token_1(), 1e21, 2e12, 0.003);
+        let saved = vec![PoolSnapshot::V2(v2.to_snapshot()), PoolSnapshot::V3(v3.to_snapshot())];
+
+        for format in [SnapshotFormat::Json, SnapshotFormat::Binary] {
+            let bytes = encode(&saved, format).unwrap();
+            let loaded: Vec<PoolSnapshot> = decode(&bytes, format).unwrap();
+            assert_eq!(loaded, saved);
+        }
+        let json = encode(&saved, SnapshotFormat::Json).unwrap();
+        let binary = encode(&saved, SnapshotFormat::Binary).unwrap();
+        assert!(binary.len() * 2 < json.len());
+
+        // restored pools trade exactly like the originals
+        let MarketPool::V3(restored) = saved[1].restore().unwrap() else { panic!() };
+        assert_eq!(restored.ticks(), v3.ticks());
+        assert_eq!(restored.holdings(), v3.holdings());
+        assert_eq!(restored.version(), v3.version());
+        let (token_0, token_1) = (v3.token_0(), v3.token_1());
+        let mut trader = Account::new(3, &[(token_1, 1e12)]);
+        let mut twin = Account::new(3, &[(token_1, 1e12)]);
+        let out = v3.apply_swap(&mut trader, token_1, token_0, 5e11, 0.0).unwrap();
+        assert_eq!(restored.apply_swap(&mut twin, token_1, token_0, 5e11, 0.0).unwrap(), out);
+        assert_eq!(restored.to_snapshot(), v3.to_snapshot());
+
+        let path = std::env::temp_dir().join(format!("rusty-arb-v2-{}.bin", std::process::id()));
+        save(&path, &saved[0]).unwrap();
+        let restored = load_pool(&path);
+        fs::remove_file(&path).unwrap();
+        let Ok(MarketPool::V2(restored)) = restored else { panic!() };
+        assert_eq!(restored.state(), v2.state());
+    }
+
+    #[test]
+    fn bad_snapshots_are_rejected() {
+        let saved = PoolSnapshot::V3(v3_pool().to_snapshot());
+        let bytes = encode(&saved, SnapshotFormat::Binary).unwrap();
+        assert!(matches!(decode::<PoolSnapshot>(&bytes[1..], SnapshotFormat::Binary), Err(SnapshotError::Format(_))));
+        assert!(matches!(
+            decode::<PoolSnapshot>(&bytes[..bytes.len() - 3], SnapshotFormat::Binary),
+            Err(SnapshotError::Parse(_))
+        ));
+
+        let PoolSnapshot::V3(mut snapshot) = saved else { panic!() };
+        snapshot.ticks[0].liquidity_gross = 0.0;
+        assert_eq!(
+            UniswapV3Pool::from_snapshot(&snapshot).err(),
+            Some(PoolError::InvalidParameter { name: "liquidity_gross", value: 0.0 })
+        );
+        snapshot.ticks[0].liquidity_gross = 1e18;
+        snapshot.ticks[1].tick = snapshot.ticks[0].tick;
+        assert!(UniswapV3Pool::from_snapshot(&snapshot).is_err());
+
+        // positions must be distinct and held by their ticks
+        let good = v3_pool().to_snapshot();
+        let invalid = |change: &dyn Fn(&mut V3Snapshot)| {
+            let mut snapshot = good.clone();
+            change(&mut snapshot);
+            UniswapV3Pool::from_snapshot(&snapshot).err()
+        };
+        assert_eq!(invalid(&|_| ()), None);
+        assert_eq!(
+            invalid(&|s| s.positions.push(s.positions[0].clone())),
+            Some(PoolError::InvalidParameter { name: "owner", value: 1.0 })
+        );
+        assert!(matches!(invalid(&|s| s.positions[1].liquidity *= 2.0), Some(PoolError::InvalidParameter { name: "position", .. })));
+        assert!(matches!(invalid(&|s| s.positions[0].upper_tick += 1), Some(PoolError::InvalidTickRange { .. })));
+        assert!(matches!(invalid(&|s| s.tick += 100), Some(PoolError::InvalidParameter { name: "tick", .. })));
+        assert_eq!(
+            invalid(&|s| s.balance_1 = -1.0),
+            Some(PoolError::InvalidParameter { name: "balance_1", value: -1.0 })
+        );
+
+        assert!(matches!(format_of(Path::new("pool.toml")), Err(SnapshotError::Format(_))));
+    }
+}
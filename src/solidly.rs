@@ -0,0 +1,382 @@
+use crate::amm::{check_pair, Amm};
+use crate::amount::scale;
+use crate::error::PoolError;
+use crate::events::{EventKind, Publisher};
+use crate::ledger::{Account, EntryReason, Holdings};
+use crate::token::{Token, TokenId};
+use std::sync::RwLock;
+
+const MAX_ITERATIONS: usize = 255;
+
+/// Solidly stable pair (Velodrome/Aerodrome `stable = true`), holding
+/// `x³y + y³x` constant. Reserves are raw base units; the invariant works on
+/// reserves normalized by each token's decimals. The fee is taken from the
+/// input and stays in the pool.
+pub struct SolidlyStablePool {
+    token_0: TokenId,
+    token_1: TokenId,
+    decimals_0: u8,
+    decimals_1: u8,
+    fee: f64,
+    state: RwLock<ReserveState>,
+    events: Publisher,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct ReserveState {
+    reserve_0: f64,
+    reserve_1: f64,
+    version: u64,
+}
+
+impl Clone for SolidlyStablePool {
+    fn clone(&self) -> SolidlyStablePool {
+        SolidlyStablePool {
+            token_0: self.token_0,
+            token_1: self.token_1,
+            decimals_0: self.decimals_0,
+            decimals_1: self.decimals_1,
+            fee: self.fee,
+            state: RwLock::new(*self.state.read().unwrap()),
+            events: Publisher::default(),
+        }
+    }
+}
+
+/// The invariant x³y + y³x for normalized reserves.
+pub fn stable_k(x: f64, y: f64) -> f64 {
+    x * y * (x * x + y * y)
+}
+
+/// Solves x0 y³ + x0³ y = `k` for y with Newton's method, starting from `y`.
+pub fn get_y(x0: f64, k: f64, mut y: f64) -> Result<f64, PoolError> {
+    for _ in 0..MAX_ITERATIONS {
+        let f = x0 * y * y * y + x0 * x0 * x0 * y;
+        let d = 3.0 * x0 * y * y + x0 * x0 * x0;
+        let dy = (k - f) / d;
+        y += dy;
+        if dy.abs() <= y.abs() * 1e-15 {
+            return Ok(y);
+        }
+    }
+    Err(PoolError::NoConvergence)
+}
+
+impl SolidlyStablePool {
+    pub fn new(
+        token_0: &Token,
+        token_1: &Token,
+        reserve_0: f64,
+        reserve_1: f64,
+        fee: f64
+    ) -> Result<SolidlyStablePool, PoolError> {
+        if token_0.id == token_1.id {
+            return Err(PoolError::UnknownToken { token: token_1.id });
+        }
+        if !(0.0..1.0).contains(&fee) {
+            return Err(PoolError::InvalidParameter { name: "fee", value: fee });
+        }
+        for (name, reserve) in [("reserve_0", reserve_0), ("reserve_1", reserve_1)] {
+            if !reserve.is_finite() || reserve < 0.0 {
+                return Err(PoolError::InvalidParameter { name, value: reserve });
+            }
+        }
+        Ok(SolidlyStablePool {
+            token_0: token_0.id,
+            token_1: token_1.id,
+            decimals_0: token_0.decimals,
+            decimals_1: token_1.decimals,
+            fee,
+            state: RwLock::new(ReserveState { reserve_0, reserve_1, version: 0 }),
+            events: Publisher::default(),
+        })
+    }
+
+    pub fn with_events(mut self, events: Publisher) -> SolidlyStablePool {
+        self.events = events;
+        self
+    }
+
+    pub fn token_0(&self) -> TokenId {
+        self.token_0
+    }
+
+    pub fn token_1(&self) -> TokenId {
+        self.token_1
+    }
+
+    pub fn reserves(&self) -> (f64, f64) {
+        let state = self.state.read().unwrap();
+        (state.reserve_0, state.reserve_1)
+    }
+
+    /// The invariant x³y + y³x on the current normalized reserves.
+    pub fn invariant(&self) -> f64 {
+        let (reserve_0, reserve_1) = self.reserves();
+        stable_k(reserve_0 / scale(self.decimals_0), reserve_1 / scale(self.decimals_1))
+    }
+
+    // [normalized] returns (reserve_in, reserve_out, scale_in, scale_out) for a swap of [token_in], with the reserves of [state] normalized.
+    fn normalized(
+        &self,
+        state: &ReserveState,
+        token_in: TokenId,
+        token_out: TokenId
+    ) -> Result<(f64, f64, f64, f64), PoolError> {
+        check_pair(self, token_in, token_out)?;
+        let (reserve_0, reserve_1) = (state.reserve_0, state.reserve_1);
+        if reserve_0 <= 0.0 || reserve_1 <= 0.0 {
+            return Err(PoolError::NotInitialized);
+        }
+        let (scale_0, scale_1) = (scale(self.decimals_0), scale(self.decimals_1));
+        if token_in == self.token_0 {
+            Ok((reserve_0 / scale_0, reserve_1 / scale_1, scale_0, scale_1))
+        } else {
+            Ok((reserve_1 / scale_1, reserve_0 / scale_0, scale_1, scale_0))
+        }
+    }
+
+    // [amount_out] is the output of swapping [amount_in] of [token_in] against [state].
+    fn amount_out(
+        &self,
+        state: &ReserveState,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64
+    ) -> Result<f64, PoolError> {
+        let (x, y, scale_in, scale_out) = self.normalized(state, token_in, token_out)?;
+        if !amount_in.is_finite() || amount_in <= 0.0 {
+            return Err(PoolError::InvalidParameter { name: "amount_in", value: amount_in });
+        }
+        let amount_in_less_fee = (amount_in - amount_in * self.fee) / scale_in;
+        let new_y = get_y(x + amount_in_less_fee, stable_k(x, y), y)?;
+        Ok((y - new_y) * scale_out)
+    }
+}
+
+impl Holdings for SolidlyStablePool {
+    fn holdings(&self) -> Vec<(TokenId, f64)> {
+        let (reserve_0, reserve_1) = self.reserves();
+        vec![(self.token_0, reserve_0), (self.token_1, reserve_1)]
+    }
+}
+
+/// Swaps `amount_in` of `token_in` and returns the amount of the other token paid out.
+pub fn solidly_swap(
+    trader: &mut Account,
+    pool: &SolidlyStablePool,
+    token_in: TokenId,
+    amount_in: f64,
+    min_amount_out: f64
+) -> Result<f64, PoolError> {
+    if !amount_in.is_finite() || amount_in <= 0.0 {
+        return Err(PoolError::InvalidParameter { name: "amount_in", value: amount_in });
+    }
+    let token_out = if token_in == pool.token_0 { pool.token_1 } else { pool.token_0 };
+    trader.ensure(token_in, amount_in)?;
+    let mut state = pool.state.write().unwrap();
+    let amount_out = pool.amount_out(&state, token_in, token_out, amount_in)?;
+    if amount_out < min_amount_out {
+        return Err(PoolError::SlippageExceeded { min_out: min_amount_out, amount_out });
+    }
+
+    if token_in == pool.token_0 {
+        state.reserve_0 += amount_in;
+        state.reserve_1 -= amount_out;
+    } else {
+        state.reserve_1 += amount_in;
+        state.reserve_0 -= amount_out;
+    }
+    state.version += 1;
+    pool.events.emit(EventKind::Swap, state.version);
+    drop(state);
+
+    trader.debit_with_fee(token_in, amount_in, pool.fee)?;
+    trader.credit(token_out, amount_out, EntryReason::Swap)?;
+    Ok(amount_out)
+}
+
+impl Amm for SolidlyStablePool {
+    fn tokens(&self) -> Vec<TokenId> {
+        vec![self.token_0, self.token_1]
+    }
+
+    fn fee(&self) -> f64 {
+        self.fee
+    }
+
+    fn spot_price(&self, token_in: TokenId, token_out: TokenId) -> Result<f64, PoolError> {
+        let (x, y, scale_in, scale_out) = self.normalized(&self.state.read().unwrap(), token_in, token_out)?;
+        // -dy/dx along x³y + y³x = k
+        let price = (3.0 * x * x * y + y * y * y) / (x * x * x + 3.0 * x * y * y);
+        Ok((price * scale_out) / scale_in)
+    }
+
+    fn quote_exact_in(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64
+    ) -> Result<f64, PoolError> {
+        self.amount_out(&self.state.read().unwrap(), token_in, token_out, amount_in)
+    }
+
+    fn quote_exact_out(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_out: f64
+    ) -> Result<f64, PoolError> {
+        let (x, y, scale_in, scale_out) = self.normalized(&self.state.read().unwrap(), token_in, token_out)?;
+        if !amount_out.is_finite() || amount_out <= 0.0 {
+            return Err(PoolError::InvalidParameter { name: "amount_out", value: amount_out });
+        }
+        let out = amount_out / scale_out;
+        if out >= y {
+            return Err(PoolError::InsufficientLiquidity);
+        }
+        // the invariant is symmetric, so the same solver gives the input side
+        let new_x = get_y(y - out, stable_k(x, y), x)?;
+        Ok(((new_x - x) * scale_in) / (1.0 - self.fee))
+    }
+
+    fn apply_swap(
+        &self,
+        trader: &mut Account,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64,
+        min_amount_out: f64
+    ) -> Result<f64, PoolError> {
+        check_pair(self, token_in, token_out)?;
+        solidly_swap(trader, self, token_in, amount_in, min_amount_out)
+    }
+
+    fn version(&self) -> u64 {
+        self.state.read().unwrap().version
+    }
+
+    fn snapshot(&self) -> Box<dyn Amm + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::{find_two_pool_arb, Route};
+    use crate::ledger::{check_conservation, totals};
+    use crate::math::price_to_sqrtp;
+    use crate::token::TokenRegistry;
+    use crate::v3::UniswapV3Pool;
+
+    fn set_up_tokens() -> (Token, Token) {
+        let mut registry = TokenRegistry::new();
+        let usdc = registry.register("USDC", 6, "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+        let dai = registry.register("DAI", 18, "0x6B175474E89094C44Da98b954EedeAC495271d0F");
+        (registry.get(usdc).unwrap().clone(), registry.get(dai).unwrap().clone())
+    }
+
+    fn set_up_pool() -> (Token, Token, SolidlyStablePool) {
+        let (usdc, dai) = set_up_tokens();
+        let pool = SolidlyStablePool::new(
+            &usdc,
+            &dai,
+            usdc.amount(1000000.0).raw_f64(),
+            dai.amount(1000000.0).raw_f64(),
+            0.0005
+        ).unwrap();
+        (usdc, dai, pool)
+    }
+
+    #[test]
+    fn invariant_solver() {
+        assert_eq!(stable_k(2.0, 3.0), 78.0);
+        let y = get_y(2.0, 78.0, 1.0).unwrap();
+        assert!((y - 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn quotes_across_decimals() {
+        let (usdc, dai, pool) = set_up_pool();
+
+        assert!((pool.spot_price(usdc.id, dai.id).unwrap() - 1e12).abs() < 1e-3);
+
+        // the curve is flat around the peg, so 1000 USDC buys almost 1000 DAI less the fee
+        let out = pool.quote_exact_in(usdc.id, dai.id, usdc.amount(1000.0).raw_f64()).unwrap();
+        let human_out = out / 1e18;
+        assert!(human_out < 999.5 && human_out > 999.49);
+
+        let back = pool.quote_exact_out(usdc.id, dai.id, out).unwrap();
+        assert!((back - usdc.amount(1000.0).raw_f64()).abs() / back < 1e-9);
+    }
+
+    #[test]
+    fn swap_conserves_tokens() {
+        let (usdc, dai, pool) = set_up_pool();
+        let mut trader = Account::new(1, &[(dai.id, dai.amount(5000.0).raw_f64())]);
+        let supply = totals(&[&trader, &pool]);
+        let k = pool.invariant();
+
+        let out = pool.apply_swap(&mut trader, dai.id, usdc.id, dai.amount(5000.0).raw_f64(), 0.0).unwrap();
+
+        assert_eq!(trader.balance(usdc.id), out);
+        assert_eq!(trader.balance(dai.id), 0.0);
+        assert!(pool.invariant() > k);
+        check_conservation(&supply, &[&trader, &pool]).unwrap();
+        assert!(matches!(
+            pool.apply_swap(&mut trader, usdc.id, dai.id, out, out * 1e12),
+            Err(PoolError::SlippageExceeded { .. })
+        ));
+
+        // a failed swap leaves the reserves alone
+        let reserves = pool.reserves();
+        for amount in [-5.0, 0.0, f64::NAN] {
+            assert!(matches!(
+                pool.apply_swap(&mut trader, usdc.id, dai.id, amount, 0.0),
+                Err(PoolError::InvalidParameter { name: "amount_in", .. })
+            ));
+        }
+        assert_eq!(pool.reserves(), reserves);
+    }
+
+    #[test]
+    fn bad_parameters_are_rejected() {
+        let (usdc, dai) = set_up_tokens();
+        assert_eq!(
+            SolidlyStablePool::new(&usdc, &usdc, 1.0, 1.0, 0.0005).err(),
+            Some(PoolError::UnknownToken { token: usdc.id })
+        );
+        assert_eq!(
+            SolidlyStablePool::new(&usdc, &dai, 1.0, 1.0, 1.0).err(),
+            Some(PoolError::InvalidParameter { name: "fee", value: 1.0 })
+        );
+        assert!(matches!(
+            SolidlyStablePool::new(&usdc, &dai, f64::NAN, 1.0, 0.0005).err(),
+            Some(PoolError::InvalidParameter { name: "reserve_0", .. })
+        ));
+        assert_eq!(
+            SolidlyStablePool::new(&usdc, &dai, 1.0, -1.0, 0.0005).err(),
+            Some(PoolError::InvalidParameter { name: "reserve_1", value: -1.0 })
+        );
+    }
+
+    #[test]
+    fn arb_against_v3() {
+        let (usdc, dai, stable) = set_up_pool();
+        let lp = Account::new(
+            2,
+            &[(usdc.id, usdc.amount(10000000.0).raw_f64()), (dai.id, dai.amount(10000000.0).raw_f64())]
+        );
+        // USDC trades at 1.01 DAI in the V3 pool
+        let v3 = UniswapV3Pool::new(&usdc, &dai, price_to_sqrtp(1.01, 6, 18), 0.0005);
+        let tick = v3.tick();
+        v3.mint(&lp, tick - 1000, tick + 1000, 1000000000000000.0).unwrap();
+
+        let best = find_two_pool_arb(&stable, &v3, usdc.id, dai.id, usdc.amount(100000.0).raw_f64())
+            .unwrap()
+            .unwrap();
+        assert_eq!(best.route, Route::SecondToFirst);
+        assert!(best.profit > 0.0);
+    }
+}
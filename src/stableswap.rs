@@ -0,0 +1,584 @@
+use crate::amm::{check_pair, Amm};
+use crate::amount::scale;
+use crate::error::PoolError;
+use crate::events::{EventKind, Publisher};
+use crate::ledger::{Account, EntryReason, Holdings};
+use crate::token::{Token, TokenId};
+use std::sync::RwLock;
+
+const MAX_ITERATIONS: usize = 255;
+
+// largest factor A may move by in a single ramp, as in the Curve contracts
+const MAX_A_CHANGE: f64 = 10.0;
+
+// every balance is scaled to this many decimals before touching the invariant
+const PRECISION_DECIMALS: u8 = 18;
+
+#[derive(Clone)]
+struct Ramp {
+    initial_a: f64,
+    future_a: f64,
+    initial_time: u64,
+    future_time: u64,
+}
+
+/// Everything that moves the pool's prices, kept behind a single lock. The
+/// timestamp belongs here because A, and so every quote, depends on it.
+#[derive(Clone)]
+struct CurveState {
+    balances: Vec<f64>,
+    admin_balances: Vec<f64>,
+    ramp: Ramp,
+    timestamp: u64,
+    version: u64,
+}
+
+impl CurveState {
+    // [a] is the amplification coefficient at this state's timestamp, interpolated linearly while a ramp is in progress.
+    fn a(&self) -> f64 {
+        let ramp = &self.ramp;
+        if self.timestamp >= ramp.future_time {
+            return ramp.future_a;
+        }
+        // a clock set back before the ramp started reads as its start
+        let elapsed = self.timestamp.saturating_sub(ramp.initial_time) as f64;
+        let duration = (ramp.future_time - ramp.initial_time) as f64;
+        ramp.initial_a + ((ramp.future_a - ramp.initial_a) * elapsed) / duration
+    }
+}
+
+/// Curve style StableSwap pool over n coins. Balances are raw base units;
+/// the invariant works on balances normalized to 18 decimals. `A` is the
+/// amplification coefficient of the whitepaper, so `Ann = A * n^n`.
+pub struct StableSwapPool {
+    tokens: Vec<TokenId>,
+    rates: Vec<f64>,
+    fee: f64,
+    admin_fee: f64,
+    state: RwLock<CurveState>,
+    events: Publisher,
+}
+
+impl Clone for StableSwapPool {
+    fn clone(&self) -> StableSwapPool {
+        StableSwapPool {
+            tokens: self.tokens.clone(),
+            rates: self.rates.clone(),
+            fee: self.fee,
+            admin_fee: self.admin_fee,
+            state: RwLock::new(self.state.read().unwrap().clone()),
+            events: Publisher::default(),
+        }
+    }
+}
+
+/// Solves the StableSwap invariant for D with Newton's method, given normalized balances `xp`.
+pub fn get_d(xp: &[f64], amp: f64) -> Result<f64, PoolError> {
+    let n = xp.len() as f64;
+    let s: f64 = xp.iter().sum();
+    if s == 0.0 {
+        return Ok(0.0);
+    }
+    let ann = amp * n.powi(xp.len() as i32);
+    let mut d = s;
+    for _ in 0..MAX_ITERATIONS {
+        let mut d_p = d;
+        for x in xp {
+            d_p = (d_p * d) / (x * n);
+        }
+        let d_prev = d;
+        d = ((ann * s + d_p * n) * d) / ((ann - 1.0) * d + (n + 1.0) * d_p);
+        if (d - d_prev).abs() <= d * 1e-15 {
+            return Ok(d);
+        }
+    }
+    Err(PoolError::NoConvergence)
+}
+
+/// Returns the normalized balance of coin `j` that keeps D unchanged once coin `i` holds `x`.
+pub fn get_y(i: usize, j: usize, x: f64, xp: &[f64], amp: f64) -> Result<f64, PoolError> {
+    let n = xp.len() as f64;
+    let d = get_d(xp, amp)?;
+    let ann = amp * n.powi(xp.len() as i32);
+
+    let mut c = d;
+    let mut s = 0.0;
+    for (k, balance) in xp.iter().enumerate() {
+        if k == j {
+            continue;
+        }
+        let x_k = if k == i { x } else { *balance };
+        s += x_k;
+        c = (c * d) / (x_k * n);
+    }
+    c = (c * d) / (ann * n);
+    let b = s + d / ann;
+
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+        let y_prev = y;
+        y = (y * y + c) / (2.0 * y + b - d);
+        if (y - y_prev).abs() <= y * 1e-15 {
+            return Ok(y);
+        }
+    }
+    Err(PoolError::NoConvergence)
+}
+
+impl StableSwapPool {
+    pub fn new(
+        tokens: &[&Token],
+        balances: &[f64],
+        amp: f64,
+        fee: f64,
+        admin_fee: f64
+    ) -> Result<StableSwapPool, PoolError> {
+        if tokens.len() < 2 || tokens.len() != balances.len() {
+            return Err(PoolError::InvalidParameter {
+                name: "balances",
+                value: balances.len() as f64,
+            });
+        }
+        if let Some(b) = balances.iter().find(|b| !b.is_finite() || **b < 0.0) {
+            return Err(PoolError::InvalidParameter { name: "balance", value: *b });
+        }
+        if !amp.is_finite() || amp <= 0.0 {
+            return Err(PoolError::InvalidParameter { name: "amp", value: amp });
+        }
+        if !(0.0..1.0).contains(&fee) {
+            return Err(PoolError::InvalidParameter { name: "fee", value: fee });
+        }
+        if !(0.0..=1.0).contains(&admin_fee) {
+            return Err(PoolError::InvalidParameter { name: "admin_fee", value: admin_fee });
+        }
+        Ok(StableSwapPool {
+            tokens: tokens
+                .iter()
+                .map(|t| t.id)
+                .collect(),
+            rates: tokens
+                .iter()
+                .map(|t| scale(PRECISION_DECIMALS) / scale(t.decimals))
+                .collect(),
+            fee,
+            admin_fee,
+            state: RwLock::new(CurveState {
+                balances: balances.to_vec(),
+                admin_balances: vec![0.0; balances.len()],
+                ramp: Ramp {
+                    initial_a: amp,
+                    future_a: amp,
+                    initial_time: 0,
+                    future_time: 0,
+                },
+                timestamp: 0,
+                version: 0,
+            }),
+            events: Publisher::default(),
+        })
+    }
+
+    pub fn with_events(mut self, events: Publisher) -> StableSwapPool {
+        self.events = events;
+        self
+    }
+
+    pub fn balances(&self) -> Vec<f64> {
+        self.state.read().unwrap().balances.clone()
+    }
+
+    pub fn admin_balances(&self) -> Vec<f64> {
+        self.state.read().unwrap().admin_balances.clone()
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.state.read().unwrap().timestamp
+    }
+
+    /// Moves the pool clock, which drives any A ramp in progress.
+    pub fn set_timestamp(&self, timestamp: u64) {
+        let mut state = self.state.write().unwrap();
+        state.timestamp = timestamp;
+        state.version += 1;
+        self.events.emit(EventKind::Sync, state.version);
+    }
+
+    /// The amplification coefficient at the pool's current timestamp, interpolated
+    /// linearly while a ramp is in progress.
+    pub fn a(&self) -> f64 {
+        self.state.read().unwrap().a()
+    }
+
+    /// Starts moving A from its current value to `future_a`, reaching it at `future_time`.
+    pub fn ramp_a(&self, future_a: f64, future_time: u64) -> Result<(), PoolError> {
+        let mut state = self.state.write().unwrap();
+        let initial_a = state.a();
+        let now = state.timestamp;
+        if !future_a.is_finite() || future_a <= 0.0 || future_a > initial_a * MAX_A_CHANGE || future_a < initial_a / MAX_A_CHANGE {
+            return Err(PoolError::InvalidParameter { name: "future_a", value: future_a });
+        }
+        if future_time <= now {
+            return Err(PoolError::InvalidParameter {
+                name: "future_time",
+                value: future_time as f64,
+            });
+        }
+        state.ramp = Ramp {
+            initial_a,
+            future_a,
+            initial_time: now,
+            future_time,
+        };
+        state.version += 1;
+        self.events.emit(EventKind::Sync, state.version);
+        Ok(())
+    }
+
+    /// Freezes A at its current value.
+    pub fn stop_ramp_a(&self) {
+        let mut state = self.state.write().unwrap();
+        let current_a = state.a();
+        let now = state.timestamp;
+        state.ramp = Ramp {
+            initial_a: current_a,
+            future_a: current_a,
+            initial_time: now,
+            future_time: now,
+        };
+        state.version += 1;
+        self.events.emit(EventKind::Sync, state.version);
+    }
+
+    fn index_of(&self, token: TokenId) -> Result<usize, PoolError> {
+        self.tokens
+            .iter()
+            .position(|t| *t == token)
+            .ok_or(PoolError::UnknownToken { token })
+    }
+
+    fn xp(&self, state: &CurveState) -> Result<Vec<f64>, PoolError> {
+        if state.balances.iter().any(|b| *b <= 0.0) {
+            return Err(PoolError::NotInitialized);
+        }
+        Ok(
+            state.balances
+                .iter()
+                .zip(&self.rates)
+                .map(|(b, r)| b * r)
+                .collect()
+        )
+    }
+
+    // [get_dy] returns the gross amount of coin [j] paid for [dx] of coin [i] in [state], before the fee, in raw units of [j].
+    fn get_dy(&self, state: &CurveState, i: usize, j: usize, dx: f64) -> Result<f64, PoolError> {
+        let xp = self.xp(state)?;
+        let x = xp[i] + dx * self.rates[i];
+        let y = get_y(i, j, x, &xp, state.a())?;
+        Ok((xp[j] - y) / self.rates[j])
+    }
+
+    /// The invariant D for the current balances, which only grows as fees accrue.
+    pub fn invariant(&self) -> Result<f64, PoolError> {
+        let state = self.state.read().unwrap();
+        get_d(&self.xp(&state)?, state.a())
+    }
+
+    /// Pays the accrued admin share of fees out to `to`.
+    pub fn withdraw_admin_fees(&self, to: &Account) -> Result<(), PoolError> {
+        let mut state = self.state.write().unwrap();
+        for (token, amount) in self.tokens.iter().zip(state.admin_balances.iter_mut()) {
+            if *amount > 0.0 {
+                to.credit(*token, *amount, EntryReason::Fee)?;
+            }
+            *amount = 0.0;
+        }
+        state.version += 1;
+        self.events.emit(EventKind::Burn, state.version);
+        Ok(())
+    }
+}
+
+impl Holdings for StableSwapPool {
+    fn holdings(&self) -> Vec<(TokenId, f64)> {
+        let state = self.state.read().unwrap();
+        self.tokens
+            .iter()
+            .enumerate()
+            .map(|(k, t)| (*t, state.balances[k] + state.admin_balances[k]))
+            .collect()
+    }
+}
+
+/// Exchanges `amount_in` of `token_in` for `token_out` and returns the amount paid
+/// out after fees. The admin share of the fee is set aside in the pool's admin
+/// balances.
+pub fn stable_swap(
+    trader: &mut Account,
+    pool: &StableSwapPool,
+    token_in: TokenId,
+    token_out: TokenId,
+    amount_in: f64,
+    min_amount_out: f64
+) -> Result<f64, PoolError> {
+    check_pair(pool, token_in, token_out)?;
+    let (i, j) = (pool.index_of(token_in)?, pool.index_of(token_out)?);
+    if !amount_in.is_finite() || amount_in <= 0.0 {
+        return Err(PoolError::InvalidParameter { name: "amount_in", value: amount_in });
+    }
+    trader.ensure(token_in, amount_in)?;
+
+    let mut state = pool.state.write().unwrap();
+    let dy = pool.get_dy(&state, i, j, amount_in)?;
+    let fee_amount = dy * pool.fee;
+    let amount_out = dy - fee_amount;
+    if amount_out < min_amount_out {
+        return Err(PoolError::SlippageExceeded { min_out: min_amount_out, amount_out });
+    }
+    if dy >= state.balances[j] {
+        return Err(PoolError::InsufficientLiquidity);
+    }
+
+    let admin_amount = fee_amount * pool.admin_fee;
+    state.balances[i] += amount_in;
+    state.balances[j] -= amount_out + admin_amount;
+    state.admin_balances[j] += admin_amount;
+    state.version += 1;
+    pool.events.emit(EventKind::Swap, state.version);
+    drop(state);
+
+    trader.debit(token_in, amount_in, EntryReason::Swap)?;
+    trader.credit(token_out, dy, EntryReason::Swap)?;
+    trader.debit(token_out, fee_amount, EntryReason::Fee)?;
+    Ok(amount_out)
+}
+
+impl Amm for StableSwapPool {
+    fn tokens(&self) -> Vec<TokenId> {
+        self.tokens.clone()
+    }
+
+    fn fee(&self) -> f64 {
+        self.fee
+    }
+
+    fn spot_price(&self, token_in: TokenId, token_out: TokenId) -> Result<f64, PoolError> {
+        check_pair(self, token_in, token_out)?;
+        let (i, j) = (self.index_of(token_in)?, self.index_of(token_out)?);
+        let state = self.state.read().unwrap();
+        let xp = self.xp(&state)?;
+        let n = xp.len() as f64;
+        let amp = state.a();
+        let ann = amp * n.powi(xp.len() as i32);
+        let d = get_d(&xp, amp)?;
+
+        // the ratio of the invariant's partial derivatives, D^(n+1) / (n^n prod(x)) being shared by both
+        let mut p = d;
+        for x in &xp {
+            p = (p * d) / (x * n);
+        }
+        let price = (ann + p / xp[i]) / (ann + p / xp[j]);
+        Ok((price * self.rates[i]) / self.rates[j])
+    }
+
+    fn quote_exact_in(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64
+    ) -> Result<f64, PoolError> {
+        check_pair(self, token_in, token_out)?;
+        if !amount_in.is_finite() || amount_in <= 0.0 {
+            return Err(PoolError::InvalidParameter { name: "amount_in", value: amount_in });
+        }
+        let state = self.state.read().unwrap();
+        let dy = self.get_dy(&state, self.index_of(token_in)?, self.index_of(token_out)?, amount_in)?;
+        Ok(dy - dy * self.fee)
+    }
+
+    fn quote_exact_out(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_out: f64
+    ) -> Result<f64, PoolError> {
+        check_pair(self, token_in, token_out)?;
+        if !amount_out.is_finite() || amount_out <= 0.0 {
+            return Err(PoolError::InvalidParameter { name: "amount_out", value: amount_out });
+        }
+        let (i, j) = (self.index_of(token_in)?, self.index_of(token_out)?);
+        let state = self.state.read().unwrap();
+        let xp = self.xp(&state)?;
+        let dy = (amount_out / (1.0 - self.fee)) * self.rates[j];
+        if dy >= xp[j] {
+            return Err(PoolError::InsufficientLiquidity);
+        }
+        let x = get_y(j, i, xp[j] - dy, &xp, state.a())?;
+        Ok((x - xp[i]) / self.rates[i])
+    }
+
+    fn apply_swap(
+        &self,
+        trader: &mut Account,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64,
+        min_amount_out: f64
+    ) -> Result<f64, PoolError> {
+        stable_swap(trader, self, token_in, token_out, amount_in, min_amount_out)
+    }
+
+    fn version(&self) -> u64 {
+        self.state.read().unwrap().version
+    }
+
+    fn snapshot(&self) -> Box<dyn Amm + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::arb::find_two_pool_arb;
+    use crate::ledger::{check_conservation, totals};
+    use crate::token::TokenRegistry;
+    use crate::v2::Pool;
+
+    fn set_up_pool(amp: f64) -> (Token, Token, StableSwapPool) {
+        let mut registry = TokenRegistry::new();
+        let dai = registry.register("DAI", 18, "0x6B175474E89094C44Da98b954EedeAC495271d0F");
+        let usdc = registry.register("USDC", 6, "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+        let usdt = registry.register("USDT", 6, "0xdAC17F958D2ee523a2206206994597C13D831ec7");
+        let (dai, usdc, usdt) = (
+            registry.get(dai).unwrap(),
+            registry.get(usdc).unwrap(),
+            registry.get(usdt).unwrap(),
+        );
+        let pool = StableSwapPool::new(
+            &[dai, usdc, usdt],
+            &[
+                dai.amount(1000000.0).raw_f64(),
+                usdc.amount(1000000.0).raw_f64(),
+                usdt.amount(1000000.0).raw_f64(),
+            ],
+            amp,
+            0.0004,
+            0.5
+        ).unwrap();
+        (dai.clone(), usdc.clone(), pool)
+    }
+
+    #[test]
+    fn balanced_pool_invariant() {
+        // with equal balances the invariant is simply their sum
+        assert_eq!(get_d(&[1000.0, 1000.0, 1000.0], 100.0).unwrap(), 3000.0);
+        let d = get_d(&[500.0, 1500.0], 100.0).unwrap();
+        assert!(d < 2000.0 && d > 1998.0);
+        let y = get_y(0, 1, 500.0, &[500.0, 1500.0], 100.0).unwrap();
+        assert!((y - 1500.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn swaps_across_decimals() {
+        let (dai, usdc, pool) = set_up_pool(100.0);
+
+        assert!((pool.spot_price(dai.id, usdc.id).unwrap() * 1e12 - 1.0).abs() < 1e-12);
+
+        let out = pool.quote_exact_in(dai.id, usdc.id, dai.amount(1000.0).raw_f64()).unwrap();
+        let human_out = Amount::from_raw_f64(out, usdc.decimals).value;
+        assert!(human_out < 1000.0 * (1.0 - 0.0004));
+        assert!(human_out > 999.5);
+
+        let back = pool.quote_exact_out(dai.id, usdc.id, out).unwrap();
+        assert!((back - dai.amount(1000.0).raw_f64()).abs() / back < 1e-9);
+
+        let mut trader = Account::new(1, &[(dai.id, dai.amount(1000.0).raw_f64())]);
+        let supply = totals(&[&trader, &pool]);
+        let invariant = pool.invariant().unwrap();
+        assert_eq!(pool.apply_swap(&mut trader, dai.id, usdc.id, dai.amount(1000.0).raw_f64(), out), Ok(out));
+        check_conservation(&supply, &[&trader, &pool]).unwrap();
+        assert!(pool.invariant().unwrap() > invariant);
+
+        let admin = pool.admin_balances()[1];
+        assert!((admin - (out / (1.0 - 0.0004)) * 0.0004 * 0.5).abs() < 1e-6);
+        let treasury = Account::new(9, &[]);
+        pool.withdraw_admin_fees(&treasury).unwrap();
+        assert_eq!(treasury.balance(usdc.id), admin);
+        check_conservation(&supply, &[&trader, &pool, &treasury]).unwrap();
+    }
+
+    #[test]
+    fn ramping_a() {
+        let (_, _, pool) = set_up_pool(100.0);
+        assert_eq!(pool.a(), 100.0);
+
+        pool.ramp_a(200.0, 100).unwrap();
+        pool.set_timestamp(50);
+        assert_eq!(pool.a(), 150.0);
+        pool.set_timestamp(500);
+        assert_eq!(pool.a(), 200.0);
+
+        assert_eq!(
+            pool.ramp_a(5000.0, 1000),
+            Err(PoolError::InvalidParameter { name: "future_a", value: 5000.0 })
+        );
+        pool.ramp_a(100.0, 600).unwrap();
+        pool.set_timestamp(550);
+        pool.stop_ramp_a();
+        pool.set_timestamp(600);
+        assert_eq!(pool.a(), 150.0);
+
+        // moving the clock back before a ramp started holds A at the ramp's start
+        pool.ramp_a(300.0, 700).unwrap();
+        pool.set_timestamp(100);
+        assert_eq!(pool.a(), 150.0);
+    }
+
+    #[test]
+    fn bad_parameters_are_rejected() {
+        let (dai, usdc, pool) = set_up_pool(100.0);
+        let new = |balances: &[f64], amp, fee| StableSwapPool::new(&[&dai, &usdc], balances, amp, fee, 0.5).err();
+
+        assert_eq!(new(&[1.0], 100.0, 0.0004), Some(PoolError::InvalidParameter { name: "balances", value: 1.0 }));
+        assert_eq!(new(&[1.0, -1.0], 100.0, 0.0004), Some(PoolError::InvalidParameter { name: "balance", value: -1.0 }));
+        assert_eq!(new(&[1.0, 1.0], 0.0, 0.0004), Some(PoolError::InvalidParameter { name: "amp", value: 0.0 }));
+        assert_eq!(new(&[1.0, 1.0], 100.0, 1.5), Some(PoolError::InvalidParameter { name: "fee", value: 1.5 }));
+        assert!(new(&[1.0, 1.0], f64::NAN, 0.0004).is_some());
+
+        assert!(matches!(pool.ramp_a(f64::NAN, 100), Err(PoolError::InvalidParameter { name: "future_a", .. })));
+        assert_eq!(pool.a(), 100.0);
+
+        let mut trader = Account::new(1, &[(dai.id, dai.amount(1000.0).raw_f64())]);
+        for amount_in in [0.0, -1.0, f64::NAN] {
+            assert!(matches!(
+                pool.quote_exact_in(dai.id, usdc.id, amount_in),
+                Err(PoolError::InvalidParameter { name: "amount_in", .. })
+            ));
+            assert!(matches!(
+                stable_swap(&mut trader, &pool, dai.id, usdc.id, amount_in, 0.0),
+                Err(PoolError::InvalidParameter { name: "amount_in", .. })
+            ));
+        }
+        assert_eq!(trader.balance(dai.id), dai.amount(1000.0).raw_f64());
+    }
+
+    #[test]
+    fn arb_against_constant_product() {
+        let (dai, usdc, stable) = set_up_pool(100.0);
+        // DAI trades at 1.02 USDC in the constant product pool
+        let cp = Pool::new(
+            dai.id,
+            usdc.id,
+            dai.amount(100000.0).raw_f64(),
+            usdc.amount(102000.0).raw_f64(),
+            0.003
+        );
+
+        let best = find_two_pool_arb(&cp, &stable, dai.id, usdc.id, dai.amount(100000.0).raw_f64())
+            .unwrap()
+            .unwrap();
+        assert!(best.profit > 0.0);
+        assert!(best.amount_in < dai.amount(1000.0).raw_f64());
+    }
+}
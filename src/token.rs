@@ -0,0 +1,121 @@
+use crate::amount::{Amount, RawAmount};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Index of a token inside a [`TokenRegistry`]. Pools and traders refer to
+/// tokens only through this id.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Copy, Clone, Serialize, Deserialize)]
+pub struct TokenId(pub usize);
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Token {
+    pub id: TokenId,
+    pub symbol: String,
+    pub decimals: u8,
+    pub address: String,
+}
+
+impl Token {
+    pub fn amount(&self, value: f64) -> Amount {
+        Amount::new(value, self.decimals)
+    }
+
+    pub fn raw(&self, value: u128) -> RawAmount {
+        RawAmount::new(value, self.decimals)
+    }
+}
+
+/// Open-ended set of known tokens, looked up by id, symbol or address.
+#[derive(Debug, Default, Clone)]
+pub struct TokenRegistry {
+    tokens: Vec<Token>,
+    by_symbol: HashMap<String, TokenId>,
+    by_address: HashMap<String, TokenId>,
+}
+
+impl TokenRegistry {
+    pub fn new() -> TokenRegistry {
+        TokenRegistry::default()
+    }
+
+    /// Adds a token and returns its id. Registering an address that is already
+    /// known returns the existing id.
+    pub fn register(&mut self, symbol: &str, decimals: u8, address: &str) -> TokenId {
+        let address = address.to_lowercase();
+        if let Some(id) = self.by_address.get(&address) {
+            return *id;
+        }
+
+        let id = TokenId(self.tokens.len());
+        self.tokens.push(Token {
+            id,
+            symbol: symbol.to_string(),
+            decimals,
+            address: address.clone(),
+        });
+        self.by_symbol.insert(symbol.to_string(), id);
+        self.by_address.insert(address, id);
+        id
+    }
+
+    pub fn get(&self, id: TokenId) -> Option<&Token> {
+        self.tokens.get(id.0)
+    }
+
+    pub fn by_symbol(&self, symbol: &str) -> Option<TokenId> {
+        self.by_symbol.get(symbol).copied()
+    }
+
+    pub fn by_address(&self, address: &str) -> Option<TokenId> {
+        self.by_address.get(&address.to_lowercase()).copied()
+    }
+
+    pub fn symbol(&self, id: TokenId) -> &str {
+        self.get(id).map_or("?", |t| t.symbol.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Token> {
+        self.tokens.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_and_lookup() {
+        let mut registry = TokenRegistry::new();
+        let weth = registry.register("WETH", 18, "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let usdc = registry.register("USDC", 6, "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+
+        assert_eq!(weth, TokenId(0));
+        assert_eq!(usdc, TokenId(1));
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.get(usdc).unwrap().decimals, 6);
+        assert_eq!(registry.by_symbol("WETH"), Some(weth));
+        assert_eq!(
+            registry.by_address("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"),
+            Some(weth)
+        );
+        assert_eq!(registry.symbol(usdc), "USDC");
+    }
+
+    #[test]
+    fn register_same_address_twice() {
+        let mut registry = TokenRegistry::new();
+        let a = registry.register("DAI", 18, "0x6B175474E89094C44Da98b954EedeAC495271d0F");
+        let b = registry.register("DAI", 18, "0x6b175474e89094c44da98b954eedeac495271d0f");
+
+        assert_eq!(a, b);
+        assert_eq!(registry.len(), 1);
+    }
+}
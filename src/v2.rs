@@ -0,0 +1,448 @@
+use crate::amm::{check_pair, Amm};
+use crate::error::PoolError;
+use crate::events::{EventKind, Publisher};
+use crate::ledger::{Account, EntryReason, Holdings};
+use crate::token::TokenId;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+/// Reserves of a [`Pool`] at one version. Every change to the pool replaces
+/// the whole state under a single lock and bumps `version`, so readers never
+/// see `x` from one update and `y` from another.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct PoolState {
+    pub x: f64,
+    pub y: f64,
+    pub k: f64,
+    pub version: u64,
+}
+
+impl PoolState {
+    // [reserves_for] returns (reserve_in, reserve_out) when swapping [token_in] into [pool].
+    fn reserves_for(&self, pool: &Pool, token_in: TokenId) -> Result<(f64, f64), PoolError> {
+        if self.x <= 0.0 || self.y <= 0.0 {
+            return Err(PoolError::NotInitialized);
+        }
+        if token_in == pool.token_x {
+            Ok((self.x, self.y))
+        } else if token_in == pool.token_y {
+            Ok((self.y, self.x))
+        } else {
+            Err(PoolError::UnknownToken { token: token_in })
+        }
+    }
+
+    fn amount_out(&self, pool: &Pool, amount_in: f64, token_in: TokenId) -> Result<f64, PoolError> {
+        let (reserve_in, reserve_out) = self.reserves_for(pool, token_in)?;
+        let amount_in_less_fee = amount_in * (1.0 - pool.fee);
+        Ok((amount_in_less_fee * reserve_out) / (reserve_in + amount_in_less_fee))
+    }
+
+    // [with_reserves] is the next version of the state holding [x] and [y].
+    fn with_reserves(&self, x: f64, y: f64) -> PoolState {
+        PoolState { x, y, k: x * y, version: self.version + 1 }
+    }
+}
+
+/// Everything needed to rebuild a [`Pool`], in a form serde can save.
+/// `k` is not kept since it follows from the reserves.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct V2Snapshot {
+    pub token_x: TokenId,
+    pub token_y: TokenId,
+    pub fee: f64,
+    pub x: f64,
+    pub y: f64,
+    pub version: u64,
+}
+
+pub struct Pool {
+    token_x: TokenId,
+    token_y: TokenId,
+    fee: f64,
+    state: RwLock<PoolState>,
+    events: Publisher,
+}
+
+impl Pool {
+    pub fn new(token_x: TokenId, token_y: TokenId, x: f64, y: f64, fee: f64) -> Pool {
+        Pool {
+            token_x,
+            token_y,
+            fee,
+            state: RwLock::new(PoolState { x, y, k: x * y, version: 0 }),
+            events: Publisher::default(),
+        }
+    }
+
+    pub fn with_events(mut self, events: Publisher) -> Pool {
+        self.events = events;
+        self
+    }
+
+    pub fn token_x(&self) -> TokenId {
+        self.token_x
+    }
+
+    pub fn token_y(&self) -> TokenId {
+        self.token_y
+    }
+
+    /// A coherent copy of the reserves and their version.
+    pub fn state(&self) -> PoolState {
+        *self.state.read().unwrap()
+    }
+
+    pub fn reserves(&self) -> (f64, f64) {
+        let state = self.state();
+        (state.x, state.y)
+    }
+
+    pub fn to_snapshot(&self) -> V2Snapshot {
+        let state = self.state();
+        V2Snapshot {
+            token_x: self.token_x,
+            token_y: self.token_y,
+            fee: self.fee,
+            x: state.x,
+            y: state.y,
+            version: state.version,
+        }
+    }
+
+    /// Rebuilds a pool saved by `to_snapshot`, at the same version and without subscribers.
+    pub fn from_snapshot(snapshot: &V2Snapshot) -> Result<Pool, PoolError> {
+        if snapshot.token_x == snapshot.token_y {
+            return Err(PoolError::UnknownToken { token: snapshot.token_y });
+        }
+        if !(0.0..1.0).contains(&snapshot.fee) {
+            return Err(PoolError::InvalidParameter { name: "fee", value: snapshot.fee });
+        }
+        for (name, reserve) in [("x", snapshot.x), ("y", snapshot.y)] {
+            if !reserve.is_finite() || reserve < 0.0 {
+                return Err(PoolError::InvalidParameter { name, value: reserve });
+            }
+        }
+        let pool = Pool::new(snapshot.token_x, snapshot.token_y, snapshot.x, snapshot.y, snapshot.fee);
+        pool.state.write().unwrap().version = snapshot.version;
+        Ok(pool)
+    }
+}
+
+impl Clone for Pool {
+    fn clone(&self) -> Pool {
+        Pool {
+            token_x: self.token_x,
+            token_y: self.token_y,
+            fee: self.fee,
+            state: RwLock::new(self.state()),
+            events: Publisher::default(),
+        }
+    }
+}
+
+impl Holdings for Pool {
+    fn holdings(&self) -> Vec<(TokenId, f64)> {
+        let state = self.state();
+        vec![(self.token_x, state.x), (self.token_y, state.y)]
+    }
+}
+
+pub fn add(pool: &Pool, add_to_x: f64, add_to_y: f64) -> Result<(), PoolError> {
+    check_change(add_to_x, add_to_y)?;
+    let mut state = pool.state.write().unwrap();
+    *state = state.with_reserves(state.x + add_to_x, state.y + add_to_y);
+    pool.events.emit(EventKind::Mint, state.version);
+    Ok(())
+}
+
+pub fn remove(pool: &Pool, rem_from_x: f64, rem_from_y: f64) -> Result<(), PoolError> {
+    check_change(rem_from_x, rem_from_y)?;
+    let mut state = pool.state.write().unwrap();
+    if rem_from_x > state.x || rem_from_y > state.y {
+        return Err(PoolError::InsufficientLiquidity);
+    }
+    *state = state.with_reserves(state.x - rem_from_x, state.y - rem_from_y);
+    pool.events.emit(EventKind::Burn, state.version);
+    Ok(())
+}
+
+// [check_change] verifies that neither side of a liquidity change is negative or non-finite.
+fn check_change(x: f64, y: f64) -> Result<(), PoolError> {
+    for (name, amount) in [("x", x), ("y", y)] {
+        if !amount.is_finite() || amount < 0.0 {
+            return Err(PoolError::InvalidParameter { name, value: amount });
+        }
+    }
+    Ok(())
+}
+
+/// Overwrites the reserves with `x` and `y`, as a pair does when its token balances
+/// change outside a swap.
+pub fn sync(pool: &Pool, x: f64, y: f64) {
+    let mut state = pool.state.write().unwrap();
+    *state = state.with_reserves(x, y);
+    pool.events.emit(EventKind::Sync, state.version);
+}
+
+pub fn get_amount_out(amount_in: f64, pool: &Pool, token_in: TokenId) -> Result<f64, PoolError> {
+    pool.state().amount_out(pool, amount_in, token_in)
+}
+
+pub fn get_amount_in(amount_out: f64, pool: &Pool, token_in: TokenId) -> Result<f64, PoolError> {
+    let (reserve_in, reserve_out) = pool.state().reserves_for(pool, token_in)?;
+    if amount_out >= reserve_out {
+        return Err(PoolError::InsufficientLiquidity);
+    }
+    Ok((reserve_in * amount_out) / ((reserve_out - amount_out) * (1.0 - pool.fee)))
+}
+
+/// Prices and applies the trade under one write lock, so no other update can land in between.
+pub fn swap(
+    trader: &mut Account,
+    pool: &Pool,
+    token_in: TokenId,
+    amount_in: f64,
+    min_amount_out: f64
+) -> Result<f64, PoolError> {
+    if !amount_in.is_finite() || amount_in <= 0.0 {
+        return Err(PoolError::InvalidParameter { name: "amount_in", value: amount_in });
+    }
+    let token_out = if token_in == pool.token_x { pool.token_y } else { pool.token_x };
+    trader.ensure(token_in, amount_in)?;
+
+    let mut state = pool.state.write().unwrap();
+    let amt_out = state.amount_out(pool, amount_in, token_in)?;
+    if amt_out < min_amount_out {
+        return Err(PoolError::SlippageExceeded { min_out: min_amount_out, amount_out: amt_out });
+    }
+    *state = if token_in == pool.token_x {
+        state.with_reserves(state.x + amount_in, state.y - amt_out)
+    } else {
+        state.with_reserves(state.x - amt_out, state.y + amount_in)
+    };
+    pool.events.emit(EventKind::Swap, state.version);
+    drop(state);
+
+    // the fee is taken from the input and stays in the pool
+    trader.debit_with_fee(token_in, amount_in, pool.fee)?;
+    trader.credit(token_out, amt_out, EntryReason::Swap)?;
+    Ok(amt_out)
+}
+
+impl Amm for Pool {
+    fn tokens(&self) -> Vec<TokenId> {
+        vec![self.token_x, self.token_y]
+    }
+
+    fn fee(&self) -> f64 {
+        self.fee
+    }
+
+    fn spot_price(&self, token_in: TokenId, token_out: TokenId) -> Result<f64, PoolError> {
+        check_pair(self, token_in, token_out)?;
+        let (reserve_in, reserve_out) = self.state().reserves_for(self, token_in)?;
+        Ok(reserve_out / reserve_in)
+    }
+
+    fn quote_exact_in(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64
+    ) -> Result<f64, PoolError> {
+        check_pair(self, token_in, token_out)?;
+        get_amount_out(amount_in, self, token_in)
+    }
+
+    fn quote_exact_out(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_out: f64
+    ) -> Result<f64, PoolError> {
+        check_pair(self, token_in, token_out)?;
+        get_amount_in(amount_out, self, token_in)
+    }
+
+    fn apply_swap(
+        &self,
+        trader: &mut Account,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64,
+        min_amount_out: f64
+    ) -> Result<f64, PoolError> {
+        check_pair(self, token_in, token_out)?;
+        swap(trader, self, token_in, amount_in, min_amount_out)
+    }
+
+    fn version(&self) -> u64 {
+        self.state().version
+    }
+
+    fn snapshot(&self) -> Box<dyn Amm + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::{check_conservation, totals};
+    use crate::token::TokenRegistry;
+    use std::sync::Arc;
+
+    fn set_up_tokens() -> (TokenRegistry, TokenId, TokenId) {
+        let mut registry = TokenRegistry::new();
+        let eth = registry.register("WETH", 18, "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let dai = registry.register("DAI", 18, "0x6B175474E89094C44Da98b954EedeAC495271d0F");
+        (registry, eth, dai)
+    }
+
+    #[test]
+    fn initialize() {
+        let (_, eth, dai) = set_up_tokens();
+        let xx = 1000.0;
+        let yy = 200.0;
+        let pool = Pool {
+            token_x: eth,
+            token_y: dai,
+            fee: 0.03,
+            state: RwLock::new(PoolState { x: xx, y: yy, k: xx * yy, version: 0 }),
+            events: Publisher::default(),
+        };
+        let trader = Account::new(1, &[(eth, xx), (dai, yy)]);
+
+        assert_eq!(trader.balance(eth), 1000.0);
+        assert_eq!(trader.balance(dai), 200.0);
+
+        assert_eq!(pool.state().x, 1000.0);
+        assert_eq!(pool.state().y, 200.0);
+    }
+
+    #[test]
+    fn add_and_remove() {
+        let (_, eth, dai) = set_up_tokens();
+        let xx = 1000.0;
+        let yy = 200.0;
+        let pool = Arc::new(Pool {
+            token_x: eth,
+            token_y: dai,
+            fee: 0.03,
+            state: RwLock::new(PoolState { x: xx, y: yy, k: xx * yy, version: 0 }),
+            events: Publisher::default(),
+        });
+
+        let safepool = Arc::clone(&pool);
+
+        add(&safepool, 4.0, 4.0).unwrap();
+        assert_eq!(Arc::clone(&pool).state().x, 1004.0);
+        assert_eq!(Arc::clone(&pool).state().y, 204.0);
+        remove(&safepool, 4.0, 4.0).unwrap();
+        assert_eq!(pool.state(), PoolState { x: 1000.0, y: 200.0, k: 200000.0, version: 2 });
+    }
+
+    #[test]
+    fn test_swap() {
+        let (_, eth, dai) = set_up_tokens();
+        let xx = 1000.0;
+        let yy = 200.0;
+        let pool = Pool {
+            token_x: eth,
+            token_y: dai,
+            fee: 0.03,
+            state: RwLock::new(PoolState { x: xx, y: yy, k: xx * yy, version: 0 }),
+            events: Publisher::default(),
+        };
+        let mut trader = Account::new(1, &[(eth, xx), (dai, yy)]);
+        swap(&mut trader, &pool, eth, 1.0, 0.0).unwrap();
+
+        assert_eq!(trader.balance(eth), 999.0);
+        assert_eq!(trader.balance(dai), 200.1938120023577);
+    }
+
+    #[test]
+    fn quotes_match_constant_product() {
+        let (_, eth, dai) = set_up_tokens();
+        let pool = Pool::new(eth, dai, 1000.0, 200.0, 0.03);
+
+        let out = pool.quote_exact_in(eth, dai, 10.0).unwrap();
+        assert_eq!(out, 9.7 * 200.0 / 1009.7);
+        let back = pool.quote_exact_out(eth, dai, out).unwrap();
+        assert!((back - 10.0).abs() < 1e-9);
+        assert_eq!(pool.spot_price(dai, eth).unwrap(), 5.0);
+        assert_eq!(
+            pool.quote_exact_in(eth, TokenId(9), 1.0),
+            Err(PoolError::UnknownToken { token: TokenId(9) })
+        );
+
+        let mut trader = Account::new(1, &[(eth, 10.0)]);
+        let snapshot = pool.snapshot();
+        assert_eq!(pool.apply_swap(&mut trader, eth, dai, 10.0, out).unwrap(), out);
+        assert_eq!(snapshot.spot_price(eth, dai).unwrap(), 0.2);
+        let (x, y) = pool.reserves();
+        assert!(x * y > 1000.0 * 200.0);
+    }
+
+    #[test]
+    fn swap_conserves_tokens() {
+        let (_, eth, dai) = set_up_tokens();
+        let pool = Pool {
+            token_x: eth,
+            token_y: dai,
+            fee: 0.03,
+            state: RwLock::new(PoolState { x: 1000.0, y: 200.0, k: 200000.0, version: 0 }),
+            events: Publisher::default(),
+        };
+        let mut trader = Account::new(1, &[(eth, 10.0), (dai, 10.0)]);
+        let supply = totals(&[&trader, &pool]);
+
+        swap(&mut trader, &pool, eth, 1.0, 0.0).unwrap();
+        check_conservation(&supply, &[&trader, &pool]).unwrap();
+        swap(&mut trader, &pool, dai, 2.0, 0.0).unwrap();
+        check_conservation(&supply, &[&trader, &pool]).unwrap();
+
+        let fees: Vec<_> = trader
+            .history()
+            .into_iter()
+            .filter(|e| e.reason == EntryReason::Fee)
+            .collect();
+        assert_eq!(fees.len(), 2);
+        assert_eq!(fees[1].token, dai);
+    }
+
+    #[test]
+    fn swap_errors() {
+        let (_, eth, dai) = set_up_tokens();
+        let pool = Pool {
+            token_x: eth,
+            token_y: dai,
+            fee: 0.03,
+            state: RwLock::new(PoolState { x: 1000.0, y: 200.0, k: 200000.0, version: 0 }),
+            events: Publisher::default(),
+        };
+        let mut trader = Account::new(1, &[(eth, 10.0), (dai, 10000.0)]);
+
+        assert!(matches!(
+            swap(&mut trader, &pool, eth, 20.0, 0.0),
+            Err(PoolError::InsufficientBalance { .. })
+        ));
+        assert!(matches!(
+            swap(&mut trader, &pool, eth, 1.0, 1.0),
+            Err(PoolError::SlippageExceeded { .. })
+        ));
+        assert_eq!(get_amount_in(200.0, &pool, eth), Err(PoolError::InsufficientLiquidity));
+        assert_eq!(remove(&pool, 2000.0, 0.0), Err(PoolError::InsufficientLiquidity));
+        for amount in [0.0, -5.0, f64::NAN] {
+            assert!(matches!(
+                swap(&mut trader, &pool, eth, amount, -1000.0),
+                Err(PoolError::InvalidParameter { name: "amount_in", .. })
+            ));
+        }
+        assert!(matches!(add(&pool, f64::NAN, 1.0), Err(PoolError::InvalidParameter { name: "x", .. })));
+        assert_eq!(remove(&pool, 1.0, -1.0), Err(PoolError::InvalidParameter { name: "y", value: -1.0 }));
+        assert_eq!(pool.state().x, 1000.0);
+        assert!(trader.history().is_empty());
+    }
+}
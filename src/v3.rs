@@ -0,0 +1,1115 @@
+use crate::amm::{check_pair, Amm};
+use crate::error::PoolError;
+use crate::events::{EventKind, Publisher};
+use crate::ledger::{Account, EntryReason, Holdings};
+use crate::math::{
+    calc_amount0,
+    calc_amount1,
+    compute_swap_step,
+    compute_swap_step_exact_out,
+    get_q96,
+    get_max_tick,
+    get_min_tick,
+    sqrtp_to_price,
+    sqrtp_to_tick,
+    tick_to_sqrtp,
+};
+use crate::token::{Token, TokenId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+// relative slack allowed when a snapshot's positions are summed against its ticks
+const SNAPSHOT_TOLERANCE: f64 = 1e-9;
+
+#[derive(Clone)]
+struct Tick {
+    liquidity_gross: f64,
+    liquidity_net: f64,
+    initialized: bool,
+}
+
+/// An initialized tick and the liquidity that starts or stops there.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TickLiquidity {
+    pub tick: i32,
+    pub liquidity_net: f64,
+    pub liquidity_gross: f64,
+}
+
+#[derive(Clone)]
+struct Position {
+    liquidity: f64,
+}
+
+/// Everything a mint or swap changes, kept behind a single lock so that
+/// readers always see one coherent version of the pool.
+#[derive(Clone)]
+struct PoolState {
+    balance_0: f64,
+    balance_1: f64,
+    tick_mapping: HashMap<i32, Tick>,
+    // initialized ticks mapped to their net liquidity
+    liquidity_mapping: HashMap<i32, f64>,
+    // positions keyed by (owner, lower tick, upper tick)
+    position_mapping: HashMap<(i32, i32, i32), Position>,
+    sqrt_price_x96: f64,
+    tick: i32,
+    liquidity: f64,
+    version: u64,
+}
+
+/// The liquidity an account holds in one range of the pool.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PositionLiquidity {
+    pub owner: i32,
+    pub lower_tick: i32,
+    pub upper_tick: i32,
+    pub liquidity: f64,
+}
+
+/// Everything needed to rebuild a [`UniswapV3Pool`], in a form serde can
+/// save. Ticks and positions are sorted so that equal pools save to equal
+/// bytes. The pool keeps swap fees in its balances rather than in fee
+/// growth accumulators, so the balances are the whole fee state.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct V3Snapshot {
+    pub token_0: TokenId,
+    pub token_1: TokenId,
+    pub decimals_0: u8,
+    pub decimals_1: u8,
+    pub fee: f64,
+    pub min_tick: i32,
+    pub max_tick: i32,
+    pub sqrt_price_x96: f64,
+    pub tick: i32,
+    pub liquidity: f64,
+    pub balance_0: f64,
+    pub balance_1: f64,
+    pub version: u64,
+    pub ticks: Vec<TickLiquidity>,
+    pub positions: Vec<PositionLiquidity>,
+}
+
+pub struct UniswapV3Pool {
+    token_0: TokenId,
+    token_1: TokenId,
+    decimals_0: u8,
+    decimals_1: u8,
+    fee: f64,
+    min_tick: i32,
+    max_tick: i32,
+    state: RwLock<PoolState>,
+    events: Publisher,
+}
+
+impl Clone for UniswapV3Pool {
+    fn clone(&self) -> UniswapV3Pool {
+        UniswapV3Pool {
+            token_0: self.token_0,
+            token_1: self.token_1,
+            decimals_0: self.decimals_0,
+            decimals_1: self.decimals_1,
+            fee: self.fee,
+            min_tick: self.min_tick,
+            max_tick: self.max_tick,
+            state: RwLock::new(self.state.read().unwrap().clone()),
+            events: Publisher::default(),
+        }
+    }
+}
+
+impl PoolState {
+    fn ticks(&self) -> Vec<TickLiquidity> {
+        let mut ticks: Vec<TickLiquidity> = self.tick_mapping
+            .iter()
+            .filter(|(_, info)| info.initialized)
+            .map(|(tick, info)| TickLiquidity {
+                tick: *tick,
+                liquidity_net: info.liquidity_net,
+                liquidity_gross: info.liquidity_gross,
+            })
+            .collect();
+        ticks.sort_by_key(|t| t.tick);
+        ticks
+    }
+
+    fn update(&mut self, tick: i32, liquidity_delta: f64, upper: bool) -> bool {
+        let default_tick = Tick {
+            liquidity_gross: 0.0,
+            liquidity_net: 0.0,
+            initialized: false,
+        };
+
+        let info = self.tick_mapping.entry(tick).or_insert(default_tick);
+
+        let liquidity_before = info.liquidity_gross;
+
+        let liquidity_after = liquidity_before + liquidity_delta;
+
+        info.liquidity_gross = liquidity_after;
+        if upper {
+            info.liquidity_net -= liquidity_delta;
+        } else {
+            info.liquidity_net += liquidity_delta;
+        }
+        info.initialized = liquidity_after != 0.0;
+
+        if info.initialized {
+            self.liquidity_mapping.insert(tick, info.liquidity_net);
+        } else {
+            self.liquidity_mapping.remove(&tick);
+            self.tick_mapping.remove(&tick);
+        }
+
+        (liquidity_after == 0.0) != (liquidity_before == 0.0)
+    }
+
+    fn _update_position(
+        &mut self,
+        owner: &Account,
+        lower_tick: i32,
+        upper_tick: i32,
+        liquidity_delta: f64
+    ) {
+        let default_position = Position { liquidity: 0.0 };
+
+        let key = (owner.id, lower_tick, upper_tick);
+        let position = self.position_mapping.entry(key).or_insert(default_position);
+
+        position.liquidity += liquidity_delta;
+        if position.liquidity == 0.0 {
+            self.position_mapping.remove(&key);
+        }
+    }
+
+    fn _position_amounts(&self, lower_tick: i32, upper_tick: i32, liquidity_delta: f64) -> (f64, f64) {
+        let mut amount0: f64 = 0.0;
+        let mut amount1: f64 = 0.0;
+        if liquidity_delta != 0.0 {
+            if self.tick < lower_tick {
+                amount0 = calc_amount0(
+                    liquidity_delta,
+                    tick_to_sqrtp(lower_tick),
+                    tick_to_sqrtp(upper_tick)
+                );
+            } else if self.tick < upper_tick {
+                amount0 = calc_amount0(liquidity_delta, self.sqrt_price_x96, tick_to_sqrtp(upper_tick));
+
+                amount1 = calc_amount1(liquidity_delta, tick_to_sqrtp(lower_tick), self.sqrt_price_x96);
+            } else {
+                amount1 = calc_amount1(
+                    liquidity_delta,
+                    tick_to_sqrtp(lower_tick),
+                    tick_to_sqrtp(upper_tick)
+                );
+            }
+        }
+        (amount0, amount1)
+    }
+
+    fn _modify_position(
+        &mut self,
+        owner: &Account,
+        lower_tick: i32,
+        upper_tick: i32,
+        liquidity_delta: f64
+    ) -> (f64, f64) {
+        let amounts = self._modify_ticks(lower_tick, upper_tick, liquidity_delta);
+        self._update_position(owner, lower_tick, upper_tick, liquidity_delta);
+        amounts
+    }
+
+    // [_modify_ticks] adds [liquidity_delta] to a range without booking it to any position.
+    fn _modify_ticks(&mut self, lower_tick: i32, upper_tick: i32, liquidity_delta: f64) -> (f64, f64) {
+        let amounts = self._position_amounts(lower_tick, upper_tick, liquidity_delta);
+        self.update(lower_tick, liquidity_delta, false);
+        self.update(upper_tick, liquidity_delta, true);
+        if lower_tick <= self.tick && self.tick < upper_tick {
+            self.liquidity += liquidity_delta;
+        }
+        amounts
+    }
+}
+
+impl UniswapV3Pool {
+    pub fn new(token_0: &Token, token_1: &Token, sqrt_price_x96: f64, fee: f64) -> UniswapV3Pool {
+        UniswapV3Pool {
+            max_tick: get_max_tick(),
+            min_tick: get_min_tick(),
+            token_0: token_0.id,
+            token_1: token_1.id,
+            decimals_0: token_0.decimals,
+            decimals_1: token_1.decimals,
+            fee,
+            state: RwLock::new(PoolState {
+                balance_0: 0.0,
+                balance_1: 0.0,
+                tick_mapping: HashMap::new(),
+                liquidity_mapping: HashMap::new(),
+                position_mapping: HashMap::new(),
+                sqrt_price_x96,
+                tick: sqrtp_to_tick(sqrt_price_x96),
+                liquidity: 0.0,
+                version: 0,
+            }),
+            events: Publisher::default(),
+        }
+    }
+
+    pub fn with_events(mut self, events: Publisher) -> UniswapV3Pool {
+        self.events = events;
+        self
+    }
+
+    /// The current human price of token_0 in units of token_1.
+    pub fn price(&self) -> f64 {
+        sqrtp_to_price(self.sqrt_price_x96(), self.decimals_0, self.decimals_1)
+    }
+
+    pub fn token_0(&self) -> TokenId {
+        self.token_0
+    }
+
+    pub fn token_1(&self) -> TokenId {
+        self.token_1
+    }
+
+    pub fn sqrt_price_x96(&self) -> f64 {
+        self.state.read().unwrap().sqrt_price_x96
+    }
+
+    pub fn tick(&self) -> i32 {
+        self.state.read().unwrap().tick
+    }
+
+    pub fn liquidity(&self) -> f64 {
+        self.state.read().unwrap().liquidity
+    }
+
+    /// Lists the initialized ticks from lowest to highest.
+    pub fn ticks(&self) -> Vec<TickLiquidity> {
+        self.state.read().unwrap().ticks()
+    }
+
+    pub fn to_snapshot(&self) -> V3Snapshot {
+        let state = self.state.read().unwrap();
+        let mut positions: Vec<PositionLiquidity> = state.position_mapping
+            .iter()
+            .map(|(&(owner, lower_tick, upper_tick), position)| PositionLiquidity {
+                owner,
+                lower_tick,
+                upper_tick,
+                liquidity: position.liquidity,
+            })
+            .collect();
+        positions.sort_by_key(|p| (p.owner, p.lower_tick, p.upper_tick));
+        V3Snapshot {
+            token_0: self.token_0,
+            token_1: self.token_1,
+            decimals_0: self.decimals_0,
+            decimals_1: self.decimals_1,
+            fee: self.fee,
+            min_tick: self.min_tick,
+            max_tick: self.max_tick,
+            sqrt_price_x96: state.sqrt_price_x96,
+            tick: state.tick,
+            liquidity: state.liquidity,
+            balance_0: state.balance_0,
+            balance_1: state.balance_1,
+            version: state.version,
+            ticks: state.ticks(),
+            positions,
+        }
+    }
+
+    /// Rebuilds a pool saved by `to_snapshot`, at the same version and without subscribers.
+    pub fn from_snapshot(snapshot: &V3Snapshot) -> Result<UniswapV3Pool, PoolError> {
+        if snapshot.token_0 == snapshot.token_1 {
+            return Err(PoolError::UnknownToken { token: snapshot.token_1 });
+        }
+        if !(0.0..1.0).contains(&snapshot.fee) {
+            return Err(PoolError::InvalidParameter { name: "fee", value: snapshot.fee });
+        }
+        if !snapshot.sqrt_price_x96.is_finite() || snapshot.sqrt_price_x96 <= 0.0 {
+            return Err(PoolError::InvalidParameter {
+                name: "sqrt_price_x96",
+                value: snapshot.sqrt_price_x96,
+            });
+        }
+        if !snapshot.liquidity.is_finite() || snapshot.liquidity < 0.0 {
+            return Err(PoolError::InvalidParameter { name: "liquidity", value: snapshot.liquidity });
+        }
+        if snapshot.min_tick >= snapshot.max_tick {
+            return Err(PoolError::InvalidTickRange { lower: snapshot.min_tick, upper: snapshot.max_tick });
+        }
+        // a swap that stops on a tick boundary leaves the tick one below the price's
+        if (snapshot.tick - sqrtp_to_tick(snapshot.sqrt_price_x96)).abs() > 1 {
+            return Err(PoolError::InvalidParameter { name: "tick", value: snapshot.tick as f64 });
+        }
+        for (name, balance) in [("balance_0", snapshot.balance_0), ("balance_1", snapshot.balance_1)] {
+            if !balance.is_finite() || balance < 0.0 {
+                return Err(PoolError::InvalidParameter { name, value: balance });
+            }
+        }
+
+        let mut tick_mapping = HashMap::new();
+        let mut liquidity_mapping = HashMap::new();
+        for t in &snapshot.ticks {
+            if t.tick < snapshot.min_tick || t.tick > snapshot.max_tick || tick_mapping.contains_key(&t.tick) {
+                return Err(PoolError::InvalidParameter { name: "tick", value: t.tick as f64 });
+            }
+            if !t.liquidity_gross.is_finite() || t.liquidity_gross <= 0.0 {
+                return Err(PoolError::InvalidParameter { name: "liquidity_gross", value: t.liquidity_gross });
+            }
+            if !t.liquidity_net.is_finite() {
+                return Err(PoolError::InvalidParameter { name: "liquidity_net", value: t.liquidity_net });
+            }
+            tick_mapping.insert(t.tick, Tick {
+                liquidity_gross: t.liquidity_gross,
+                liquidity_net: t.liquidity_net,
+                initialized: true,
+            });
+            liquidity_mapping.insert(t.tick, t.liquidity_net);
+        }
+        // positions cannot hold more than their ticks, though ticks may hold liquidity no position owns
+        let mut position_mapping = HashMap::new();
+        let mut owned: HashMap<i32, f64> = HashMap::new();
+        for p in &snapshot.positions {
+            let (lower, upper) = (p.lower_tick, p.upper_tick);
+            if lower >= upper || !tick_mapping.contains_key(&lower) || !tick_mapping.contains_key(&upper) {
+                return Err(PoolError::InvalidTickRange { lower, upper });
+            }
+            if !p.liquidity.is_finite() || p.liquidity <= 0.0 {
+                return Err(PoolError::InvalidParameter { name: "position", value: p.liquidity });
+            }
+            if position_mapping.insert((p.owner, lower, upper), Position { liquidity: p.liquidity }).is_some() {
+                return Err(PoolError::InvalidParameter { name: "owner", value: p.owner as f64 });
+            }
+            *owned.entry(lower).or_default() += p.liquidity;
+            *owned.entry(upper).or_default() += p.liquidity;
+        }
+        for (tick, liquidity) in owned {
+            let gross = tick_mapping[&tick].liquidity_gross;
+            if liquidity > gross * (1.0 + SNAPSHOT_TOLERANCE) {
+                return Err(PoolError::InvalidParameter { name: "position", value: liquidity });
+            }
+        }
+
+        Ok(UniswapV3Pool {
+            token_0: snapshot.token_0,
+            token_1: snapshot.token_1,
+            decimals_0: snapshot.decimals_0,
+            decimals_1: snapshot.decimals_1,
+            fee: snapshot.fee,
+            min_tick: snapshot.min_tick,
+            max_tick: snapshot.max_tick,
+            state: RwLock::new(PoolState {
+                balance_0: snapshot.balance_0,
+                balance_1: snapshot.balance_1,
+                tick_mapping,
+                liquidity_mapping,
+                position_mapping,
+                sqrt_price_x96: snapshot.sqrt_price_x96,
+                tick: snapshot.tick,
+                liquidity: snapshot.liquidity,
+                version: snapshot.version,
+            }),
+            events: Publisher::default(),
+        })
+    }
+
+    /// Adds liquidity to a range, or removes it when `liquidity_delta` is negative.
+    /// Returns the amounts of token_0 and token_1 paid into the pool (negative when
+    /// withdrawn).
+    pub fn mint(
+        &self,
+        owner: &Account,
+        lower_tick: i32,
+        upper_tick: i32,
+        liquidity_delta: f64
+    ) -> Result<(f64, f64), PoolError> {
+        // a negative delta is a burn, but a zero or non-finite one changes nothing sensible
+        if !liquidity_delta.is_finite() || liquidity_delta == 0.0 {
+            return Err(PoolError::InvalidParameter { name: "liquidity_delta", value: liquidity_delta });
+        }
+        let mut state = self.state.write().unwrap();
+        if state.sqrt_price_x96 <= 0.0 {
+            return Err(PoolError::NotInitialized);
+        }
+        if lower_tick >= upper_tick || lower_tick < self.min_tick || upper_tick > self.max_tick {
+            return Err(PoolError::InvalidTickRange { lower: lower_tick, upper: upper_tick });
+        }
+
+        let position_liquidity = state.position_mapping
+            .get(&(owner.id, lower_tick, upper_tick))
+            .map_or(0.0, |p| p.liquidity);
+        if position_liquidity + liquidity_delta < 0.0 {
+            return Err(PoolError::InsufficientLiquidity);
+        }
+
+        let (amount0, amount1) = state._position_amounts(lower_tick, upper_tick, liquidity_delta);
+        owner.ensure(self.token_0, amount0)?;
+        owner.ensure(self.token_1, amount1)?;
+
+        let (amount0, amount1) = state._modify_position(owner, lower_tick, upper_tick, liquidity_delta);
+
+        state.balance_0 += amount0;
+        state.balance_1 += amount1;
+        state.version += 1;
+        let kind = if liquidity_delta > 0.0 { EventKind::Mint } else { EventKind::Burn };
+        self.events.emit(kind, state.version);
+        drop(state);
+
+        for (token, amount) in [(self.token_0, amount0), (self.token_1, amount1)] {
+            if amount > 0.0 {
+                owner.debit(token, amount, EntryReason::Mint)?;
+            } else if amount < 0.0 {
+                owner.credit(token, -amount, EntryReason::Burn)?;
+            }
+        }
+        Ok((amount0, amount1))
+    }
+}
+
+impl Holdings for UniswapV3Pool {
+    fn holdings(&self) -> Vec<(TokenId, f64)> {
+        let state = self.state.read().unwrap();
+        vec![(self.token_0, state.balance_0), (self.token_1, state.balance_1)]
+    }
+}
+
+struct SwapState {
+    amount_specified_remaining: f64,
+    amount_calculated: f64,
+    sqrt_price_x96: f64,
+    tick: i32,
+    liquidity: f64,
+    ticks_crossed: usize,
+}
+
+struct StepState {
+    next_tick: i32,
+    initialized: bool,
+    sqrt_price_next_x96: f64,
+    amount_in: f64,
+    amount_out: f64,
+}
+
+// [next_initialized_tick] is the closest initialized tick above [tick] when [is_up], or at or below it otherwise.
+fn next_initialized_tick(liquidity_mapping: &HashMap<i32, f64>, tick: i32, is_up: bool) -> Option<i32> {
+    if is_up {
+        liquidity_mapping.keys().filter(|&&x| x > tick).min().copied()
+    } else {
+        liquidity_mapping.keys().filter(|&&x| x <= tick).max().copied()
+    }
+}
+
+fn cross(liquidity_mapping: &HashMap<i32, f64>, next_tick: i32) -> Result<f64, PoolError> {
+    liquidity_mapping.get(&next_tick).copied().ok_or(PoolError::NotInitialized)
+}
+
+impl UniswapV3Pool {
+    // [simulate_swap] walks the ticks for a swap of [amount_specified], the input if [exact_in] or else the output before fees, without changing anything.
+    fn simulate_swap(
+        &self,
+        pool_state: &PoolState,
+        token_in: TokenId,
+        amount_specified: f64,
+        exact_in: bool
+    ) -> Result<SwapState, PoolError> {
+        if token_in != self.token_0 && token_in != self.token_1 {
+            return Err(PoolError::UnknownToken { token: token_in });
+        }
+        if pool_state.sqrt_price_x96 <= 0.0 {
+            return Err(PoolError::NotInitialized);
+        }
+
+        let zero_for_one: bool = token_in == self.token_0;
+
+        let mut state = SwapState {
+            amount_specified_remaining: amount_specified,
+            amount_calculated: 0.0,
+            sqrt_price_x96: pool_state.sqrt_price_x96,
+            tick: pool_state.tick,
+            liquidity: pool_state.liquidity,
+            ticks_crossed: 0,
+        };
+
+        let liquidity_mapping = &pool_state.liquidity_mapping;
+
+        while state.amount_specified_remaining > 0.0 {
+            let next_tick = next_initialized_tick(liquidity_mapping, state.tick, !zero_for_one);
+            let (next_tick, initialized) = match next_tick {
+                Some(t) => (t, true),
+                None if zero_for_one => (self.min_tick, false),
+                None => (self.max_tick, false),
+            };
+            let sqrt_price_next_x96 = tick_to_sqrtp(next_tick);
+
+            if state.sqrt_price_x96 == sqrt_price_next_x96 {
+                break;
+            }
+
+            let (next_sqrt_price_x96, amount_in, amount_out) = if exact_in {
+                compute_swap_step(
+                    state.sqrt_price_x96,
+                    sqrt_price_next_x96,
+                    state.liquidity,
+                    state.amount_specified_remaining
+                )
+            } else {
+                compute_swap_step_exact_out(
+                    state.sqrt_price_x96,
+                    sqrt_price_next_x96,
+                    state.liquidity,
+                    state.amount_specified_remaining
+                )
+            };
+
+            let step = StepState {
+                next_tick,
+                initialized,
+                sqrt_price_next_x96,
+                amount_in,
+                amount_out,
+            };
+
+            state.sqrt_price_x96 = next_sqrt_price_x96;
+            if exact_in {
+                state.amount_specified_remaining -= step.amount_in;
+                state.amount_calculated += step.amount_out;
+            } else {
+                state.amount_specified_remaining -= step.amount_out;
+                state.amount_calculated += step.amount_in;
+            }
+
+            if state.sqrt_price_x96 == step.sqrt_price_next_x96 {
+                if !step.initialized {
+                    break;
+                }
+                let mut liquidity_delta = cross(liquidity_mapping, step.next_tick)?;
+
+                if zero_for_one {
+                    liquidity_delta = -liquidity_delta;
+                }
+
+                state.liquidity += liquidity_delta;
+                state.ticks_crossed += 1;
+
+                state.tick = if zero_for_one { step.next_tick - 1 } else { step.next_tick };
+            } else {
+                state.tick = sqrtp_to_tick(state.sqrt_price_x96);
+            }
+        }
+
+        if state.amount_specified_remaining > 0.0 {
+            if state.amount_calculated == 0.0 {
+                return Err(PoolError::InsufficientLiquidity);
+            }
+            return Err(PoolError::PriceLimitReached {
+                amount_remaining: state.amount_specified_remaining,
+            });
+        }
+        Ok(state)
+    }
+
+    // [quote] simulates a swap against the current state, read under one lock.
+    fn quote(&self, token_in: TokenId, amount_specified: f64, exact_in: bool) -> Result<SwapState, PoolError> {
+        let pool_state = self.state.read().unwrap();
+        self.simulate_swap(&pool_state, token_in, amount_specified, exact_in)
+    }
+}
+
+/// Swaps `amount_specified` of `token_in` and returns (amount in, amount out after
+/// fees). Priced and applied under one write lock; nothing changes on error.
+pub fn v3_swap(
+    trader: &mut Account,
+    pool: &UniswapV3Pool,
+    token_in: TokenId,
+    amount_specified: f64,
+    min_amount_out: f64
+) -> Result<(f64, f64), PoolError> {
+    let mut pool_state = pool.state.write().unwrap();
+    if pool_state.sqrt_price_x96 <= 0.0 {
+        return Err(PoolError::NotInitialized);
+    }
+    if !amount_specified.is_finite() || amount_specified <= 0.0 {
+        return Err(PoolError::InvalidParameter { name: "amount_in", value: amount_specified });
+    }
+    trader.ensure(token_in, amount_specified)?;
+
+    let state = pool.simulate_swap(&pool_state, token_in, amount_specified, true)?;
+
+    let amount_in = amount_specified - state.amount_specified_remaining;
+    let amount_out = state.amount_calculated;
+    // the fee is taken from the output and stays in the pool
+    let fee_amount = pool.fee * amount_out;
+
+    if amount_out - fee_amount < min_amount_out {
+        return Err(PoolError::SlippageExceeded {
+            min_out: min_amount_out,
+            amount_out: amount_out - fee_amount,
+        });
+    }
+
+    pool_state.liquidity = state.liquidity;
+    pool_state.tick = state.tick;
+    pool_state.sqrt_price_x96 = state.sqrt_price_x96;
+
+    let token_out = if token_in == pool.token_0 {
+        pool_state.balance_0 += amount_in;
+        pool_state.balance_1 -= amount_out - fee_amount;
+        pool.token_1
+    } else {
+        pool_state.balance_1 += amount_in;
+        pool_state.balance_0 -= amount_out - fee_amount;
+        pool.token_0
+    };
+    pool_state.version += 1;
+    pool.events.emit(EventKind::Swap, pool_state.version);
+    drop(pool_state);
+
+    trader.debit(token_in, amount_in, EntryReason::Swap)?;
+    trader.credit(token_out, amount_out, EntryReason::Swap)?;
+    trader.debit(token_out, fee_amount, EntryReason::Fee)?;
+    Ok((amount_in, amount_out - fee_amount))
+}
+
+/// Applies a recorded mint or burn to a range without an owner, so liquidity
+/// minted before a log started can be burned. The range's ticks must hold what
+/// is burned.
+pub fn v3_liquidity(
+    pool: &UniswapV3Pool,
+    lower_tick: i32,
+    upper_tick: i32,
+    liquidity_delta: f64
+) -> Result<(f64, f64), PoolError> {
+    if !liquidity_delta.is_finite() {
+        return Err(PoolError::InvalidParameter { name: "liquidity", value: liquidity_delta });
+    }
+    let mut state = pool.state.write().unwrap();
+    if state.sqrt_price_x96 <= 0.0 {
+        return Err(PoolError::NotInitialized);
+    }
+    if lower_tick >= upper_tick || lower_tick < pool.min_tick || upper_tick > pool.max_tick {
+        return Err(PoolError::InvalidTickRange { lower: lower_tick, upper: upper_tick });
+    }
+    if liquidity_delta == 0.0 {
+        return Ok((0.0, 0.0));
+    }
+    let gross = |tick| state.tick_mapping.get(&tick).map_or(0.0, |t| t.liquidity_gross);
+    if gross(lower_tick).min(gross(upper_tick)) + liquidity_delta < 0.0 {
+        return Err(PoolError::InsufficientLiquidity);
+    }
+    let (amount0, amount1) = state._position_amounts(lower_tick, upper_tick, liquidity_delta);
+    if state.balance_0 + amount0 < 0.0 || state.balance_1 + amount1 < 0.0 {
+        return Err(PoolError::InsufficientLiquidity);
+    }
+
+    state._modify_ticks(lower_tick, upper_tick, liquidity_delta);
+    state.balance_0 += amount0;
+    state.balance_1 += amount1;
+    state.version += 1;
+    let kind = if liquidity_delta > 0.0 { EventKind::Mint } else { EventKind::Burn };
+    pool.events.emit(kind, state.version);
+    Ok((amount0, amount1))
+}
+
+/// Moves the price to `sqrt_price_x96` without trading, crossing the ticks in
+/// between. Balances are left alone.
+pub fn v3_sync(pool: &UniswapV3Pool, sqrt_price_x96: f64) -> Result<(), PoolError> {
+    if !sqrt_price_x96.is_finite() || sqrt_price_x96 <= 0.0 {
+        return Err(PoolError::InvalidParameter { name: "sqrt_price_x96", value: sqrt_price_x96 });
+    }
+    let mut state = pool.state.write().unwrap();
+    let tick = sqrtp_to_tick(sqrt_price_x96);
+    // crossing a tick upwards adds its net liquidity, crossing it downwards takes it away
+    let crossed: f64 = state.liquidity_mapping
+        .iter()
+        .map(|(&t, &net)| {
+            if state.tick < t && t <= tick {
+                net
+            } else if tick < t && t <= state.tick {
+                -net
+            } else {
+                0.0
+            }
+        })
+        .sum();
+    state.liquidity += crossed;
+    state.sqrt_price_x96 = sqrt_price_x96;
+    state.tick = tick;
+    state.version += 1;
+    pool.events.emit(EventKind::Sync, state.version);
+    Ok(())
+}
+
+impl Amm for UniswapV3Pool {
+    fn tokens(&self) -> Vec<TokenId> {
+        vec![self.token_0, self.token_1]
+    }
+
+    fn fee(&self) -> f64 {
+        self.fee
+    }
+
+    fn spot_price(&self, token_in: TokenId, token_out: TokenId) -> Result<f64, PoolError> {
+        check_pair(self, token_in, token_out)?;
+        let sqrt_price_x96 = self.sqrt_price_x96();
+        if sqrt_price_x96 <= 0.0 {
+            return Err(PoolError::NotInitialized);
+        }
+        let sqrt_price = sqrt_price_x96 / get_q96();
+        let raw_price = sqrt_price * sqrt_price;
+        Ok(if token_in == self.token_0 { raw_price } else { 1.0 / raw_price })
+    }
+
+    fn quote_exact_in(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64
+    ) -> Result<f64, PoolError> {
+        check_pair(self, token_in, token_out)?;
+        if amount_in <= 0.0 {
+            return Ok(0.0);
+        }
+        let state = self.quote(token_in, amount_in, true)?;
+        Ok(state.amount_calculated * (1.0 - self.fee))
+    }
+
+    fn quote_exact_out(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_out: f64
+    ) -> Result<f64, PoolError> {
+        check_pair(self, token_in, token_out)?;
+        if amount_out <= 0.0 {
+            return Ok(0.0);
+        }
+        let state = self.quote(token_in, amount_out / (1.0 - self.fee), false)?;
+        Ok(state.amount_calculated)
+    }
+
+    fn apply_swap(
+        &self,
+        trader: &mut Account,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64,
+        min_amount_out: f64
+    ) -> Result<f64, PoolError> {
+        check_pair(self, token_in, token_out)?;
+        v3_swap(trader, self, token_in, amount_in, min_amount_out).map(|(_, out)| out)
+    }
+
+    fn ticks_crossed(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64
+    ) -> Result<usize, PoolError> {
+        check_pair(self, token_in, token_out)?;
+        if amount_in <= 0.0 {
+            return Ok(0);
+        }
+        Ok(self.quote(token_in, amount_in, true)?.ticks_crossed)
+    }
+
+    fn version(&self) -> u64 {
+        self.state.read().unwrap().version
+    }
+
+    fn snapshot(&self) -> Box<dyn Amm + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::{check_conservation, totals};
+    use crate::math::{price_to_sqrtp, price_to_tick};
+    use crate::token::TokenRegistry;
+
+    fn set_up_tokens() -> (Token, Token) {
+        let mut registry = TokenRegistry::new();
+        let eth = registry.register("WETH", 18, "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let dai = registry.register("DAI", 18, "0x6B175474E89094C44Da98b954EedeAC495271d0F");
+        (registry.get(eth).unwrap().clone(), registry.get(dai).unwrap().clone())
+    }
+
+    fn set_up_pool(
+        mint: bool,
+        lower_tick: i32,
+        upper_tick: i32,
+        liquidity: f64
+    ) -> (Account, UniswapV3Pool) {
+        let (eth, dai) = set_up_tokens();
+        let trader = Account::new(2, &[(eth.id, 1000000000000.0), (dai.id, 1000000000000000.0)]);
+        let pool = UniswapV3Pool::new(&eth, &dai, 5602277097478614198912276234240.0, 0.03);
+        if mint {
+            pool.mint(&trader, lower_tick, upper_tick, liquidity).unwrap();
+        }
+
+        (trader, pool)
+    }
+
+    #[test]
+    fn pool_price_across_decimals() {
+        let mut registry = TokenRegistry::new();
+        let weth = registry.register("WETH", 18, "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let usdc = registry.register("USDC", 6, "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+        let sqrtp = price_to_sqrtp(2000.0, 18, 6);
+        let pool = UniswapV3Pool::new(registry.get(weth).unwrap(), registry.get(usdc).unwrap(), sqrtp, 0.003);
+
+        assert_eq!(pool.tick(), price_to_tick(2000.0, 18, 6));
+        assert!((pool.price() - 2000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn v3_test_mint() {
+        let (eth, dai) = set_up_tokens();
+        let trader = Account::new(
+            2,
+            &[(eth.id, eth.amount(2000.0).raw_f64()), (dai.id, dai.amount(10000.0).raw_f64())]
+        );
+        let pool = UniswapV3Pool::new(&eth, &dai, 5602277097478614198912276234240.0, 0.03);
+
+        pool.mint(&trader, 84222, 86129, 1517882343751509868544.0).unwrap();
+
+        assert_eq!(pool.sqrt_price_x96(), 5602277097478614198912276234240.0);
+        assert_eq!(pool.tick(), 85176);
+        let ticks: Vec<(i32, f64)> = pool
+            .ticks()
+            .iter()
+            .map(|t| (t.tick, t.liquidity_net))
+            .collect();
+        assert_eq!(ticks, vec![(84222, 1517882343751509868544.0), (86129, -1517882343751509868544.0)]);
+    }
+    #[test]
+    fn v3_test_remove() {
+        let (eth, dai) = set_up_tokens();
+        let trader = Account::new(
+            2,
+            &[(eth.id, eth.amount(2000.0).raw_f64()), (dai.id, dai.amount(10000.0).raw_f64())]
+        );
+        let pool = UniswapV3Pool::new(&eth, &dai, 5602277097478614198912276234240.0, 0.03);
+
+        pool.mint(&trader, 84222, 86129, 1517882343751509868544.0).unwrap();
+
+        let liq = pool.liquidity();
+
+        assert_eq!(liq, 1517882343751509868544.0);
+
+        pool.mint(&trader, 84222, 86129, -1517882343751509868544.0).unwrap();
+
+        assert_eq!(pool.sqrt_price_x96(), 5602277097478614198912276234240.0);
+        let new_liquidity = pool.liquidity();
+        assert_eq!(new_liquidity, 0.0)
+    }
+
+    #[test]
+    fn test_swap_eth() {
+        let (mut trader, pool) = set_up_pool(true, -86000, 86000, 100000000000.0);
+        let (eth, dai) = (pool.token_0(), pool.token_1());
+        let original = trader.balance(eth);
+        let og_dai = trader.balance(dai);
+
+        v3_swap(&mut trader, &pool, eth, 1000000.0, 0.0).unwrap();
+
+        let post = trader.balance(eth);
+        let post_dai = trader.balance(dai);
+
+        assert!(original > post);
+        assert!(post_dai > og_dai);
+    }
+
+    #[test]
+    fn test_swap_dai() {
+        let (mut trader, pool) = set_up_pool(true, -86000, 86000, 10000000000000.0);
+        let (eth, dai) = (pool.token_0(), pool.token_1());
+        let original = trader.balance(eth);
+        let og_dai = trader.balance(dai);
+
+        v3_swap(&mut trader, &pool, dai, 100.0, 0.0).unwrap();
+
+        let post = trader.balance(eth);
+        let post_dai = trader.balance(dai);
+
+        assert!(original < post);
+        assert!(post_dai < og_dai);
+    }
+
+    #[test]
+    fn swap_crosses_into_next_range() {
+        let (mut trader, pool) = set_up_pool(true, 84000, 86000, 1000000000000.0);
+        pool.mint(&trader, 80000, 84000, 1000000000000.0).unwrap();
+
+        v3_swap(&mut trader, &pool, pool.token_0(), 1000000000.0, 0.0).unwrap();
+
+        let tick = pool.tick();
+        assert!(tick < 84000);
+        assert_eq!(pool.liquidity(), 1000000000000.0);
+    }
+
+    #[test]
+    fn sync_crosses_ticks_like_a_swap() {
+        let (mut trader, pool) = set_up_pool(true, 84000, 86000, 1000000000000.0);
+        pool.mint(&trader, 80000, 84000, 2000000000000.0).unwrap();
+        let synced = pool.clone();
+        let balances = synced.holdings();
+
+        v3_swap(&mut trader, &pool, pool.token_0(), 1000000000.0, 0.0).unwrap();
+        v3_sync(&synced, pool.sqrt_price_x96()).unwrap();
+        assert_eq!((synced.tick(), synced.liquidity()), (pool.tick(), pool.liquidity()));
+        assert_eq!(synced.holdings(), balances);
+
+        // and back up again
+        v3_sync(&synced, 5602277097478614198912276234240.0).unwrap();
+        assert_eq!(synced.liquidity(), 1000000000000.0);
+        for bad in [0.0, f64::NAN, f64::INFINITY] {
+            assert!(v3_sync(&synced, bad).is_err());
+        }
+        assert_eq!(synced.liquidity(), 1000000000000.0);
+    }
+
+    #[test]
+    fn quotes_match_swaps_in_both_directions() {
+        let (mut trader, pool) = set_up_pool(true, 84000, 86000, 1000000000000.0);
+        pool.mint(&trader, 80000, 84000, 1000000000000.0).unwrap();
+        let (eth, dai) = (pool.token_0(), pool.token_1());
+
+        let out = pool.quote_exact_in(eth, dai, 1000000000.0).unwrap();
+        let back = pool.quote_exact_out(eth, dai, out).unwrap();
+        assert!((back - 1000000000.0).abs() / 1000000000.0 < 1e-9);
+        assert!(pool.spot_price(eth, dai).unwrap() * pool.spot_price(dai, eth).unwrap() - 1.0 < 1e-12);
+
+        let snapshot = pool.snapshot();
+        let paid = pool.apply_swap(&mut trader, eth, dai, 1000000000.0, 0.0).unwrap();
+        assert_eq!(paid, out);
+        assert_eq!(pool.version(), snapshot.version() + 1);
+        assert!(pool.tick() < 84000);
+        assert!(snapshot.spot_price(eth, dai).unwrap() > pool.spot_price(eth, dai).unwrap());
+        assert_eq!(snapshot.ticks_crossed(eth, dai, 1000000000.0), Ok(1));
+        assert_eq!(snapshot.ticks_crossed(eth, dai, 1000.0), Ok(0));
+        assert_eq!(
+            pool.quote_exact_in(eth, eth, 1.0),
+            Err(PoolError::UnknownToken { token: eth })
+        );
+    }
+
+    #[test]
+    fn mint_without_funds_fails() {
+        let (eth, dai) = set_up_tokens();
+        let trader = Account::new(2, &[(eth.id, 1.0), (dai.id, 1.0)]);
+        let pool = UniswapV3Pool::new(&eth, &dai, 5602277097478614198912276234240.0, 0.03);
+
+        let err = pool.mint(&trader, 84222, 86129, 1517882343751509868544.0).unwrap_err();
+
+        assert!(matches!(err, PoolError::InsufficientBalance { account: 2, .. }));
+        assert_eq!(pool.liquidity(), 0.0);
+        assert_eq!(trader.balance(eth.id), 1.0);
+        assert!(trader.history().is_empty());
+    }
+
+    #[test]
+    fn mint_rejects_bad_ranges_and_over_burns() {
+        let (trader, pool) = set_up_pool(true, -86000, 86000, 100000000000.0);
+
+        assert_eq!(
+            pool.mint(&trader, 86000, -86000, 1.0),
+            Err(PoolError::InvalidTickRange { lower: 86000, upper: -86000 })
+        );
+        assert_eq!(
+            pool.mint(&trader, -900000, 86000, 1.0),
+            Err(PoolError::InvalidTickRange { lower: -900000, upper: 86000 })
+        );
+        assert_eq!(
+            pool.mint(&trader, -86000, 86000, -200000000000.0),
+            Err(PoolError::InsufficientLiquidity)
+        );
+
+        for delta in [0.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                pool.mint(&trader, -86000, 86000, delta),
+                Err(PoolError::InvalidParameter { name: "liquidity_delta", .. })
+            ));
+        }
+
+        // liquidity in one range cannot be burned from another
+        assert_eq!(
+            pool.mint(&trader, -1000, 1000, -1.0),
+            Err(PoolError::InsufficientLiquidity)
+        );
+        assert_eq!(pool.liquidity(), 100000000000.0);
+    }
+
+    #[test]
+    fn swap_errors_leave_pool_untouched() {
+        let (eth, dai) = set_up_tokens();
+        let mut trader = Account::new(2, &[(eth.id, 1000000000000.0), (dai.id, 1000000000000000.0)]);
+
+        let uninitialized = UniswapV3Pool::new(&eth, &dai, 0.0, 0.03);
+        assert_eq!(
+            v3_swap(&mut trader, &uninitialized, eth.id, 1.0, 0.0),
+            Err(PoolError::NotInitialized)
+        );
+        assert_eq!(uninitialized.mint(&trader, -10, 10, 1.0), Err(PoolError::NotInitialized));
+
+        let empty = UniswapV3Pool::new(&eth, &dai, 5602277097478614198912276234240.0, 0.03);
+        assert_eq!(
+            v3_swap(&mut trader, &empty, eth.id, 1.0, 0.0),
+            Err(PoolError::InsufficientLiquidity)
+        );
+
+        let (mut trader, pool) = set_up_pool(true, 84000, 86000, 1000000000000.0);
+        let sqrtp = pool.sqrt_price_x96();
+
+        let err = v3_swap(&mut trader, &pool, eth.id, 100000000000.0, 0.0).unwrap_err();
+        assert!(matches!(err, PoolError::PriceLimitReached { .. }));
+
+        let err = v3_swap(&mut trader, &pool, eth.id, 1000.0, 10000000.0).unwrap_err();
+        assert!(matches!(err, PoolError::SlippageExceeded { .. }));
+
+        let err = v3_swap(&mut trader, &pool, eth.id, 1.0e20, 0.0).unwrap_err();
+        assert!(matches!(err, PoolError::InsufficientBalance { .. }));
+
+        for amount in [0.0, -1000.0, f64::NAN] {
+            let err = v3_swap(&mut trader, &pool, eth.id, amount, 0.0).unwrap_err();
+            assert!(matches!(err, PoolError::InvalidParameter { name: "amount_in", .. }));
+        }
+
+        assert_eq!(pool.sqrt_price_x96(), sqrtp);
+        assert_eq!(trader.history().len(), 2);
+    }
+
+    #[test]
+    fn swap_records_entries_and_conserves_tokens() {
+        let (mut trader, pool) = set_up_pool(true, -86000, 86000, 10000000000000.0);
+        let (eth, dai) = (pool.token_0(), pool.token_1());
+        let supply = totals(&[&trader, &pool]);
+
+        v3_swap(&mut trader, &pool, eth, 1000000.0, 0.0).unwrap();
+        check_conservation(&supply, &[&trader, &pool]).unwrap();
+
+        let reasons: Vec<(TokenId, EntryReason)> = trader
+            .history()
+            .iter()
+            .map(|e| (e.token, e.reason))
+            .collect();
+        assert_eq!(reasons, vec![
+            (eth, EntryReason::Mint),
+            (dai, EntryReason::Mint),
+            (eth, EntryReason::Swap),
+            (dai, EntryReason::Swap),
+            (dai, EntryReason::Fee)
+        ]);
+
+        pool.mint(&trader, -86000, 86000, -10000000000000.0).unwrap();
+        check_conservation(&supply, &[&trader, &pool]).unwrap();
+        assert_eq!(trader.history().last().unwrap().reason, EntryReason::Burn);
+    }
+}
@@ -0,0 +1,490 @@
+use crate::amm::{check_pair, Amm};
+use crate::error::PoolError;
+use crate::events::{EventKind, Publisher};
+use crate::ledger::{Account, EntryReason, Holdings};
+use crate::token::TokenId;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+// Balancer caps a single swap at 30% of the balances it touches
+const MAX_IN_RATIO: f64 = 0.3;
+const MAX_OUT_RATIO: f64 = 0.3;
+
+const MIN_WEIGHT: f64 = 0.01;
+
+/// Balancer style weighted constant-mean pool, holding `prod(b_i ^ w_i)`
+/// constant across swaps. Weights are normalized to sum to one. Liquidity
+/// providers hold pool shares keyed by account id.
+pub struct WeightedPool {
+    tokens: Vec<TokenId>,
+    weights: Vec<f64>,
+    fee: f64,
+    state: RwLock<ShareState>,
+    events: Publisher,
+}
+
+/// Balances and shares, kept behind a single lock so that joins, exits and
+/// swaps land as one unit.
+#[derive(Clone)]
+struct ShareState {
+    balances: Vec<f64>,
+    total_shares: f64,
+    share_mapping: HashMap<i32, f64>,
+    version: u64,
+}
+
+impl Clone for WeightedPool {
+    fn clone(&self) -> WeightedPool {
+        WeightedPool {
+            tokens: self.tokens.clone(),
+            weights: self.weights.clone(),
+            fee: self.fee,
+            state: RwLock::new(self.state.read().unwrap().clone()),
+            events: Publisher::default(),
+        }
+    }
+}
+
+/// The amount of the out token paid for `amount_in`, after a fee taken from the input.
+pub fn calc_out_given_in(
+    balance_in: f64,
+    weight_in: f64,
+    balance_out: f64,
+    weight_out: f64,
+    amount_in: f64,
+    fee: f64
+) -> f64 {
+    let amount_in_less_fee = amount_in * (1.0 - fee);
+    let base = balance_in / (balance_in + amount_in_less_fee);
+    balance_out * (1.0 - base.powf(weight_in / weight_out))
+}
+
+/// The amount of the in token, fee included, needed to take `amount_out` from the pool.
+pub fn calc_in_given_out(
+    balance_in: f64,
+    weight_in: f64,
+    balance_out: f64,
+    weight_out: f64,
+    amount_out: f64,
+    fee: f64
+) -> f64 {
+    let base = balance_out / (balance_out - amount_out);
+    (balance_in * (base.powf(weight_out / weight_in) - 1.0)) / (1.0 - fee)
+}
+
+impl WeightedPool {
+    /// Creates an empty pool; the first `join` sets the initial balances. `weights`
+    /// are normalized here, and each must end up at least 1%.
+    pub fn new(tokens: &[TokenId], weights: &[f64], fee: f64) -> Result<WeightedPool, PoolError> {
+        if tokens.len() < 2 || tokens.len() != weights.len() {
+            return Err(PoolError::InvalidParameter {
+                name: "weights",
+                value: weights.len() as f64,
+            });
+        }
+        if let Some(w) = weights.iter().find(|w| !w.is_finite() || **w <= 0.0) {
+            return Err(PoolError::InvalidParameter { name: "weight", value: *w });
+        }
+        let total: f64 = weights.iter().sum();
+        let weights: Vec<f64> = weights
+            .iter()
+            .map(|w| w / total)
+            .collect();
+        if let Some(w) = weights.iter().find(|w| **w < MIN_WEIGHT) {
+            return Err(PoolError::InvalidParameter { name: "weight", value: *w });
+        }
+        if !(0.0..1.0).contains(&fee) {
+            return Err(PoolError::InvalidParameter { name: "fee", value: fee });
+        }
+        Ok(WeightedPool {
+            tokens: tokens.to_vec(),
+            weights,
+            fee,
+            state: RwLock::new(ShareState {
+                balances: vec![0.0; tokens.len()],
+                total_shares: 0.0,
+                share_mapping: HashMap::new(),
+                version: 0,
+            }),
+            events: Publisher::default(),
+        })
+    }
+
+    pub fn with_events(mut self, events: Publisher) -> WeightedPool {
+        self.events = events;
+        self
+    }
+
+    pub fn weights(&self) -> Vec<f64> {
+        self.weights.clone()
+    }
+
+    pub fn balances(&self) -> Vec<f64> {
+        self.state.read().unwrap().balances.clone()
+    }
+
+    pub fn total_shares(&self) -> f64 {
+        self.state.read().unwrap().total_shares
+    }
+
+    pub fn shares(&self, owner: &Account) -> f64 {
+        self.state.read().unwrap().share_mapping.get(&owner.id).copied().unwrap_or(0.0)
+    }
+
+    /// The weighted geometric mean of the balances, prod(b_i ^ w_i).
+    pub fn invariant(&self) -> f64 {
+        self.state
+            .read()
+            .unwrap()
+            .balances
+            .iter()
+            .zip(&self.weights)
+            .map(|(b, w)| b.powf(*w))
+            .product()
+    }
+
+    fn index_of(&self, token: TokenId) -> Result<usize, PoolError> {
+        self.tokens
+            .iter()
+            .position(|t| *t == token)
+            .ok_or(PoolError::UnknownToken { token })
+    }
+
+    // [pair] returns (balance_in, weight_in, balance_out, weight_out) in [state] for a swap of [token_in] into [token_out].
+    fn pair(
+        &self,
+        state: &ShareState,
+        token_in: TokenId,
+        token_out: TokenId
+    ) -> Result<(f64, f64, f64, f64), PoolError> {
+        check_pair(self, token_in, token_out)?;
+        let (i, o) = (self.index_of(token_in)?, self.index_of(token_out)?);
+        let balances = &state.balances;
+        if balances[i] <= 0.0 || balances[o] <= 0.0 {
+            return Err(PoolError::NotInitialized);
+        }
+        Ok((balances[i], self.weights[i], balances[o], self.weights[o]))
+    }
+
+    // [amount_out] is the output of swapping [amount_in] of [token_in] against [state].
+    fn amount_out(
+        &self,
+        state: &ShareState,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64
+    ) -> Result<f64, PoolError> {
+        let (balance_in, weight_in, balance_out, weight_out) = self.pair(state, token_in, token_out)?;
+        if amount_in > balance_in * MAX_IN_RATIO {
+            return Err(PoolError::InsufficientLiquidity);
+        }
+        Ok(calc_out_given_in(balance_in, weight_in, balance_out, weight_out, amount_in, self.fee))
+    }
+
+    /// Adds `amounts_in` and returns the shares minted to `owner`. The first join
+    /// sets the balances; later ones pay the swap fee on whatever exceeds the
+    /// pool's proportions.
+    pub fn join(&self, owner: &Account, amounts_in: &[f64], min_shares_out: f64) -> Result<f64, PoolError> {
+        if amounts_in.len() != self.tokens.len() {
+            return Err(PoolError::InvalidParameter {
+                name: "amounts_in",
+                value: amounts_in.len() as f64,
+            });
+        }
+        if let Some(a) = amounts_in.iter().find(|a| !a.is_finite() || **a < 0.0) {
+            return Err(PoolError::InvalidParameter { name: "amount_in", value: *a });
+        }
+        for (token, amount) in self.tokens.iter().zip(amounts_in) {
+            owner.ensure(*token, *amount)?;
+        }
+
+        let mut state = self.state.write().unwrap();
+        let balances = &state.balances;
+        let total_shares = state.total_shares;
+        let shares_out = if total_shares == 0.0 {
+            if amounts_in.iter().any(|a| *a <= 0.0) {
+                return Err(PoolError::NotInitialized);
+            }
+            let invariant: f64 = amounts_in
+                .iter()
+                .zip(&self.weights)
+                .map(|(a, w)| a.powf(*w))
+                .product();
+            invariant * (self.tokens.len() as f64)
+        } else {
+            let ratio_with_fees: f64 = balances
+                .iter()
+                .zip(amounts_in)
+                .zip(&self.weights)
+                .map(|((b, a), w)| (w * (b + a)) / b)
+                .sum();
+            let mut invariant_ratio = 1.0;
+            for ((b, a), w) in balances.iter().zip(amounts_in).zip(&self.weights) {
+                let balance_ratio = (b + a) / b;
+                let amount_without_fee = if balance_ratio > ratio_with_fees {
+                    let non_taxable = b * (ratio_with_fees - 1.0);
+                    non_taxable + (a - non_taxable) * (1.0 - self.fee)
+                } else {
+                    *a
+                };
+                invariant_ratio *= ((b + amount_without_fee) / b).powf(*w);
+            }
+            total_shares * (invariant_ratio - 1.0)
+        };
+        if shares_out < min_shares_out {
+            return Err(PoolError::SlippageExceeded { min_out: min_shares_out, amount_out: shares_out });
+        }
+
+        for (balance, amount) in state.balances.iter_mut().zip(amounts_in) {
+            *balance += amount;
+        }
+        state.total_shares += shares_out;
+        *state.share_mapping.entry(owner.id).or_insert(0.0) += shares_out;
+        state.version += 1;
+        self.events.emit(EventKind::Mint, state.version);
+        drop(state);
+        for (token, amount) in self.tokens.iter().zip(amounts_in) {
+            if *amount > 0.0 {
+                owner.debit(*token, *amount, EntryReason::Mint)?;
+            }
+        }
+        Ok(shares_out)
+    }
+
+    /// Burns `shares` of `owner` and pays out the same fraction of every balance.
+    pub fn exit(&self, owner: &Account, shares: f64) -> Result<Vec<f64>, PoolError> {
+        if !shares.is_finite() || shares <= 0.0 {
+            return Err(PoolError::InvalidParameter { name: "shares", value: shares });
+        }
+        let mut state = self.state.write().unwrap();
+        let owned = state.share_mapping.get(&owner.id).copied().unwrap_or(0.0);
+        if shares > owned {
+            return Err(PoolError::InsufficientLiquidity);
+        }
+        let fraction = shares / state.total_shares;
+        let amounts_out: Vec<f64> = state.balances
+            .iter()
+            .map(|b| b * fraction)
+            .collect();
+
+        for (balance, amount) in state.balances.iter_mut().zip(&amounts_out) {
+            *balance -= amount;
+        }
+        state.total_shares -= shares;
+        *state.share_mapping.entry(owner.id).or_insert(0.0) -= shares;
+        state.version += 1;
+        self.events.emit(EventKind::Burn, state.version);
+        drop(state);
+        for (token, amount) in self.tokens.iter().zip(&amounts_out) {
+            owner.credit(*token, *amount, EntryReason::Burn)?;
+        }
+        Ok(amounts_out)
+    }
+}
+
+impl Holdings for WeightedPool {
+    fn holdings(&self) -> Vec<(TokenId, f64)> {
+        self.tokens.iter().copied().zip(self.balances()).collect()
+    }
+}
+
+/// Swaps `amount_in` of `token_in` for `token_out` and returns the amount paid out.
+/// The fee is taken from the input and stays in the pool.
+pub fn weighted_swap(
+    trader: &mut Account,
+    pool: &WeightedPool,
+    token_in: TokenId,
+    token_out: TokenId,
+    amount_in: f64,
+    min_amount_out: f64
+) -> Result<f64, PoolError> {
+    if !amount_in.is_finite() || amount_in <= 0.0 {
+        return Err(PoolError::InvalidParameter { name: "amount_in", value: amount_in });
+    }
+    trader.ensure(token_in, amount_in)?;
+    let mut state = pool.state.write().unwrap();
+    let amount_out = pool.amount_out(&state, token_in, token_out, amount_in)?;
+    if amount_out < min_amount_out {
+        return Err(PoolError::SlippageExceeded { min_out: min_amount_out, amount_out });
+    }
+
+    let (i, o) = (pool.index_of(token_in)?, pool.index_of(token_out)?);
+    state.balances[i] += amount_in;
+    state.balances[o] -= amount_out;
+    state.version += 1;
+    pool.events.emit(EventKind::Swap, state.version);
+    drop(state);
+
+    trader.debit_with_fee(token_in, amount_in, pool.fee)?;
+    trader.credit(token_out, amount_out, EntryReason::Swap)?;
+    Ok(amount_out)
+}
+
+impl Amm for WeightedPool {
+    fn tokens(&self) -> Vec<TokenId> {
+        self.tokens.clone()
+    }
+
+    fn fee(&self) -> f64 {
+        self.fee
+    }
+
+    fn spot_price(&self, token_in: TokenId, token_out: TokenId) -> Result<f64, PoolError> {
+        let (balance_in, weight_in, balance_out, weight_out) = self.pair(&self.state.read().unwrap(), token_in, token_out)?;
+        Ok(balance_out / weight_out / (balance_in / weight_in))
+    }
+
+    fn quote_exact_in(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64
+    ) -> Result<f64, PoolError> {
+        self.amount_out(&self.state.read().unwrap(), token_in, token_out, amount_in)
+    }
+
+    fn quote_exact_out(
+        &self,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_out: f64
+    ) -> Result<f64, PoolError> {
+        let (balance_in, weight_in, balance_out, weight_out) = self.pair(&self.state.read().unwrap(), token_in, token_out)?;
+        if amount_out > balance_out * MAX_OUT_RATIO {
+            return Err(PoolError::InsufficientLiquidity);
+        }
+        Ok(calc_in_given_out(balance_in, weight_in, balance_out, weight_out, amount_out, self.fee))
+    }
+
+    fn apply_swap(
+        &self,
+        trader: &mut Account,
+        token_in: TokenId,
+        token_out: TokenId,
+        amount_in: f64,
+        min_amount_out: f64
+    ) -> Result<f64, PoolError> {
+        weighted_swap(trader, self, token_in, token_out, amount_in, min_amount_out)
+    }
+
+    fn version(&self) -> u64 {
+        self.state.read().unwrap().version
+    }
+
+    fn snapshot(&self) -> Box<dyn Amm + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::find_two_pool_arb;
+    use crate::ledger::{check_conservation, totals};
+    use crate::v2::Pool;
+
+    const BAL: TokenId = TokenId(0);
+    const WETH: TokenId = TokenId(1);
+    const DAI: TokenId = TokenId(2);
+
+    // 80/20 BAL/WETH pool holding 80000 BAL at 0.005 WETH each
+    fn set_up_pool() -> (Account, WeightedPool) {
+        let lp = Account::new(1, &[(BAL, 1000000.0), (WETH, 1000.0)]);
+        let pool = WeightedPool::new(&[BAL, WETH], &[80.0, 20.0], 0.01).unwrap();
+        pool.join(&lp, &[80000.0, 100.0], 0.0).unwrap();
+        (lp, pool)
+    }
+
+    #[test]
+    fn swap_formulas() {
+        let (_, pool) = set_up_pool();
+
+        assert_eq!(pool.spot_price(BAL, WETH).unwrap(), 100.0 / 0.2 / (80000.0 / 0.8));
+        // (80000 / 80990)^4 of the WETH balance stays in the pool
+        let out = pool.quote_exact_in(BAL, WETH, 1000.0).unwrap();
+        assert!((out - 100.0 * (1.0 - (80000.0f64 / 80990.0).powi(4))).abs() < 1e-12);
+        let back = pool.quote_exact_out(BAL, WETH, out).unwrap();
+        assert!((back - 1000.0).abs() < 1e-8);
+
+        assert_eq!(pool.quote_exact_in(BAL, WETH, 30000.0), Err(PoolError::InsufficientLiquidity));
+        assert_eq!(
+            WeightedPool::new(&[BAL, WETH], &[999.0, 1.0], 0.01).err(),
+            Some(PoolError::InvalidParameter { name: "weight", value: 0.001 })
+        );
+        for weight in [f64::NAN, f64::INFINITY, -1.0] {
+            assert!(matches!(
+                WeightedPool::new(&[BAL, WETH], &[weight, 1.0], 0.01),
+                Err(PoolError::InvalidParameter { name: "weight", .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn swap_keeps_invariant_and_tokens() {
+        let (_, pool) = set_up_pool();
+        let mut trader = Account::new(2, &[(WETH, 10.0)]);
+        let supply = totals(&[&trader, &pool]);
+        let invariant = pool.invariant();
+
+        let out = pool.apply_swap(&mut trader, WETH, BAL, 10.0, 0.0).unwrap();
+
+        assert_eq!(trader.balance(BAL), out);
+        assert!(pool.invariant() > invariant);
+        check_conservation(&supply, &[&trader, &pool]).unwrap();
+
+        for amount in [0.0, -1.0, f64::INFINITY] {
+            assert!(matches!(
+                pool.apply_swap(&mut trader, BAL, WETH, amount, 0.0),
+                Err(PoolError::InvalidParameter { name: "amount_in", .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn joins_and_exits() {
+        let (lp, pool) = set_up_pool();
+        let shares = pool.shares(&lp);
+        assert_eq!(shares, pool.total_shares());
+        let supply = totals(&[&lp, &pool]);
+
+        // a proportional join mints shares in proportion and pays no fee
+        let minted = pool.join(&lp, &[8000.0, 10.0], 0.0).unwrap();
+        assert!((minted - shares * 0.1).abs() < 1e-9);
+
+        // a single-sided join pays the fee on most of its deposit
+        let single = pool.join(&lp, &[0.0, 10.0], 0.0).unwrap();
+        assert!(single < minted * 0.2);
+
+        let amounts = pool.exit(&lp, minted).unwrap();
+        assert!(amounts[0] < 8000.0 && amounts[1] > 10.0);
+        assert_eq!(pool.exit(&lp, pool.total_shares() * 2.0), Err(PoolError::InsufficientLiquidity));
+        for shares in [0.0, f64::NAN] {
+            assert!(matches!(pool.exit(&lp, shares), Err(PoolError::InvalidParameter { name: "shares", .. })));
+        }
+        check_conservation(&supply, &[&lp, &pool]).unwrap();
+
+        // a negative amount would take tokens out of the pool without paying them to anyone
+        let balances = pool.balances();
+        assert_eq!(
+            pool.join(&lp, &[-10.0, 10.0], 0.0),
+            Err(PoolError::InvalidParameter { name: "amount_in", value: -10.0 })
+        );
+        assert!(pool.join(&lp, &[f64::NAN, 10.0], 0.0).is_err());
+        assert_eq!(pool.balances(), balances);
+    }
+
+    #[test]
+    fn arb_against_constant_product() {
+        let (_, weighted) = set_up_pool();
+        let cp = Pool::new(BAL, WETH, 20000.0, 120.0, 0.003);
+        assert_eq!(
+            weighted.quote_exact_in(BAL, DAI, 1.0),
+            Err(PoolError::UnknownToken { token: DAI })
+        );
+
+        // BAL is worth 0.006 WETH in the constant product pool and 0.005 in the weighted one
+        let best = find_two_pool_arb(&weighted, &cp, WETH, BAL, 100.0).unwrap().unwrap();
+        assert!(best.profit > 0.0);
+        assert!(best.amount_in < 30.0);
+    }
+}